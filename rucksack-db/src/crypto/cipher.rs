@@ -0,0 +1,63 @@
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+use bincode::{Decode, Encode};
+
+// The AEAD used for a database is chosen when it's created and recorded in
+// the file, so that it can be read back regardless of the current default.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Encode, Decode)]
+pub enum Cipher {
+    #[default]
+    Aes256Gcm,
+    XChaCha20Poly1305,
+}
+
+impl Cipher {
+    pub fn from_name(name: &str) -> Result<Cipher> {
+        match name.trim().to_lowercase().as_str() {
+            "" => Ok(Cipher::default()),
+            "aes-256-gcm" | "aes256gcm" => Ok(Cipher::Aes256Gcm),
+            "xchacha20-poly1305" | "xchacha20poly1305" => Ok(Cipher::XChaCha20Poly1305),
+            other => Err(anyhow!("unsupported cipher '{other}'")),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Cipher::Aes256Gcm => "aes-256-gcm",
+            Cipher::XChaCha20Poly1305 => "xchacha20-poly1305",
+        }
+    }
+
+    pub fn nonce_size(&self) -> usize {
+        match self {
+            Cipher::Aes256Gcm => 12,
+            Cipher::XChaCha20Poly1305 => 24,
+        }
+    }
+}
+
+impl fmt::Display for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cipher;
+
+    #[test]
+    fn names() {
+        assert_eq!(Cipher::from_name("").unwrap(), Cipher::Aes256Gcm);
+        assert_eq!(
+            Cipher::from_name("XChaCha20-Poly1305").unwrap(),
+            Cipher::XChaCha20Poly1305
+        );
+        assert_eq!(
+            Cipher::from_name(Cipher::XChaCha20Poly1305.name()).unwrap(),
+            Cipher::XChaCha20Poly1305
+        );
+        assert!(Cipher::from_name("rot13").is_err());
+    }
+}
@@ -0,0 +1,61 @@
+// Blind indexes: keyed, truncated hashes of metadata values. These let
+// records whose metadata is sealed be filtered without decrypting them; all
+// they reveal is whether two records share a value, never the value itself.
+use super::{mac, Key};
+
+const INDEX_KEY_INFO: &[u8] = b"rucksack-db index key";
+// Only part of each MAC is kept for index values; the occasional collision
+// just means a record gets decrypted (and then discarded) needlessly.
+const BLIND_SIZE: usize = 8;
+
+pub fn derive_key(key: &Key) -> Key {
+    super::subkey(key, INDEX_KEY_INFO)
+}
+
+// The field name is mixed in so that, e.g., a tag and a category with the
+// same value don't get the same index value.
+pub fn blind(index_key: &Key, field: &str, value: &str) -> u64 {
+    let tag = mac::sign(index_key, format!("{field}\0{value}").as_bytes());
+    let mut bytes = [0u8; BLIND_SIZE];
+    bytes.copy_from_slice(&tag[..BLIND_SIZE]);
+    u64::from_be_bytes(bytes)
+}
+
+// An opaque, stable identifier for a record, used in place of its key.
+pub fn id(index_key: &Key, key: &str) -> String {
+    mac::sign(index_key, format!("id\0{key}").as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+// Substring searches are supported by indexing every three-character window
+// of a value; a search term matches if all of its windows are present.
+pub fn trigrams(value: &str) -> Vec<String> {
+    let chars: Vec<char> = value.chars().collect();
+    let mut grams: Vec<String> = chars.windows(3).map(|w| w.iter().collect()).collect();
+    grams.sort();
+    grams.dedup();
+    grams
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+
+    #[test]
+    fn blind_values() {
+        let index_key = super::derive_key(&testing::data::store_key());
+        let a = super::blind(&index_key, "category", "work");
+        assert_eq!(a, super::blind(&index_key, "category", "work"));
+        assert_ne!(a, super::blind(&index_key, "tag", "work"));
+        assert_ne!(a, super::blind(&index_key, "category", "play"));
+        let other = super::derive_key(&testing::data::kdf_params().derive("not it").unwrap());
+        assert_ne!(a, super::blind(&other, "category", "work"));
+        let id = super::id(&index_key, "alice:https://site.com/:Password:default");
+        assert_eq!(id.len(), 64);
+        assert!(!id.contains("alice"));
+        assert_eq!(super::trigrams("abcab"), vec!["abc", "bca", "cab"]);
+        assert!(super::trigrams("ab").is_empty());
+    }
+}
@@ -0,0 +1,154 @@
+use anyhow::{anyhow, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use bincode::{Decode, Encode};
+use rand::RngCore;
+
+use super::{Key, KEY_SIZE};
+
+// These match the argon2 crate's defaults (which follow the OWASP
+// recommendations for Argon2id).
+pub const DEFAULT_M_COST: u32 = Params::DEFAULT_M_COST;
+pub const DEFAULT_T_COST: u32 = Params::DEFAULT_T_COST;
+pub const DEFAULT_P_COST: u32 = Params::DEFAULT_P_COST;
+// An upper bound on the memory cost (in KiB) we're willing to honour when
+// reading parameters back from a file: 4 GiB.
+pub const MAX_M_COST: u32 = 4 * 1024 * 1024;
+// Likewise for the number of passes (the defaults make 2), so that a crafted
+// header can't ask for a derivation that never finishes
+pub const MAX_T_COST: u32 = 64;
+pub const SALT_SIZE: usize = 16;
+
+// The Argon2id parameters, along with the random salt, are stored alongside
+// the encrypted data so that the key can be re-derived when the database is
+// next opened.
+#[derive(Clone, Debug, Eq, PartialEq, Encode, Decode)]
+pub struct KdfParams {
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+    pub salt: Vec<u8>,
+}
+
+impl KdfParams {
+    pub fn new() -> KdfParams {
+        KdfParams::with_costs(DEFAULT_M_COST, DEFAULT_T_COST, DEFAULT_P_COST)
+    }
+
+    pub fn with_costs(m_cost: u32, t_cost: u32, p_cost: u32) -> KdfParams {
+        KdfParams {
+            m_cost,
+            t_cost,
+            p_cost,
+            salt: random_salt(),
+        }
+    }
+
+    // Keep the costs, but use a fresh salt; used whenever a database is
+    // re-keyed.
+    pub fn renew(&self) -> KdfParams {
+        KdfParams::with_costs(self.m_cost, self.t_cost, self.p_cost)
+    }
+
+    pub fn derive(&self, pwd: &str) -> Result<Key> {
+        self.derive_with_key_file(pwd, None)
+    }
+
+    // A key file (see crate::crypto::keyfile) is given to Argon2 as its
+    // secret input, so the same password gives a different key with it.
+    pub fn derive_with_key_file(&self, pwd: &str, key_file: Option<&Key>) -> Result<Key> {
+        log::debug!(
+            "Deriving key (Argon2id, m={}, t={}, p={}) ...",
+            self.m_cost,
+            self.t_cost,
+            self.p_cost
+        );
+        if self.m_cost > MAX_M_COST {
+            return Err(anyhow!(
+                "KDF memory cost {} KiB exceeds the maximum of {} KiB",
+                self.m_cost,
+                MAX_M_COST
+            ));
+        }
+        if self.t_cost > MAX_T_COST {
+            return Err(anyhow!(
+                "KDF iterations {} exceed the maximum of {}",
+                self.t_cost,
+                MAX_T_COST
+            ));
+        }
+        let params = match Params::new(self.m_cost, self.t_cost, self.p_cost, Some(KEY_SIZE)) {
+            Ok(p) => p,
+            Err(e) => return Err(anyhow!("invalid KDF parameters ({e})")),
+        };
+        let argon2 = match key_file {
+            Some(secret) => {
+                match Argon2::new_with_secret(
+                    secret.expose(),
+                    Algorithm::Argon2id,
+                    Version::V0x13,
+                    params,
+                ) {
+                    Ok(a) => a,
+                    Err(e) => return Err(anyhow!("invalid key file secret ({e})")),
+                }
+            }
+            None => Argon2::new(Algorithm::Argon2id, Version::V0x13, params),
+        };
+        let mut bytes = vec![0u8; KEY_SIZE];
+        match argon2.hash_password_into(pwd.as_bytes(), &self.salt, &mut bytes) {
+            Ok(_) => Ok(Key::new(bytes)),
+            Err(e) => Err(anyhow!("could not derive key ({e})")),
+        }
+    }
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn random_salt() -> Vec<u8> {
+    let mut salt = vec![0u8; SALT_SIZE];
+    rand::thread_rng().fill_bytes(&mut salt);
+    salt
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+
+    #[test]
+    fn derive() {
+        let kdf = testing::data::kdf_params();
+        let key1 = kdf.derive("abc123").unwrap();
+        let key2 = kdf.derive("abc123").unwrap();
+        assert_eq!(key1.expose().len(), super::KEY_SIZE);
+        assert_eq!(key1.expose(), key2.expose());
+        // Long passwords aren't truncated ...
+        let long1 = kdf.derive(&"a".repeat(40)).unwrap();
+        let long2 = kdf.derive(&format!("{}b", "a".repeat(39))).unwrap();
+        assert_ne!(long1.expose(), long2.expose());
+        // ... and a new salt gives a new key
+        let renewed = kdf.renew();
+        assert_ne!(kdf.salt, renewed.salt);
+        assert_eq!(kdf.m_cost, renewed.m_cost);
+        assert_ne!(renewed.derive("abc123").unwrap().expose(), key1.expose());
+        // ... as does a key file
+        let key_file = crate::crypto::keyfile::from_bytes(b"key file").unwrap();
+        let with_file = kdf.derive_with_key_file("abc123", Some(&key_file)).unwrap();
+        assert_ne!(with_file.expose(), key1.expose());
+    }
+
+    #[test]
+    fn bad_params() {
+        let mut kdf = testing::data::kdf_params();
+        kdf.m_cost = super::MAX_M_COST + 1;
+        assert!(kdf.derive("abc123").is_err());
+        kdf.m_cost = 1;
+        assert!(kdf.derive("abc123").is_err());
+        let mut kdf = testing::data::kdf_params();
+        kdf.t_cost = super::MAX_T_COST + 1;
+        assert!(kdf.derive("abc123").is_err());
+    }
+}
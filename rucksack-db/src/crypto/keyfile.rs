@@ -0,0 +1,60 @@
+// Key files: a second factor for password key slots. The contents of a key
+// file are hashed and given to Argon2 as its secret input, so a slot created
+// with a key file can't be unlocked by its password alone. Any file will do,
+// but `generate` makes one of 256 random bits (as hex, so it survives being
+// copied around); since the whole file is hashed, editing it in any way
+// (even adding a newline) means it no longer works.
+use anyhow::{anyhow, Result};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use rucksack_lib::file;
+
+use super::{Key, KEY_SIZE};
+
+const DOMAIN: &[u8] = b"rucksack key file\0";
+
+pub fn generate() -> String {
+    let mut bytes = [0u8; KEY_SIZE];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    format!("{hex}\n")
+}
+
+pub fn from_bytes(bytes: &[u8]) -> Result<Key> {
+    if bytes.is_empty() {
+        return Err(anyhow!("key files can't be empty"));
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(DOMAIN);
+    hasher.update(bytes);
+    Ok(Key::new(hasher.finalize().to_vec()))
+}
+
+pub fn read(path: &str) -> Result<Key> {
+    log::debug!("Reading key file {path} ...");
+    match file::read(path.to_string()) {
+        Ok(bytes) => from_bytes(&bytes).map_err(|e| anyhow!("{path}: {e}")),
+        Err(e) => Err(anyhow!("could not read key file {path} ({e})")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn key_files() {
+        let contents = super::generate();
+        assert_eq!(contents.trim().len(), 2 * super::KEY_SIZE);
+        assert_ne!(contents, super::generate());
+        let key = super::from_bytes(contents.as_bytes()).unwrap();
+        assert_eq!(key.expose().len(), super::KEY_SIZE);
+        assert_eq!(
+            key.expose(),
+            super::from_bytes(contents.as_bytes()).unwrap().expose()
+        );
+        let edited = super::from_bytes(contents.trim().as_bytes()).unwrap();
+        assert_ne!(key.expose(), edited.expose());
+        assert!(super::from_bytes(b"").is_err());
+        assert!(super::read("/tmp/rucksack-no-such-key-file").is_err());
+    }
+}
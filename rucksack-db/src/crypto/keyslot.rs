@@ -0,0 +1,208 @@
+// Key slots, along the lines of LUKS: the database is encrypted with a random
+// data-encryption key (DEK), and each slot holds a copy of the DEK wrapped
+// with a key derived from one secret (a password, or a recovery key). Any one
+// slot's secret unlocks the database, and slots can be added or removed
+// without re-encrypting anything but the (tiny) slot itself. A password slot
+// may also need a key file (see crate::crypto::keyfile).
+use anyhow::{anyhow, Result};
+use bincode::{Decode, Encode};
+use bip39::Mnemonic;
+use rand::RngCore;
+
+use rucksack_lib::time;
+
+use super::{decrypt_with_aad, encrypt_with_aad, Cipher, KdfParams, Key, KEY_SIZE};
+
+// The name given to the slot created along with a database.
+pub const DEFAULT_SLOT: &str = "default";
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Encode, Decode)]
+pub enum SlotKind {
+    #[default]
+    Password,
+    // Unlocked by a BIP39 mnemonic generated by rucksack
+    Recovery,
+}
+
+impl SlotKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SlotKind::Password => "password",
+            SlotKind::Recovery => "recovery",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Encode, Decode)]
+pub struct KeySlot {
+    pub name: String,
+    pub kind: SlotKind,
+    pub kdf: KdfParams,
+    // Whether a key file is needed (along with the password) to unlock it
+    pub key_file: bool,
+    pub created: String,
+    wrapped: Vec<u8>,
+}
+
+impl KeySlot {
+    pub fn new(
+        name: &str,
+        kind: SlotKind,
+        kdf: KdfParams,
+        secret: &str,
+        key_file: Option<&Key>,
+        dek: &Key,
+        cipher: Cipher,
+    ) -> Result<KeySlot> {
+        if kind == SlotKind::Recovery && key_file.is_some() {
+            return Err(anyhow!("recovery key slots can't need a key file"));
+        }
+        let kek = kdf.derive_with_key_file(&normalise(kind, secret)?, key_file)?;
+        Ok(KeySlot {
+            name: name.to_string(),
+            kind,
+            key_file: key_file.is_some(),
+            wrapped: encrypt_with_aad(dek.expose().to_vec(), &kek, cipher, &aad(name, kind)),
+            kdf,
+            created: time::now(),
+        })
+    }
+
+    // Unwrap the DEK; this fails if the secret (or key file) isn't the one
+    // the slot was created with (or if the slot has been tampered with). A key
+    // file is ignored by slots that don't need one.
+    pub fn unlock(&self, secret: &str, key_file: Option<&Key>, cipher: Cipher) -> Result<Key> {
+        let key_file = match (self.key_file, key_file) {
+            (true, None) => return Err(anyhow!("key slot {:?} needs a key file", self.name)),
+            (true, Some(k)) => Some(k),
+            (false, _) => None,
+        };
+        let kek = self
+            .kdf
+            .derive_with_key_file(&normalise(self.kind, secret)?, key_file)?;
+        let dek = decrypt_with_aad(
+            self.wrapped.clone(),
+            &kek,
+            cipher,
+            &aad(&self.name, self.kind),
+        )?;
+        Ok(Key::new(dek))
+    }
+}
+
+// Try each slot in turn, returning the DEK along with the name of the slot
+// that was unlocked. Since every attempt derives a key, this takes longer
+// the more slots there are.
+pub fn unlock(
+    slots: &[KeySlot],
+    secret: &str,
+    key_file: Option<&Key>,
+    cipher: Cipher,
+) -> Result<(Key, String)> {
+    for slot in slots {
+        match slot.unlock(secret, key_file, cipher) {
+            Ok(dek) => {
+                log::debug!("Unlocked key slot {:?}", slot.name);
+                return Ok((dek, slot.name.clone()));
+            }
+            Err(e) => log::trace!("Couldn't unlock key slot {:?} ({e})", slot.name),
+        }
+    }
+    if key_file.is_none() && slots.iter().any(|s| s.key_file) {
+        return Err(anyhow!(
+            "no key slot could be unlocked (some need a key file, and none was given)"
+        ));
+    }
+    Err(anyhow!("no key slot could be unlocked"))
+}
+
+pub fn new_dek() -> Key {
+    let mut bytes = vec![0u8; KEY_SIZE];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    Key::new(bytes)
+}
+
+// A 24-word BIP39 mnemonic encoding 256 random bits.
+pub fn new_recovery_key() -> String {
+    let mut entropy = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut entropy);
+    Mnemonic::from_entropy(&entropy)
+        .expect("32 bytes is a valid BIP39 entropy length")
+        .to_string()
+}
+
+fn aad(name: &str, kind: SlotKind) -> Vec<u8> {
+    format!("rucksack key slot\0{}\0{}", name, kind.name()).into_bytes()
+}
+
+// Recovery keys are compared word-for-word, so that differences in case or
+// spacing don't matter when one is typed back in.
+fn normalise(kind: SlotKind, secret: &str) -> Result<String> {
+    match kind {
+        SlotKind::Password => Ok(secret.to_string()),
+        SlotKind::Recovery => match Mnemonic::parse_normalized(&secret.to_lowercase()) {
+            Ok(m) => Ok(m.to_string()),
+            Err(e) => Err(anyhow!("not a recovery key ({e})")),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeySlot, SlotKind};
+    use crate::crypto::Cipher;
+    use crate::testing;
+
+    #[test]
+    fn slots() {
+        let cipher = Cipher::default();
+        let dek = super::new_dek();
+        let recovery = super::new_recovery_key();
+        assert_eq!(recovery.split(' ').count(), 24);
+        let slots = vec![
+            KeySlot::new(
+                super::DEFAULT_SLOT,
+                SlotKind::Password,
+                testing::data::kdf_params(),
+                "abc123",
+                None,
+                &dek,
+                cipher,
+            )
+            .unwrap(),
+            KeySlot::new(
+                "offline",
+                SlotKind::Recovery,
+                testing::data::kdf_params(),
+                &recovery,
+                None,
+                &dek,
+                cipher,
+            )
+            .unwrap(),
+        ];
+        // Recovery keys can't be created from arbitrary strings
+        assert!(KeySlot::new(
+            "bad",
+            SlotKind::Recovery,
+            testing::data::kdf_params(),
+            "abc123",
+            None,
+            &dek,
+            cipher
+        )
+        .is_err());
+        let (key, name) = super::unlock(&slots, "abc123", None, cipher).unwrap();
+        assert_eq!(key.expose(), dek.expose());
+        assert_eq!(name, super::DEFAULT_SLOT);
+        let messy = format!("  {}", recovery.to_uppercase().replace(' ', "  "));
+        let (key, name) = super::unlock(&slots, &messy, None, cipher).unwrap();
+        assert_eq!(key.expose(), dek.expose());
+        assert_eq!(name, "offline");
+        assert!(super::unlock(&slots, "wrong", None, cipher).is_err());
+        // A slot can't be renamed (or have its kind changed)
+        let mut renamed = slots[0].clone();
+        renamed.name = "other".to_string();
+        assert!(renamed.unlock("abc123", None, cipher).is_err());
+    }
+}
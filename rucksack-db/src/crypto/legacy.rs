@@ -0,0 +1,80 @@
+// Databases written before the schema version 0.10.0 turned the master
+// password directly into an AES key by zero-padding (or truncating) it to the
+// key size. This is only kept around so that those files can be read and then
+// re-keyed; nothing new should ever be encrypted this way.
+use std::cmp::Ordering;
+
+use anyhow::{anyhow, Result};
+use secrecy::{ExposeSecret, SecretVec};
+
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Nonce,
+};
+
+const NONCE_SIZE: usize = 12;
+
+pub enum KeySize {
+    Bit128,
+    Bit256,
+}
+
+pub fn encrypt(data: Vec<u8>, pwd: &str, salt: &str) -> Vec<u8> {
+    let key_bytes = sized_key(pwd, KeySize::Bit256);
+    let key = aead::Key::<Aes256Gcm>::from_slice(key_bytes.as_ref());
+    let cipher = Aes256Gcm::new(key);
+    let nonce_bytes = sized_nonce(salt);
+    let nonce = Nonce::from_slice(nonce_bytes.as_ref());
+    cipher.encrypt(nonce, &data[..]).unwrap()
+}
+
+pub fn decrypt(encrypted: Vec<u8>, pwd: &str, salt: &str) -> Result<Vec<u8>> {
+    LegacyKey::new(pwd, salt).decrypt(&encrypted)
+}
+
+// The key (and nonce) that a password and salt make, so that legacy data can
+// still be read once the password itself is gone
+pub struct LegacyKey {
+    key: SecretVec<u8>,
+    nonce: SecretVec<u8>,
+}
+
+impl LegacyKey {
+    pub fn new(pwd: &str, salt: &str) -> LegacyKey {
+        LegacyKey {
+            key: SecretVec::new(sized_key(pwd, KeySize::Bit256)),
+            nonce: SecretVec::new(sized_nonce(salt)),
+        }
+    }
+
+    pub fn decrypt(&self, encrypted: &[u8]) -> Result<Vec<u8>> {
+        let key = aead::Key::<Aes256Gcm>::from_slice(self.key.expose_secret());
+        let cipher = Aes256Gcm::new(key);
+        let nonce = Nonce::from_slice(self.nonce.expose_secret());
+        match cipher.decrypt(nonce, encrypted) {
+            Ok(result) => Ok(result),
+            Err(e) => Err(anyhow!(e)),
+        }
+    }
+}
+
+fn sized_key(source: &str, key_size: KeySize) -> Vec<u8> {
+    let size: usize = match key_size {
+        KeySize::Bit128 => 16,
+        KeySize::Bit256 => 32,
+    };
+    let mut bytes = source.as_bytes().to_vec();
+    match bytes.len().cmp(&size) {
+        Ordering::Equal => bytes,
+        _ => {
+            bytes.resize(size, 0x00);
+            bytes
+        }
+    }
+}
+
+fn sized_nonce(source: &str) -> Vec<u8> {
+    let mut bytes = source.as_bytes().to_vec();
+    bytes.resize(NONCE_SIZE, 0x00);
+    bytes
+}
@@ -0,0 +1,55 @@
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::Key;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const MAC_SIZE: usize = 32;
+
+const MAC_KEY_INFO: &[u8] = b"rucksack-db mac key";
+
+// The MAC key is derived from the database key (rather than being the same
+// key) so that the two uses can never interfere with each other.
+pub fn derive_key(key: &Key) -> Key {
+    super::subkey(key, MAC_KEY_INFO)
+}
+
+pub fn sign(mac_key: &Key, data: &[u8]) -> Vec<u8> {
+    let mut mac = new_mac(mac_key);
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+pub fn verify(mac_key: &Key, data: &[u8], tag: &[u8]) -> Result<()> {
+    let mut mac = new_mac(mac_key);
+    mac.update(data);
+    match mac.verify_slice(tag) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(anyhow!(e)),
+    }
+}
+
+fn new_mac(mac_key: &Key) -> HmacSha256 {
+    <HmacSha256 as Mac>::new_from_slice(mac_key.expose()).expect("HMAC can take a key of any size")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+
+    #[test]
+    fn sign_and_verify() {
+        let key = testing::data::store_key();
+        let mac_key = super::derive_key(&key);
+        assert_ne!(mac_key.expose(), key.expose());
+        let tag = super::sign(&mac_key, b"header and records");
+        assert_eq!(tag.len(), super::MAC_SIZE);
+        assert!(super::verify(&mac_key, b"header and records", &tag).is_ok());
+        assert!(super::verify(&mac_key, b"header and r3cords", &tag).is_err());
+        assert!(super::verify(&mac_key, b"header and records", &tag[..31]).is_err());
+        let other = super::derive_key(&testing::data::kdf_params().derive("not it").unwrap());
+        assert!(super::verify(&other, b"header and records", &tag).is_err());
+    }
+}
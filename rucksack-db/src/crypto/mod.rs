@@ -0,0 +1,214 @@
+use std::fmt;
+use std::ptr::NonNull;
+
+use anyhow::{anyhow, Result};
+use zeroize::Zeroize;
+
+use aead::generic_array::typenum::Unsigned;
+use aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::Aes256Gcm;
+use chacha20poly1305::XChaCha20Poly1305;
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+pub mod cipher;
+pub mod index;
+pub mod kdf;
+pub mod keyfile;
+pub mod keyslot;
+pub mod legacy;
+pub mod mac;
+pub mod shamir;
+
+pub use cipher::Cipher;
+pub use kdf::KdfParams;
+
+pub const KEY_SIZE: usize = 32;
+
+// A symmetric key: either the random key that the database blob and the
+// secrets of each record are encrypted with (see crate::crypto::keyslot), or
+// one derived from a password or from that key.
+//
+// The bytes live in an allocation of their own (from memsec, with guard pages
+// on either side) that is locked into RAM, so they're never swapped out, and
+// that is zeroed when the key is dropped. Keys are only ever lent out by
+// reference; cloning one makes a new locked allocation.
+pub struct Key {
+    bytes: NonNull<[u8]>,
+}
+
+// The allocation is owned by the key, and never written to after creation.
+unsafe impl Send for Key {}
+unsafe impl Sync for Key {}
+
+impl Key {
+    // The given bytes are copied into locked memory and then wiped.
+    pub fn new(mut bytes: Vec<u8>) -> Key {
+        let key = Key::from_slice(&bytes);
+        bytes.zeroize();
+        key
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Key {
+        // Locking may fail (e.g., if RLIMIT_MEMLOCK is low); the memory is
+        // still zeroed on drop and kept out of core dumps.
+        let mut ptr = unsafe { memsec::malloc_sized(bytes.len()) }
+            .expect("could not allocate memory for a key");
+        unsafe { ptr.as_mut() }.copy_from_slice(bytes);
+        Key { bytes: ptr }
+    }
+
+    pub fn expose(&self) -> &[u8] {
+        unsafe { self.bytes.as_ref() }
+    }
+}
+
+impl Drop for Key {
+    fn drop(&mut self) {
+        // Zeroes and unlocks the memory before freeing it
+        unsafe { memsec::free(self.bytes) }
+    }
+}
+
+impl Clone for Key {
+    fn clone(&self) -> Self {
+        Key::from_slice(self.expose())
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Key(*****)")
+    }
+}
+
+// Derive a key for a specific purpose (MACs, blind indexes) from the master
+// key with HKDF-SHA256, so that keys are never used for more than one thing.
+pub fn subkey(key: &Key, info: &[u8]) -> Key {
+    let hk = Hkdf::<Sha256>::new(None, key.expose());
+    let mut bytes = vec![0u8; KEY_SIZE];
+    hk.expand(info, &mut bytes)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    Key::new(bytes)
+}
+
+// Every encryption uses a fresh random nonce, which is prefixed to the
+// ciphertext: reusing a nonce with the same key breaks AES-GCM entirely
+// (XChaCha20's nonces are big enough that random ones never collide).
+pub fn encrypt(data: Vec<u8>, key: &Key, cipher: Cipher) -> Vec<u8> {
+    encrypt_with_aad(data, key, cipher, &[])
+}
+
+pub fn decrypt(encrypted: Vec<u8>, key: &Key, cipher: Cipher) -> Result<Vec<u8>> {
+    decrypt_with_aad(encrypted, key, cipher, &[])
+}
+
+// The associated data isn't stored with the ciphertext, but the same bytes
+// have to be given to decrypt it; this is used to bind a ciphertext to where
+// it belongs (e.g., a particular field of a particular record).
+pub fn encrypt_with_aad(data: Vec<u8>, key: &Key, cipher: Cipher, aad: &[u8]) -> Vec<u8> {
+    match cipher {
+        Cipher::Aes256Gcm => seal::<Aes256Gcm>(data, key, aad),
+        Cipher::XChaCha20Poly1305 => seal::<XChaCha20Poly1305>(data, key, aad),
+    }
+}
+
+pub fn decrypt_with_aad(
+    encrypted: Vec<u8>,
+    key: &Key,
+    cipher: Cipher,
+    aad: &[u8],
+) -> Result<Vec<u8>> {
+    if encrypted.len() < cipher.nonce_size() {
+        return Err(anyhow!("encrypted data is too short to contain a nonce"));
+    }
+    match cipher {
+        Cipher::Aes256Gcm => open::<Aes256Gcm>(encrypted, key, aad),
+        Cipher::XChaCha20Poly1305 => open::<XChaCha20Poly1305>(encrypted, key, aad),
+    }
+}
+
+fn seal<C: Aead + AeadCore + KeyInit>(data: Vec<u8>, key: &Key, aad: &[u8]) -> Vec<u8> {
+    let cipher = C::new(aead::Key::<C>::from_slice(key.expose()));
+    let nonce = C::generate_nonce(&mut OsRng);
+    let mut encrypted = nonce.to_vec();
+    let payload = Payload { msg: &data, aad };
+    encrypted.extend(cipher.encrypt(&nonce, payload).unwrap());
+    encrypted
+}
+
+fn open<C: Aead + AeadCore + KeyInit>(
+    encrypted: Vec<u8>,
+    key: &Key,
+    aad: &[u8],
+) -> Result<Vec<u8>> {
+    let cipher = C::new(aead::Key::<C>::from_slice(key.expose()));
+    let (nonce_bytes, ciphertext) = encrypted.split_at(C::NonceSize::to_usize());
+    let nonce = aead::Nonce::<C>::from_slice(nonce_bytes);
+    let payload = Payload {
+        msg: ciphertext,
+        aad,
+    };
+    match cipher.decrypt(nonce, payload) {
+        Ok(result) => Ok(result),
+        Err(e) => Err(anyhow!(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cipher;
+    use crate::testing;
+
+    #[test]
+    fn round_trip() {
+        let key = testing::data::store_key();
+        let other = testing::data::kdf_params().derive("not it").unwrap();
+        for cipher in [Cipher::Aes256Gcm, Cipher::XChaCha20Poly1305] {
+            let encrypted = super::encrypt(b"s3kr1t".to_vec(), &key, cipher);
+            assert_ne!(encrypted, b"s3kr1t".to_vec());
+            let decrypted = super::decrypt(encrypted.clone(), &key, cipher).unwrap();
+            assert_eq!(decrypted, b"s3kr1t".to_vec());
+            assert!(super::decrypt(encrypted, &other, cipher).is_err());
+        }
+    }
+
+    #[test]
+    fn unique_nonces() {
+        let key = testing::data::store_key();
+        let cipher = Cipher::XChaCha20Poly1305;
+        let first = super::encrypt(b"s3kr1t".to_vec(), &key, cipher);
+        let second = super::encrypt(b"s3kr1t".to_vec(), &key, cipher);
+        let size = cipher.nonce_size();
+        assert_ne!(first[..size], second[..size]);
+        assert_ne!(first, second);
+        assert!(super::decrypt(first[..4].to_vec(), &key, cipher).is_err());
+        // A ciphertext can't be read with the wrong cipher
+        assert!(super::decrypt(first, &key, Cipher::Aes256Gcm).is_err());
+    }
+
+    #[test]
+    fn associated_data() {
+        let key = testing::data::store_key();
+        for cipher in [Cipher::Aes256Gcm, Cipher::XChaCha20Poly1305] {
+            let encrypted = super::encrypt_with_aad(b"s3kr1t".to_vec(), &key, cipher, b"here");
+            let decrypted = super::decrypt_with_aad(encrypted.clone(), &key, cipher, b"here");
+            assert_eq!(decrypted.unwrap(), b"s3kr1t".to_vec());
+            assert!(super::decrypt_with_aad(encrypted.clone(), &key, cipher, b"there").is_err());
+            assert!(super::decrypt(encrypted, &key, cipher).is_err());
+        }
+    }
+
+    #[test]
+    fn locked_keys() {
+        let bytes = b"0123456789abcdef0123456789abcdef";
+        let key = super::Key::from_slice(bytes);
+        let copy = key.clone();
+        assert_eq!(copy.expose(), key.expose());
+        assert_ne!(copy.expose().as_ptr(), key.expose().as_ptr());
+        drop(key);
+        assert_eq!(copy.expose(), bytes);
+        assert_eq!(format!("{copy:?}"), "Key(*****)");
+        assert!(super::Key::new(vec![]).expose().is_empty());
+    }
+}
@@ -0,0 +1,131 @@
+// Shamir secret sharing of the database's key, for emergency access: the key
+// is split into a number of shares, any `threshold` of which recover it (and
+// fewer of which reveal nothing about it). Shares are printable strings:
+//
+//   rks1-<threshold>-<share as hex>-<crc32 of what precedes it>
+//
+// The checksum only catches typos; whether the recovered key is the right one
+// is checked when the database is opened with it.
+use std::collections::HashSet;
+
+use anyhow::{anyhow, Result};
+use sharks::{Share, Sharks};
+
+use super::Key;
+
+const PREFIX: &str = "rks1";
+pub const MIN_THRESHOLD: u8 = 2;
+
+pub fn split(key: &Key, shares: u8, threshold: u8) -> Result<Vec<String>> {
+    if threshold < MIN_THRESHOLD {
+        return Err(anyhow!(
+            "the threshold must be at least {MIN_THRESHOLD} (otherwise every share is the key)"
+        ));
+    }
+    if shares < threshold {
+        return Err(anyhow!(
+            "can't make {shares} shares that need {threshold} to recover the key"
+        ));
+    }
+    Ok(Sharks(threshold)
+        .dealer(key.expose())
+        .take(shares as usize)
+        .map(|s| encode(threshold, &s))
+        .collect())
+}
+
+pub fn combine(shares: &[String]) -> Result<Key> {
+    let mut threshold = 0;
+    let mut seen = HashSet::new();
+    let mut decoded: Vec<Share> = Vec::new();
+    for (i, s) in shares.iter().enumerate() {
+        let (t, share) = decode(s).map_err(|e| anyhow!("share {} is invalid ({e})", i + 1))?;
+        if threshold != 0 && t != threshold {
+            return Err(anyhow!("the shares come from different splits"));
+        }
+        threshold = t;
+        // The same share given twice doesn't count twice
+        if seen.insert(share.x.0) {
+            decoded.push(share);
+        }
+    }
+    if decoded.len() < threshold as usize || decoded.is_empty() {
+        return Err(anyhow!(
+            "{} different shares are needed, but only {} were given",
+            threshold.max(MIN_THRESHOLD),
+            decoded.len()
+        ));
+    }
+    match Sharks(threshold).recover(&decoded) {
+        Ok(bytes) => Ok(Key::new(bytes)),
+        Err(e) => Err(anyhow!("could not recover the key ({e})")),
+    }
+}
+
+fn encode(threshold: u8, share: &Share) -> String {
+    let hex: String = Vec::from(share)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    let body = format!("{PREFIX}-{threshold}-{hex}");
+    format!("{body}-{:08x}", crc32fast::hash(body.as_bytes()))
+}
+
+fn decode(s: &str) -> Result<(u8, Share)> {
+    let s = s.trim().to_lowercase();
+    let (body, crc) = match s.rsplit_once('-') {
+        Some(parts) => parts,
+        None => return Err(anyhow!("not a rucksack key share")),
+    };
+    let parts: Vec<&str> = body.split('-').collect();
+    if parts.len() != 3 || parts[0] != PREFIX {
+        return Err(anyhow!("not a rucksack key share"));
+    }
+    if format!("{:08x}", crc32fast::hash(body.as_bytes())) != crc {
+        return Err(anyhow!("checksum mismatch; check for typos"));
+    }
+    let threshold: u8 = parts[1].parse()?;
+    if !parts[2].len().is_multiple_of(2) {
+        return Err(anyhow!("odd number of hex digits"));
+    }
+    let bytes = (0..parts[2].len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&parts[2][i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()?;
+    match Share::try_from(bytes.as_slice()) {
+        Ok(share) => Ok((threshold, share)),
+        Err(e) => Err(anyhow!(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::keyslot;
+
+    #[test]
+    fn split_and_combine() {
+        let key = keyslot::new_dek();
+        assert!(super::split(&key, 5, 1).is_err());
+        assert!(super::split(&key, 2, 3).is_err());
+        let shares = super::split(&key, 5, 3).unwrap();
+        assert_eq!(shares.len(), 5);
+        assert!(shares.iter().all(|s| s.starts_with("rks1-3-")));
+        // Any three will do ...
+        let some = vec![shares[4].clone(), shares[0].clone(), shares[2].clone()];
+        assert_eq!(super::combine(&some).unwrap().expose(), key.expose());
+        let upper: Vec<String> = shares[1..4].iter().map(|s| s.to_uppercase()).collect();
+        assert_eq!(super::combine(&upper).unwrap().expose(), key.expose());
+        // ... but two (even if one is repeated) won't
+        let repeated = vec![shares[0].clone(), shares[1].clone(), shares[0].clone()];
+        assert!(super::combine(&repeated).is_err());
+        // Typos are caught
+        let mut typo = shares[0].clone().into_bytes();
+        typo[10] = if typo[10] == b'a' { b'b' } else { b'a' };
+        let typo = String::from_utf8(typo).unwrap();
+        let e = super::combine(&[typo, shares[1].clone(), shares[2].clone()]).unwrap_err();
+        assert!(e.to_string().contains("share 1"));
+        // Shares from different splits can't be mixed
+        let other = super::split(&key, 2, 2).unwrap();
+        assert!(super::combine(&[other[0].clone(), shares[1].clone(), shares[2].clone()]).is_err());
+    }
+}
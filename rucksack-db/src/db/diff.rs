@@ -0,0 +1,230 @@
+// What changed between two sets of records (a backup and the database as it
+// is now, say): the records added, those removed, and, for those in both,
+// which of their fields changed. Records are matched by key (see
+// crate::records::DecryptedRecord::key), so a record whose key changed shows
+// up as one removed and another added. Changes to a record's password history
+// aren't listed, only those to its current secrets and metadata.
+//
+use std::collections::BTreeMap;
+
+use crate::records::{DecryptedRecord, Metadata, Secrets};
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub old: String,
+    pub new: String,
+    // Whether the values are secret, and so shouldn't be shown unless asked for
+    pub secret: bool,
+}
+
+// Keys are sorted, as are the records in `modified` (by key)
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Diff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<(String, Vec<FieldChange>)>,
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+// How `new` differs from `old`
+pub fn diff(old: &[DecryptedRecord], new: &[DecryptedRecord]) -> Diff {
+    let old: BTreeMap<String, &DecryptedRecord> = old.iter().map(|r| (r.key(), r)).collect();
+    let new: BTreeMap<String, &DecryptedRecord> = new.iter().map(|r| (r.key(), r)).collect();
+    let mut d = Diff::default();
+    for (key, record) in &new {
+        match old.get(key) {
+            None => d.added.push(key.clone()),
+            Some(was) => {
+                let mut changes = secrets(&was.secrets, &record.secrets);
+                changes.extend(metadata(&was.metadata, &record.metadata));
+                if !changes.is_empty() {
+                    d.modified.push((key.clone(), changes));
+                }
+            }
+        }
+    }
+    d.removed = old
+        .keys()
+        .filter(|key| !new.contains_key(*key))
+        .cloned()
+        .collect();
+    d
+}
+
+fn secrets(old: &Secrets, new: &Secrets) -> Vec<FieldChange> {
+    let bytes = |b: &Vec<u8>| format!("{} bytes", b.len());
+    let mut changes = Vec::new();
+    let mut compare = |field, old: String, new: String, secret| {
+        if old != new {
+            changes.push(FieldChange {
+                field,
+                old,
+                new,
+                secret,
+            });
+        }
+    };
+    compare(
+        "account_id",
+        old.account_id.clone(),
+        new.account_id.clone(),
+        false,
+    );
+    compare("user", old.user.clone(), new.user.clone(), false);
+    compare("password", old.password.clone(), new.password.clone(), true);
+    compare(
+        "public_key",
+        bytes(&old.public_key),
+        bytes(&new.public_key),
+        false,
+    );
+    compare(
+        "private_key",
+        bytes(&old.private_key),
+        bytes(&new.private_key),
+        true,
+    );
+    compare(
+        "public_cert",
+        bytes(&old.public_cert),
+        bytes(&new.public_cert),
+        false,
+    );
+    compare(
+        "private_cert",
+        bytes(&old.private_cert),
+        bytes(&new.private_cert),
+        true,
+    );
+    compare(
+        "root_cert",
+        bytes(&old.root_cert),
+        bytes(&new.root_cert),
+        false,
+    );
+    compare("key", old.key.clone(), new.key.clone(), false);
+    compare("secret", old.secret.clone(), new.secret.clone(), true);
+    changes
+}
+
+fn metadata(old: &Metadata, new: &Metadata) -> Vec<FieldChange> {
+    let tags = |md: &Metadata| {
+        md.tags
+            .iter()
+            .map(|t| t.display_or_value())
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+    let mut changes = Vec::new();
+    let mut compare = |field, old: String, new: String| {
+        if old != new {
+            changes.push(FieldChange {
+                field,
+                old,
+                new,
+                secret: false,
+            });
+        }
+    };
+    compare("kind", old.kind.name(), new.kind.name());
+    compare("category", old.category.clone(), new.category.clone());
+    compare("name", old.name.clone(), new.name.clone());
+    compare("url", old.url.clone(), new.url.clone());
+    compare("created", old.created.clone(), new.created.clone());
+    compare("imported", old.imported.clone(), new.imported.clone());
+    compare("updated", old.updated.clone(), new.updated.clone());
+    compare(
+        "password_changed",
+        old.password_changed.clone(),
+        new.password_changed.clone(),
+    );
+    compare("last_used", old.last_used.clone(), new.last_used.clone());
+    compare("synced", old.synced.clone(), new.synced.clone());
+    compare(
+        "access_count",
+        old.access_count.to_string(),
+        new.access_count.to_string(),
+    );
+    compare("status", old.status().to_string(), new.status().to_string());
+    compare("tags", tags(old), tags(new));
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use rucksack_lib::{file, time};
+
+    use crate::db::DB;
+    use crate::testing;
+
+    #[test]
+    fn diff() {
+        let same = testing::data::plaintext_record_v0100();
+        let mut changed = same.clone();
+        changed.metadata.url = "https://changed.site".to_string();
+        let mut gone = changed.clone();
+        gone.metadata.url = "https://gone.site".to_string();
+        let old = vec![same.clone(), changed.clone(), gone.clone()];
+        let mut added = same.clone();
+        added.metadata.url = "https://added.site".to_string();
+        changed.secrets.password = "n3w s3kr1t".to_string();
+        changed.metadata.access_count += 1;
+        let new = vec![same, changed.clone(), added.clone()];
+
+        let d = super::diff(&old, &new);
+        assert_eq!(d.added, vec![added.key()]);
+        assert_eq!(d.removed, vec![gone.key()]);
+        assert_eq!(d.modified.len(), 1);
+        let (key, changes) = &d.modified[0];
+        assert_eq!(*key, changed.key());
+        let fields: Vec<(&str, bool)> = changes.iter().map(|c| (c.field, c.secret)).collect();
+        assert_eq!(fields, vec![("password", true), ("access_count", false)]);
+        assert_eq!(changes[0].new, "n3w s3kr1t");
+        assert!(super::diff(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn legacy_backup() {
+        let pwd = testing::data::store_pwd();
+        let salt = time::now();
+        let mut db_handler = testing::db::new();
+        assert!(db_handler.setup().is_ok());
+        let db_file = db_handler.file_name().unwrap();
+        let backups = db_handler.backups_path().unwrap().display().to_string();
+        let bytes = testing::data::legacy_db_v090(&pwd, &salt);
+        assert!(file::write(bytes, db_file.clone()).is_ok());
+        let open = || {
+            let mut tmp_db = DB::new(
+                db_file.clone(),
+                backups.clone(),
+                Some(pwd.clone()),
+                Some(salt.clone()),
+            );
+            tmp_db.set_kdf(testing::data::kdf_params());
+            assert!(tmp_db.open().is_ok());
+            tmp_db
+        };
+        // Upgrading the database backs up the legacy file
+        assert!(open().close().is_ok());
+
+        let tmp_db = open();
+        let name = tmp_db.latest_backup().unwrap().name;
+        let old = tmp_db.backup_records(&name).unwrap();
+        let new = tmp_db.collect_decrypted().unwrap();
+        assert_eq!(old.len(), 1);
+        assert!(super::diff(&old, &new).is_empty());
+        let mut changed = new[0].clone();
+        changed.secrets.password = "n3w s3kr1t".to_string();
+        tmp_db.update(changed.key(), changed);
+        let d = super::diff(&old, &tmp_db.collect_decrypted().unwrap());
+        assert_eq!(d.modified.len(), 1);
+        assert_eq!(d.modified[0].1[0].old, "6 s3kr1t");
+        assert!(db_handler.teardown().is_ok());
+    }
+}
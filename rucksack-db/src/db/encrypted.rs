@@ -4,78 +4,216 @@ use secrecy::{ExposeSecret, Secret, SecretString};
 use rucksack_lib::file;
 
 use crate::crypto;
+use crate::crypto::legacy::LegacyKey;
+use crate::crypto::{legacy, mac, Key};
+use crate::error::IntegrityError;
+
+use super::header::Header;
 
 pub struct EncryptedDB {
     bytes: Vec<u8>,
     decrypted: Secret<Vec<u8>>,
+    header: Header,
+    key: Option<Key>,
+    key_file: Option<Key>,
+    legacy: bool,
     path: String,
     pwd: SecretString,
     salt: SecretString,
+    slot: String,
 }
 
 impl EncryptedDB {
     pub fn from_decrypted(
         decrypted: Vec<u8>,
         path: String,
-        pwd: String,
-        salt: String,
+        header: Header,
+        key: Key,
     ) -> Result<EncryptedDB> {
-        EncryptedDB::new(None, Some(decrypted), path, pwd, salt)
+        let mut edb = EncryptedDB::new(path);
+        log::debug!("Got decrypted bytes; encrypting ...");
+        edb.header = header;
+        edb.key = Some(key);
+        edb.decrypted = Secret::new(decrypted);
+        edb.encrypt()?;
+        Ok(edb)
     }
 
     pub fn from_encrypted(
         encrypted: Vec<u8>,
         path: String,
-        pwd: String,
-        salt: String,
+        pwd: &SecretString,
+        salt: &SecretString,
+    ) -> Result<EncryptedDB> {
+        let mut edb = EncryptedDB::new(path);
+        edb.pwd = pwd.clone();
+        edb.salt = salt.clone();
+        log::debug!("Got encrypted bytes; decrypting ...");
+        edb.bytes = encrypted;
+        edb.decrypt()?;
+        Ok(edb)
+    }
+
+    pub fn from_file(
+        path: String,
+        pwd: &SecretString,
+        salt: &SecretString,
+        key_file: Option<Key>,
     ) -> Result<EncryptedDB> {
-        EncryptedDB::new(Some(encrypted), None, path, pwd, salt)
+        let mut edb = EncryptedDB::new(path);
+        edb.pwd = pwd.clone();
+        edb.salt = salt.clone();
+        log::debug!("No bytes provided; reading from file ...");
+        edb.key_file = key_file;
+        edb.read()?;
+        edb.decrypt()?;
+        Ok(edb)
     }
 
-    pub fn from_file(path: String, pwd: String, salt: String) -> Result<EncryptedDB> {
-        EncryptedDB::new(None, None, path, pwd, salt)
+    // Read a database (or a backup of one) from before the header, with the
+    // key its password and salt made rather than with the password itself
+    pub fn from_legacy(encrypted: Vec<u8>, path: String, key: &LegacyKey) -> Result<EncryptedDB> {
+        if Header::has_magic(&encrypted) {
+            return Err(anyhow!("{path} is not a legacy database"));
+        }
+        let mut edb = EncryptedDB::new(path);
+        log::debug!("Got legacy bytes; decrypting ...");
+        edb.decrypted = Secret::new(key.decrypt(&encrypted)?);
+        edb.bytes = encrypted;
+        edb.legacy = true;
+        Ok(edb)
     }
 
-    pub fn new(
-        bytes: Option<Vec<u8>>,
-        decrypted: Option<Vec<u8>>,
+    // Open a database with its key (e.g., as recovered from Shamir shares)
+    // rather than with a password; no key slot is unlocked.
+    pub fn from_file_with_key(path: String, key: Key) -> Result<EncryptedDB> {
+        let mut edb = EncryptedDB::new(path);
+        log::debug!("Reading from file with a given key ...");
+        edb.key = Some(key);
+        edb.read()?;
+        edb.decrypt()?;
+        Ok(edb)
+    }
+
+    // For backends that keep the encrypted bytes themselves, rather than in
+    // a file (see crate::store::backend::memory)
+    pub fn from_bytes(
+        bytes: Vec<u8>,
         path: String,
-        pwd: String,
-        salt: String,
+        pwd: &SecretString,
+        salt: &SecretString,
+        key_file: Option<Key>,
     ) -> Result<EncryptedDB> {
-        let mut edb = EncryptedDB {
+        let mut edb = EncryptedDB::new(path);
+        edb.pwd = pwd.clone();
+        edb.salt = salt.clone();
+        edb.key_file = key_file;
+        edb.bytes = bytes;
+        edb.decrypt()?;
+        Ok(edb)
+    }
+
+    pub fn from_bytes_with_key(bytes: Vec<u8>, path: String, key: Key) -> Result<EncryptedDB> {
+        let mut edb = EncryptedDB::new(path);
+        edb.key = Some(key);
+        edb.bytes = bytes;
+        edb.decrypt()?;
+        Ok(edb)
+    }
+
+    // For backends that store records separately (see
+    // crate::store::manager::StoreManager::per_record) and so have already
+    // unlocked the key and put the records together: the decrypted bytes are
+    // the serialised VersionedDB, just as they would be for a file.
+    pub fn from_unlocked(
+        decrypted: Vec<u8>,
+        path: String,
+        header: Header,
+        key: Key,
+        slot: String,
+    ) -> EncryptedDB {
+        let mut edb = EncryptedDB::new(path);
+        edb.header = header;
+        edb.key = Some(key);
+        edb.slot = slot;
+        edb.decrypted = Secret::new(decrypted);
+        edb
+    }
+
+    fn new(path: String) -> EncryptedDB {
+        EncryptedDB {
             bytes: Vec::new(),
             decrypted: Secret::new(Vec::new()),
+            header: Header::default(),
+            key: None,
+            key_file: None,
+            legacy: false,
             path,
-            pwd: SecretString::new(pwd),
-            salt: SecretString::new(salt),
-        };
-        if bytes.is_none() && decrypted.is_none() {
-            log::debug!("No bytes provided; reading from file ...");
-            edb.read()?;
-            edb.decrypt()?;
-        } else if let Some(b) = bytes {
-            log::debug!("Got encrypted bytes; decrypting ...");
-            edb.bytes = b;
-            edb.decrypt()?;
-        } else if let Some(d) = decrypted {
-            log::debug!("Got decrypted bytes; encrypting ...");
-            edb.decrypted = Secret::new(d);
-            edb.encrypt();
+            pwd: SecretString::new(String::new()),
+            salt: SecretString::new(String::new()),
+            slot: String::new(),
         }
-        Ok(edb)
     }
 
     pub fn bytes(&self) -> Vec<u8> {
         self.bytes.clone()
     }
 
+    // Files written before the header was introduced are just the raw
+    // ciphertext, encrypted with the padded password; anything without the
+    // header's magic bytes is assumed to be one of those.
     pub fn decrypt(&mut self) -> Result<()> {
         log::debug!("Decrypting stored bytes ...");
-        log::trace!("{}, {}", self.pwd(), self.salt());
-        match crypto::decrypt(self.bytes.clone(), self.pwd(), self.salt()) {
+        if !Header::has_magic(&self.bytes) {
+            return match legacy::decrypt(self.bytes.clone(), self.pwd(), self.salt()) {
+                Ok(bytes) => {
+                    log::info!("Database was encrypted with a legacy key; it will be re-keyed when next saved");
+                    self.legacy = true;
+                    self.decrypted = Secret::new(bytes);
+                    Ok(())
+                }
+                Err(e) => {
+                    let msg = "Could not decrypt data; this is either not a rucksack database or the password is wrong";
+                    log::error!("{} ({:?})", msg, e);
+                    Err(anyhow!("{}", msg))
+                }
+            };
+        }
+        let (header, offset) = Header::decode(&self.bytes)?;
+        let unlocked = match self.key.take() {
+            Some(key) => Ok((key, String::new())),
+            None => header.unlock(self.pwd(), self.key_file.as_ref()),
+        };
+        let (key, slot) = match unlocked {
+            Ok(unlocked) => unlocked,
+            Err(e) => {
+                let msg = if self.key_file.is_none() && header.uses_key_file() {
+                    "Could not decrypt data; the password is wrong or a key file is needed"
+                } else {
+                    "Could not decrypt data; the password (or key file) is wrong"
+                };
+                log::error!("{} ({:?})", msg, e);
+                return Err(anyhow!("{}", msg));
+            }
+        };
+        let mac_key = mac::derive_key(&key);
+        if self.bytes.len() < offset + header.cipher.nonce_size() + mac::MAC_SIZE {
+            return Err(anyhow!(IntegrityError::new(
+                self.path(),
+                "file is truncated"
+            )));
+        }
+        let (signed, tag) = self.bytes.split_at(self.bytes.len() - mac::MAC_SIZE);
+        if mac::verify(&mac_key, signed, tag).is_err() {
+            log::error!("MAC mismatch for {}", self.path());
+            return Err(anyhow!(IntegrityError::new(self.path(), "MAC mismatch")));
+        }
+        match crypto::decrypt(signed[offset..].to_vec(), &key, header.cipher) {
             Ok(bytes) => {
-                log::trace!("Decrypted bytes: {:?}", bytes);
+                log::trace!("Decrypted {} bytes", bytes.len());
+                self.header = header;
+                self.key = Some(key);
+                self.slot = slot;
                 self.decrypted = Secret::new(bytes);
                 Ok(())
             }
@@ -91,18 +229,43 @@ impl EncryptedDB {
         self.decrypted.expose_secret().to_vec()
     }
 
-    pub fn encrypt(&mut self) {
+    pub fn encrypt(&mut self) -> Result<()> {
         log::trace!("Byte len before: {}", self.bytes.len());
-        self.bytes = crypto::encrypt(self.decrypted(), self.pwd(), self.salt());
+        let key = self.key()?.clone();
+        let mac_key = mac::derive_key(&key);
+        let mut bytes = self.header.encode()?;
+        bytes.extend(crypto::encrypt(self.decrypted(), &key, self.header.cipher));
+        let tag = mac::sign(&mac_key, &bytes);
+        bytes.extend(tag);
+        self.bytes = bytes;
+        self.legacy = false;
         log::trace!("Byte len after: {}", self.bytes.len());
+        Ok(())
+    }
+
+    pub fn header(&self) -> Header {
+        self.header.clone()
+    }
+
+    // A legacy database is one whose file predates the header; there are no
+    // key slots in it, so no key is unlocked when it's read.
+    pub fn is_legacy(&self) -> bool {
+        self.legacy
+    }
+
+    pub fn key(&self) -> Result<&Key> {
+        match &self.key {
+            Some(key) => Ok(key),
+            None => Err(anyhow!("no key has been derived for the encrypted DB")),
+        }
     }
 
     pub fn path(&self) -> String {
         self.path.clone()
     }
 
-    pub fn pwd(&self) -> String {
-        self.pwd.expose_secret().to_string()
+    fn pwd(&self) -> &str {
+        self.pwd.expose_secret()
     }
 
     pub fn read(&mut self) -> Result<()> {
@@ -112,12 +275,144 @@ impl EncryptedDB {
         Ok(())
     }
 
-    pub fn salt(&self) -> String {
-        self.salt.expose_secret().to_string()
+    fn salt(&self) -> &str {
+        self.salt.expose_secret()
     }
 
+    // The name of the key slot that the password unlocked (if a password was
+    // used)
+    pub fn slot(&self) -> String {
+        self.slot.clone()
+    }
+
+    // The file is replaced atomically, so that a failed write never leaves a
+    // truncated database behind.
     pub fn write(&self) -> Result<()> {
         log::debug!("Writing encrypted DB ...");
-        file::write(self.bytes(), self.path())
+        file::write_atomic(&self.bytes, self.path())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::{ExposeSecret, SecretString};
+
+    use crate::crypto::{keyslot, legacy, Cipher};
+    use crate::db::header::Header;
+    use crate::error::IntegrityError;
+    use crate::testing;
+
+    #[test]
+    fn header_round_trip() {
+        let pwd = SecretString::new(testing::data::store_pwd());
+        let salt = SecretString::new("alice".to_string());
+        let cipher = Cipher::XChaCha20Poly1305;
+        let key = keyslot::new_dek();
+        let slots = vec![testing::data::key_slot(&key, cipher)];
+        let header = Header::new(crate::db::version(), cipher, slots);
+        let edb = super::EncryptedDB::from_decrypted(
+            b"some data".to_vec(),
+            "/tmp/not-written".to_string(),
+            header.clone(),
+            key.clone(),
+        )
+        .unwrap();
+        // Writing the same data twice never produces the same bytes
+        let again = super::EncryptedDB::from_decrypted(
+            b"some data".to_vec(),
+            "/tmp/not-written".to_string(),
+            header.clone(),
+            key,
+        )
+        .unwrap();
+        assert_ne!(edb.bytes(), again.bytes());
+        // The header can be read without the password
+        let (read_header, _) = Header::decode(&edb.bytes()).unwrap();
+        assert_eq!(read_header.version, header.version);
+        assert_eq!(read_header.slots, header.slots);
+        let re_edb =
+            super::EncryptedDB::from_encrypted(edb.bytes(), edb.path(), &pwd, &salt).unwrap();
+        assert!(!re_edb.is_legacy());
+        assert_eq!(re_edb.slot(), keyslot::DEFAULT_SLOT);
+        assert_eq!(re_edb.header(), read_header);
+        assert_eq!(re_edb.decrypted(), b"some data".to_vec());
+        assert!(super::EncryptedDB::from_encrypted(
+            edb.bytes(),
+            edb.path(),
+            &SecretString::new("wrong".to_string()),
+            &salt
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn integrity() {
+        let pwd = SecretString::new(testing::data::store_pwd());
+        let salt = SecretString::new("alice".to_string());
+        let key = keyslot::new_dek();
+        let slots = vec![testing::data::key_slot(&key, Cipher::default())];
+        let header = Header::new(crate::db::version(), Cipher::default(), slots);
+        let bytes = super::EncryptedDB::from_decrypted(
+            b"some data".to_vec(),
+            "/tmp/not-written".to_string(),
+            header,
+            key,
+        )
+        .unwrap()
+        .bytes();
+        let open = |bytes: Vec<u8>, pwd: &str| {
+            super::EncryptedDB::from_encrypted(
+                bytes,
+                "/tmp/not-written".to_string(),
+                &SecretString::new(pwd.to_string()),
+                &salt,
+            )
+        };
+        let is_integrity_err = |r: anyhow::Result<super::EncryptedDB>| match r {
+            Ok(_) => false,
+            Err(e) => e.downcast_ref::<IntegrityError>().is_some(),
+        };
+        assert!(open(bytes.clone(), pwd.expose_secret()).is_ok());
+        // A wrong password isn't reported as tampering ...
+        assert!(!is_integrity_err(open(bytes.clone(), "wrong")));
+        // ... but changes to the header, the ciphertext, or the length are
+        let mut header_changed = bytes.clone();
+        let i = header_changed.iter().position(|b| *b == b'.').unwrap();
+        header_changed[i - 1] = b'9';
+        assert!(is_integrity_err(open(header_changed, pwd.expose_secret())));
+        let mut data_changed = bytes.clone();
+        let i = data_changed.len() - super::mac::MAC_SIZE - 1;
+        data_changed[i] ^= 0x01;
+        assert!(is_integrity_err(open(data_changed, pwd.expose_secret())));
+        assert!(is_integrity_err(open(
+            bytes[..bytes.len() - 1].to_vec(),
+            pwd.expose_secret()
+        )));
+    }
+
+    #[test]
+    fn legacy_bytes() {
+        let pwd = SecretString::new(testing::data::store_pwd());
+        let salt = SecretString::new("alice".to_string());
+        let bytes = legacy::encrypt(
+            b"some data".to_vec(),
+            pwd.expose_secret(),
+            salt.expose_secret(),
+        );
+        let edb =
+            super::EncryptedDB::from_encrypted(bytes, "/tmp/not-written".to_string(), &pwd, &salt)
+                .unwrap();
+        assert!(edb.is_legacy());
+        assert!(edb.key().is_err());
+        assert_eq!(edb.decrypted(), b"some data".to_vec());
+        // Anything else is rejected
+        let foreign = b"not a database at all".to_vec();
+        assert!(super::EncryptedDB::from_encrypted(
+            foreign,
+            "/tmp/not-written".to_string(),
+            &pwd,
+            &salt
+        )
+        .is_err());
     }
 }
@@ -0,0 +1,141 @@
+// Every database file starts with a small plaintext header, so that a file
+// can be identified (and its key slots found) without the password. The
+// layout on disk is:
+//
+//   MAGIC | header format (1 byte) | bincoded Header | ciphertext | MAC
+//
+// The MAC (see crate::crypto::mac) covers everything before it, so the
+// header can't be changed without that being detected when the file is
+// opened. The key slots (see crate::crypto::keyslot) each hold a wrapped copy
+// of the key the rest of the file is encrypted with.
+//
+use anyhow::{anyhow, Result};
+use bincode::{Decode, Encode};
+
+use rucksack_lib::{file, util};
+
+use crate::crypto::keyslot::{self, KeySlot};
+use crate::crypto::{Cipher, Key};
+
+pub const MAGIC: &[u8; 8] = b"RUCKSACK";
+// The version of the header layout itself (not the schema version).
+pub const FORMAT: u8 = 3;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Encode, Decode)]
+pub struct Header {
+    pub version: String,
+    pub cipher: Cipher,
+    // Whether records have their metadata sealed (see records::v0100)
+    pub sealed_metadata: bool,
+    pub slots: Vec<KeySlot>,
+}
+
+impl Header {
+    pub fn new(version: versions::SemVer, cipher: Cipher, slots: Vec<KeySlot>) -> Header {
+        Header {
+            version: version.to_string(),
+            cipher,
+            sealed_metadata: false,
+            slots,
+        }
+    }
+
+    // Returns the header along with the offset at which the ciphertext starts.
+    pub fn decode(bytes: &[u8]) -> Result<(Header, usize)> {
+        if !Header::has_magic(bytes) {
+            return Err(anyhow!("not a rucksack database file (missing header)"));
+        }
+        let start = MAGIC.len() + 1;
+        if bytes[MAGIC.len()] != FORMAT {
+            return Err(anyhow!(
+                "unsupported rucksack file header format ({})",
+                bytes[MAGIC.len()]
+            ));
+        }
+        match bincode::decode_from_slice::<Header, _>(&bytes[start..], util::bincode_cfg()) {
+            Ok((header, len)) => match versions::SemVer::new(header.version.as_str()) {
+                Some(_) => Ok((header, start + len)),
+                None => Err(anyhow!("corrupt rucksack file header (bad version)")),
+            },
+            Err(e) => Err(anyhow!("corrupt rucksack file header ({e})")),
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(FORMAT);
+        bytes.extend(bincode::encode_to_vec(self, util::bincode_cfg())?);
+        Ok(bytes)
+    }
+
+    pub fn has_magic(bytes: &[u8]) -> bool {
+        bytes.len() > MAGIC.len() && bytes.starts_with(MAGIC)
+    }
+
+    // Identify a database file without decrypting it.
+    pub fn read(path: String) -> Result<Header> {
+        let bytes = file::read(path.clone())?;
+        if !Header::has_magic(&bytes) {
+            return Err(anyhow!(
+                "{path} has no rucksack header; it's either not a rucksack database or it was written before v0.10.0 (opening it with the password will upgrade it)"
+            ));
+        }
+        let (header, _) = Header::decode(&bytes)?;
+        Ok(header)
+    }
+
+    // Get the database's key (and the name of the slot that held it).
+    pub fn unlock(&self, secret: &str, key_file: Option<&Key>) -> Result<(Key, String)> {
+        keyslot::unlock(&self.slots, secret, key_file, self.cipher)
+    }
+
+    // Whether any key slot needs a key file to unlock it
+    pub fn uses_key_file(&self) -> bool {
+        self.slots.iter().any(|s| s.key_file)
+    }
+
+    pub fn version(&self) -> versions::SemVer {
+        versions::SemVer::new(self.version.as_str()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Header;
+    use crate::crypto::{keyslot, Cipher};
+    use crate::testing;
+
+    #[test]
+    fn round_trip() {
+        let cipher = Cipher::XChaCha20Poly1305;
+        let dek = keyslot::new_dek();
+        let header = Header::new(
+            crate::db::version(),
+            cipher,
+            vec![testing::data::key_slot(&dek, cipher)],
+        );
+        let mut bytes = header.encode().unwrap();
+        let header_len = bytes.len();
+        bytes.extend(b"ciphertext");
+        assert!(bytes.starts_with(b"RUCKSACK"));
+        let (decoded, offset) = Header::decode(&bytes).unwrap();
+        assert_eq!(decoded, header);
+        assert_eq!(offset, header_len);
+        assert_eq!(&bytes[offset..], b"ciphertext");
+        let (key, slot) = decoded.unlock(&testing::data::store_pwd(), None).unwrap();
+        assert_eq!(key.expose(), dek.expose());
+        assert_eq!(slot, keyslot::DEFAULT_SLOT);
+    }
+
+    #[test]
+    fn bad_headers() {
+        let header = Header::new(crate::db::version(), Cipher::default(), vec![]);
+        let bytes = header.encode().unwrap();
+        assert!(Header::decode(b"some other file").is_err());
+        assert!(Header::decode(&bytes[..12]).is_err());
+        let mut future = bytes.clone();
+        future[super::MAGIC.len()] = super::FORMAT + 1;
+        let e = Header::decode(&future).unwrap_err();
+        assert!(e.to_string().contains("unsupported"));
+    }
+}
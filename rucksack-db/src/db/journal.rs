@@ -0,0 +1,205 @@
+// A database file is only rewritten in full now and then; in between, each
+// change to a record is appended to a journal kept alongside it (see
+// crate::store::manager::StoreManager::append_journal), and the journal is
+// replayed on top of the file when the database is opened. Writing the file
+// again takes in (and clears) the journal; this is "compaction".
+//
+// A journal starts with a header, which names the database file it applies
+// to by the file's MAC, followed by one entry per change:
+// * A 4-byte (little-endian) length
+// * The change (the record's ID, and the record, or nothing if it was
+//   deleted), bincoded and then encrypted with the database's key
+//
+// The file's MAC and the entry's position in the journal are the associated
+// data of each entry's encryption, so an entry can't be changed, moved to
+// another position, or moved to another journal without that being found
+// when it's replayed. As with the rows of per-record backends, entries
+// removed from the end can't be detected.
+//
+// A journal whose header names another file is stale: it was left by a
+// compaction that was interrupted after the file was written (so the file
+// already has its changes), or the file was since restored from a backup.
+// Either way, it's ignored. An entry cut short at the end of the journal is
+// what's left of a write that was interrupted, and is ignored, too. In both
+// cases, the file is rewritten when the database is next closed.
+//
+use anyhow::{anyhow, Result};
+
+use rucksack_lib::util;
+
+use crate::crypto::{self, mac, Cipher, Key};
+use crate::error::IntegrityError;
+use crate::records::EncryptedRecord;
+
+const MAGIC: &[u8] = b"RUXJ";
+const FORMAT: u8 = 1;
+const LEN_SIZE: usize = 4;
+// A journal of up to this many entries is never folded in on close; past
+// that, it is once it has more entries than a quarter of the records.
+const MAX_ENTRIES: u64 = 256;
+
+pub type Change = (String, Option<EncryptedRecord>);
+
+// Where the journal of an open database is up to
+#[derive(Clone, Debug, Default)]
+pub struct Journal {
+    // The MAC of the database file the journal applies to
+    pub base: Vec<u8>,
+    pub entries: u64,
+    // Whether the journal has been started (has its header) yet
+    pub started: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Replay {
+    pub changes: Vec<Change>,
+    pub stale: bool,
+    pub torn: bool,
+}
+
+// The MAC at the end of a database file, which identifies it
+pub fn base(file_bytes: &[u8]) -> Vec<u8> {
+    file_bytes[file_bytes.len().saturating_sub(mac::MAC_SIZE)..].to_vec()
+}
+
+// Whether a journal is long enough that the file should be rewritten
+pub fn too_long(entries: u64, records: usize) -> bool {
+    entries > MAX_ENTRIES.max(records as u64 / 4)
+}
+
+pub fn header(base: &[u8]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(FORMAT);
+    bytes.extend(base);
+    bytes
+}
+
+pub fn entry(
+    key: &Key,
+    cipher: Cipher,
+    journal: &Journal,
+    id: &str,
+    record: Option<&EncryptedRecord>,
+) -> Result<Vec<u8>> {
+    let change: Change = (id.to_string(), record.cloned());
+    let plain = bincode::encode_to_vec(change, util::bincode_cfg())?;
+    let aad = aad(&journal.base, journal.entries);
+    let encrypted = crypto::encrypt_with_aad(plain, key, cipher, &aad);
+    let mut bytes = (encrypted.len() as u32).to_le_bytes().to_vec();
+    bytes.extend(encrypted);
+    Ok(bytes)
+}
+
+// The changes in a journal (`bytes`) for the database file whose MAC is
+// `base`, in the order they were made
+pub fn replay(path: &str, key: &Key, cipher: Cipher, base: &[u8], bytes: &[u8]) -> Result<Replay> {
+    let mut replay = Replay::default();
+    if bytes.is_empty() {
+        return Ok(replay);
+    }
+    let start = MAGIC.len() + 1 + base.len();
+    if bytes.len() < start {
+        replay.torn = true;
+        return Ok(replay);
+    }
+    if !bytes.starts_with(MAGIC) || bytes[MAGIC.len()] != FORMAT {
+        return Err(anyhow!(
+            "{path} has a journal that isn't one rucksack can read"
+        ));
+    }
+    if bytes[MAGIC.len() + 1..start] != *base {
+        replay.stale = true;
+        return Ok(replay);
+    }
+    let mut rest = &bytes[start..];
+    while !rest.is_empty() {
+        if rest.len() < LEN_SIZE {
+            replay.torn = true;
+            break;
+        }
+        let (len, after) = rest.split_at(LEN_SIZE);
+        let len = u32::from_le_bytes(len.try_into()?) as usize;
+        if after.len() < len {
+            replay.torn = true;
+            break;
+        }
+        let (encrypted, after) = after.split_at(len);
+        let aad = aad(base, replay.changes.len() as u64);
+        let plain = match crypto::decrypt_with_aad(encrypted.to_vec(), key, cipher, &aad) {
+            Ok(plain) => plain,
+            Err(e) => {
+                log::error!(
+                    "Could not decrypt journal entry {} ({e})",
+                    replay.changes.len()
+                );
+                return Err(anyhow!(IntegrityError::new(
+                    path.to_string(),
+                    "journal entry doesn't match"
+                )));
+            }
+        };
+        let (change, _): (Change, usize) = bincode::decode_from_slice(&plain, util::bincode_cfg())?;
+        replay.changes.push(change);
+        rest = after;
+    }
+    Ok(replay)
+}
+
+fn aad(base: &[u8], position: u64) -> Vec<u8> {
+    let mut aad = base.to_vec();
+    aad.extend(position.to_le_bytes());
+    aad
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::{keyslot, Cipher};
+    use crate::error::IntegrityError;
+    use crate::testing;
+
+    use super::Journal;
+
+    #[test]
+    fn replay() {
+        let key = keyslot::new_dek();
+        let cipher = Cipher::default();
+        let base = vec![7; super::mac::MAC_SIZE];
+        let record = testing::data::plaintext_record_v0100().encrypt(&key, cipher);
+        let mut journal = Journal {
+            base: base.clone(),
+            ..Default::default()
+        };
+        let mut bytes = super::header(&base);
+        for change in [Some(&record), None] {
+            bytes.extend(super::entry(&key, cipher, &journal, "id", change).unwrap());
+            journal.entries += 1;
+        }
+        let replay = super::replay("db", &key, cipher, &base, &bytes).unwrap();
+        assert_eq!(replay.changes.len(), 2);
+        assert_eq!(replay.changes[0].1.as_ref(), Some(&record));
+        assert_eq!(replay.changes[1], ("id".to_string(), None));
+        assert!(!replay.stale && !replay.torn);
+
+        // An interrupted append loses only the entry being written
+        let torn = super::replay("db", &key, cipher, &base, &bytes[..bytes.len() - 3]).unwrap();
+        assert_eq!(torn.changes.len(), 1);
+        assert!(torn.torn);
+
+        // A journal for another file is ignored
+        let other = vec![8; super::mac::MAC_SIZE];
+        let stale = super::replay("db", &key, cipher, &other, &bytes).unwrap();
+        assert!(stale.stale && stale.changes.is_empty());
+
+        // Entries can't be swapped around (or changed)
+        let start = super::header(&base).len();
+        let first = super::LEN_SIZE
+            + u32::from_le_bytes(bytes[start..start + 4].try_into().unwrap()) as usize;
+        let mut swapped = super::header(&base);
+        swapped.extend(&bytes[start + first..]);
+        swapped.extend(&bytes[start..start + first]);
+        match super::replay("db", &key, cipher, &base, &swapped) {
+            Ok(_) => panic!("swapped journal entries weren't caught"),
+            Err(e) => assert!(e.downcast_ref::<IntegrityError>().is_some()),
+        }
+    }
+}
@@ -6,40 +6,108 @@
 // * The bytes are stored on a field of the VersionedDB struct
 // * The VersionDB struct is bincoded to bytes
 // * The bytes are stored on a field of the EncryptedDB struct
-// * The bytes are encrypted
-// * The encrypted bytes are saved to a file
+// * The bytes are encrypted with the database's (random) key
+// * A plaintext header (magic bytes, schema version, cipher, and key slots) is
+//   prepended to the encrypted bytes and the result is saved to a file
 //
 // Then, in the reverse, when a database is read from disk, this is how it's done:
 // * The file is read into memory as bytes and stored on a field of the EncryptedDB struct
-// * The encrypted bytes are decrypted
+// * The header is parsed, the database's key is unwrapped from whichever
+//   key slot the password unlocks, and the encrypted bytes that follow are
+//   decrypted
 // * The decrypted bytes are then bincode-decoded (deserialised) to a VersionedDB struct
 // * The bytes of the VersionDB are bincode-decoded to a vector of (string, record) tuples
 // * The sorted vector of tuples is converted to a hashmap (DashMap)
 // * The hashmap is stored as a field on the DB struct
 //
+// The key is kept on the DB struct and is also what's used to encrypt and
+// decrypt the secrets of individual records. Since it's wrapped separately for
+// each key slot (see crate::crypto::keyslot), passwords and recovery keys can
+// be added, changed, or removed without re-encrypting any records. Databases
+// that predate the KDF (schema versions before 0.10.0) are re-keyed when they
+// are opened and written in the new format the next time they are closed.
+//
+// Databases can optionally be created with sealed metadata, in which case the
+// hashmap is keyed by opaque record IDs (see crate::crypto::index); the public
+// methods here still take plaintext record keys and do the mapping.
+//
+// The whole file isn't written every time the database is closed, though:
+// each change to a record is appended to a journal (see crate::db::journal)
+// as it's made, and the journal is replayed on top of the file when it's
+// opened. The file is only rewritten (backing up the one it replaces) when the
+// database is new, when its header or every record has changed, when the
+// journal has grown long, or when the database is compacted or backed up.
+//
+// With a backend that stores records separately (see
+// crate::store::manager::StoreManager::per_record), there's no journal: each
+// change to a record is written as it's made, and the whole database is only
+// written when it's new, or when its header or every record has changed.
+//
 use std::fmt;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
 
 use anyhow::{anyhow, Error, Result};
 use dashmap::DashMap;
+use secrecy::{ExposeSecret, SecretString};
 
-use rucksack_lib::{file, util};
+use rucksack_lib::util;
 
+use crate::crypto::keyslot::{self, KeySlot, SlotKind};
+use crate::crypto::legacy::LegacyKey;
+use crate::crypto::{index, shamir, Cipher, KdfParams, Key};
 use crate::db::encrypted::EncryptedDB;
+use crate::db::header::Header;
+use crate::db::journal::{self, Journal};
+use crate::db::query::Query;
+use crate::db::verify::{self, Verification};
 use crate::db::versioned::VersionedDB;
 use crate::records;
-use crate::records::{DecryptedRecord, EncryptedRecord, Metadata};
+use crate::records::{shared, v0100, DecryptedRecord, EncryptedRecord, Metadata};
 use crate::store;
+use crate::store::backend::MemoryBackend;
+use crate::store::backup::BackupInfo;
+use crate::store::lock::{Lock, LockMode};
 use crate::store::manager::StoreManager;
+use crate::store::retention::{self, Policy};
 
 pub struct DB {
     pub file_name: String,
+    backed_up: AtomicBool,
     backup_dir: String,
+    cipher: Cipher,
     enabled: bool,
     hash_map: records::HashMap,
+    journal: Mutex<Journal>,
+    kdf: KdfParams,
+    key: Option<Key>,
+    index_key: Option<Key>,
+    key_file: Option<Key>,
+    // What the password and salt make in their place, for reading backups
+    // of the database from before it was upgraded (see `read_backup`)
+    legacy_key: Option<LegacyKey>,
+    lock: Mutex<Option<Lock>>,
+    lock_mode: LockMode,
+    lock_wait: Duration,
     manager: Box<dyn StoreManager>,
-    salt: Option<String>,
+    // Which backups to keep when they're pruned, after each new one
+    retention: Policy,
+    // The password (and salt, only used by legacy databases) are only kept
+    // until the database is opened
+    salt: Option<SecretString>,
+    sealed: bool,
+    slot: String,
+    slots: Vec<KeySlot>,
     store_hash: u32,
-    store_pwd: Option<String>,
+    store_pwd: Option<SecretString>,
+    // How many records the stored database (rather than the one open) has,
+    // for describing a backup of it: counted from the records whenever
+    // they're read from or written to the backend
+    stored_records: AtomicUsize,
+    // Set when a record couldn't be written as it changed (see `persist`)
+    unsaved: AtomicBool,
     version: versions::SemVer,
 }
 
@@ -61,17 +129,48 @@ impl DB {
     ) -> DB {
         DB {
             file_name,
+            backed_up: AtomicBool::new(false),
             backup_dir,
-            store_pwd,
-            salt,
+            store_pwd: store_pwd.map(SecretString::new),
+            salt: salt.map(SecretString::new),
             manager: store::manager::new(),
+            retention: Policy::default(),
+            cipher: Cipher::default(),
             enabled: true,
             hash_map: DashMap::new(),
+            journal: Mutex::new(Journal::default()),
+            kdf: KdfParams::new(),
+            key: None,
+            index_key: None,
+            key_file: None,
+            legacy_key: None,
+            lock: Mutex::new(None),
+            lock_mode: LockMode::default(),
+            lock_wait: Duration::ZERO,
+            sealed: false,
+            slot: String::new(),
+            slots: Vec::new(),
             store_hash: 0,
+            stored_records: AtomicUsize::new(0),
+            unsaved: AtomicBool::new(false),
             version: records::version(),
         }
     }
 
+    // A database that's kept in memory (see
+    // crate::store::backend::memory) rather than on disk, along with its
+    // backups; `file_name` and `backup_dir` are only names.
+    pub fn new_in_memory(
+        file_name: String,
+        backup_dir: String,
+        store_pwd: Option<String>,
+        salt: Option<String>,
+    ) -> DB {
+        let mut db = DB::new(file_name, backup_dir, store_pwd, salt);
+        db.set_manager(Box::new(MemoryBackend::new()));
+        db
+    }
+
     // Moved in v0.9.0
     pub fn init(
         file_name: String,
@@ -88,97 +187,339 @@ impl DB {
     // Moved in v0.9.0
     pub fn open(&mut self) -> Result<()> {
         log::debug!("Opening database ...");
-        let store_pwd = self.store_pwd.clone().unwrap();
-        let salt = self.salt.clone().unwrap();
-        let file_path = file::create_parents(self.file_name.clone())?;
-        if file_path.exists() {
+        self.take_lock()?;
+        let store_pwd = match self.store_pwd.take() {
+            Some(pwd) => pwd,
+            None => return Err(anyhow!("no password was given for the database")),
+        };
+        let salt = self
+            .salt
+            .take()
+            .unwrap_or_else(|| SecretString::new(String::new()));
+        self.legacy_key = Some(LegacyKey::new(
+            store_pwd.expose_secret(),
+            salt.expose_secret(),
+        ));
+        let (location, exists) = self.manager.locate(&self.file_name)?;
+        let mut base = Vec::new();
+        if exists {
             log::debug!("Creating encrypted DB ...");
-            let enc_db = self.manager.read(self.file_name.clone(), store_pwd, salt)?;
-            let vsn_db = match VersionedDB::deserialise(enc_db.decrypted()) {
-                Ok(db) => db,
-                Err(_) => {
-                    log::info!("Given database appears to be non-versioned; be sure to upgrade to the latest micro release of our old version before continuing ...");
-                    log::trace!("Bytes: {:?}", enc_db.decrypted());
-                    VersionedDB::from_bytes(enc_db.decrypted())
-                }
-            };
-            log::debug!("Getting database hash ...");
-            self.store_hash = vsn_db.hash();
-            self.version = vsn_db.version();
-            // Decode the versioned DB's bytes to a hashmap
-            self.hash_map = records::decode_hashmap(vsn_db.bytes(), self.version.clone())?;
+            let enc_db = self.manager.read(
+                self.file_name.clone(),
+                &store_pwd,
+                &salt,
+                self.key_file.clone(),
+            )?;
+            base = journal::base(&enc_db.bytes());
+            self.load(enc_db, &store_pwd, &salt)?;
+        } else {
+            log::debug!("Creating key for new database ...");
+            let key = self.new_key(store_pwd.expose_secret())?;
+            self.set_key(key);
         };
 
-        self.file_name = file_path.display().to_string();
+        self.file_name = location;
+        self.replay_journal(base)?;
         self.enabled = true;
         log::debug!("Set database path: {}", self.file_name);
         Ok(())
     }
 
+    // Open an existing database with its key rather than a password; this is
+    // how a database is opened with a key recovered from Shamir shares (see
+    // `recover`).
+    pub fn open_with_key(&mut self, key: Key) -> Result<()> {
+        log::debug!("Opening database with a given key ...");
+        self.take_lock()?;
+        let (location, exists) = self.manager.locate(&self.file_name)?;
+        if !exists {
+            return Err(anyhow!("no database found at {}", self.file_name));
+        }
+        let enc_db = match self.manager.read_with_key(self.file_name.clone(), key) {
+            Ok(enc_db) => enc_db,
+            Err(e) => {
+                log::error!("Could not open database with the given key ({e})");
+                return Err(anyhow!(
+                    "could not open {} with the given key; it's either the wrong key or the file has been changed",
+                    self.file_name
+                ));
+            }
+        };
+        let base = journal::base(&enc_db.bytes());
+        let none = SecretString::new(String::new());
+        self.load(enc_db, &none, &none)?;
+        self.file_name = location;
+        self.replay_journal(base)?;
+        self.enabled = true;
+        Ok(())
+    }
+
+    // The second half of opening an existing database: take the records (and
+    // key, cipher, etc.) from its decrypted file.
+    fn load(
+        &mut self,
+        enc_db: EncryptedDB,
+        store_pwd: &SecretString,
+        salt: &SecretString,
+    ) -> Result<()> {
+        let key = if enc_db.is_legacy() {
+            log::debug!("Creating a new key for legacy database ...");
+            self.new_key(store_pwd.expose_secret())?
+        } else {
+            let header = enc_db.header();
+            self.cipher = header.cipher;
+            self.sealed = header.sealed_metadata;
+            self.slots = header.slots;
+            self.slot = enc_db.slot();
+            // New slots get the same KDF costs as the one just unlocked
+            if let Some(slot) = self.key_slot(&self.slot) {
+                self.kdf = slot.kdf;
+            }
+            enc_db.key()?.clone()
+        };
+        let vsn_db = match VersionedDB::deserialise(enc_db.decrypted()) {
+            Ok(db) => db,
+            Err(e) if !enc_db.is_legacy() => return Err(e),
+            Err(_) => {
+                log::info!("Given database appears to be non-versioned; be sure to upgrade to the latest micro release of our old version before continuing ...");
+                VersionedDB::from_bytes(enc_db.decrypted())
+            }
+        };
+        log::debug!("Getting database hash ...");
+        self.store_hash = vsn_db.hash();
+        if enc_db.is_legacy() {
+            // Make sure the file gets re-written (and thus re-keyed) on
+            // close, even if no records change
+            self.store_hash = 0;
+        }
+        self.version = vsn_db.version();
+        if !enc_db.is_legacy() && enc_db.header().version() != self.version {
+            return Err(anyhow!(
+                "database header (version {}) doesn't match its contents (version {})",
+                enc_db.header().version,
+                self.version
+            ));
+        }
+        // Decode the versioned DB's bytes to a hashmap
+        self.hash_map = records::decode_hashmap(vsn_db.bytes(), self.version.clone())?;
+        *self.stored_records.get_mut() = self.hash_map.len();
+        self.set_key(key);
+        if shared::trim_version(self.version()) < shared::version(v0100::VERSION) {
+            let legacy_key = LegacyKey::new(store_pwd.expose_secret(), salt.expose_secret());
+            self.rekey_legacy_records(&legacy_key)?;
+        }
+        Ok(())
+    }
+
+    // Add a key slot that unlocks the database with the given secret (a
+    // password, or a recovery key from `keyslot::new_recovery_key`). Only the
+    // header changes, when the database is next closed.
+    pub fn add_key_slot(&mut self, name: &str, kind: SlotKind, secret: &str) -> Result<()> {
+        log::debug!("Adding {} key slot {name:?} ...", kind.name());
+        if name.is_empty() {
+            return Err(anyhow!("key slots must have a name"));
+        }
+        if self.key_slot(name).is_some() {
+            return Err(anyhow!("there is already a key slot named {name:?}"));
+        }
+        let slot = KeySlot::new(
+            name,
+            kind,
+            self.kdf.renew(),
+            secret,
+            self.slot_key_file(kind),
+            self.key(),
+            self.cipher(),
+        )?;
+        self.slots.push(slot);
+        self.store_hash = 0;
+        Ok(())
+    }
+
+    // The name of the storage backend (see crate::store::manager)
+    pub fn backend(&self) -> &'static str {
+        self.manager.name()
+    }
+
+    // Back up the database as it is now stored (not as it is in memory), with
+    // its journal folded in first, and then prune its backups.
+    pub fn backup(&self) -> Result<String> {
+        let backup_file = self.backup_unpruned()?;
+        self.prune_quietly();
+        Ok(backup_file)
+    }
+
+    // As `backup`, but leaving the backups that are already there alone (e.g.,
+    // so that one that's about to be restored isn't pruned first)
+    pub fn backup_unpruned(&self) -> Result<String> {
+        if !self.manager.per_record() && self.journal_entries() > 0 {
+            self.write_file(false)?;
+        }
+        self.copy_backup()
+    }
+
+    // Copy the database file (without its journal) to the backup dir
+    fn copy_backup(&self) -> Result<String> {
+        let info = self.manager.backup(
+            self.file_name(),
+            self.backup_dir(),
+            self.schema_version().to_string(),
+            Some(self.stored_records.load(Ordering::Relaxed)),
+        )?;
+        let backup_file = Path::new(&self.backup_dir).join(&info.name);
+        log::debug!("Backed up file to {}", backup_file.display());
+        Ok(backup_file.display().to_string())
+    }
+
+    // The database's backups, newest first
+    pub fn backups(&self) -> Result<Vec<BackupInfo>> {
+        self.manager.list_backups(&self.backup_dir)
+    }
+
+    // The records in a backup, decrypted, as `collect_decrypted` gives those
+    // of the database; the backup is only read. Backups from before schema
+    // version 0.10.0 are read (and their records decrypted) the way `load`
+    // reads such a database.
+    pub fn backup_records(&self, name: &str) -> Result<Vec<DecryptedRecord>> {
+        log::debug!("Reading records from backup {name} ...");
+        let enc_db = self.read_backup(name, self.key().clone())?;
+        let vsn_db = match VersionedDB::deserialise(enc_db.decrypted()) {
+            Ok(db) => db,
+            Err(_) if enc_db.is_legacy() => VersionedDB::from_bytes(enc_db.decrypted()),
+            Err(e) => return Err(e),
+        };
+        let hash_map = records::decode_hashmap(vsn_db.bytes(), vsn_db.version())?;
+        let legacy = shared::trim_version(vsn_db.version()) < shared::version(v0100::VERSION);
+        hash_map
+            .iter()
+            .map(|entry| match (legacy, &self.legacy_key) {
+                (false, _) => self.decrypt_record(entry.value()),
+                (true, Some(legacy_key)) => self.decrypt_record(
+                    &v0100::rekey_encrypted_record_from_v090(
+                        entry.value().clone(),
+                        legacy_key,
+                        self.key(),
+                        self.cipher(),
+                    )?,
+                ),
+                (true, None) => Err(anyhow!(
+                    "backup {name} predates schema version 0.10.0; its records need the database's password"
+                )),
+            })
+            .collect()
+    }
+
     pub fn backup_dir(&self) -> String {
         self.backup_dir.clone()
     }
 
+    pub fn cipher(&self) -> Cipher {
+        self.cipher
+    }
+
+    // Save any changes and release the database's lock, whether or not the
+    // changes could be saved.
     pub fn close(&self) -> Result<()> {
         log::debug!("Closing DB file ...");
-        let path = file::create_parents(self.file_name())?;
-        if path.exists() {
-            log::debug!("Database file exists; backing up ...");
-            let backup_file = self.manager.backup(
-                self.file_name(),
-                self.backup_dir(),
-                self.schema_version().to_string(),
-            )?;
-            log::debug!("Backed up file to {backup_file}");
+        let r = match self.manager.per_record() {
+            true => self.close_records(),
+            false => self.close_file(),
+        };
+        self.release_lock();
+        r
+    }
+
+    // Changes to records have already been written to the journal (see
+    // `persist`); only a new database, one whose header or every record has
+    // changed, or one whose journal has grown long, is written in full.
+    fn close_file(&self) -> Result<()> {
+        let unsaved = self.store_hash == 0 || self.unsaved.load(Ordering::Relaxed);
+        if !unsaved && (self.lock_mode == LockMode::Shared || !self.needs_compaction()) {
+            log::debug!("Changes are in the journal; not persisting ...");
+            return Ok(());
         }
+        self.write_file(true)?;
+        Ok(())
+    }
 
-        // Reverse the workflow of `open` ... encode the hashmap
-        let srl = match self.serialise() {
-            Ok(x) => Ok(x),
-            Err(e) => {
-                let msg = "Could not serialise self";
-                log::error!("{} {:?} ({:})", msg, self.file_name(), e);
-                Err(anyhow!("{} {:?} ({:})", msg, self.file_name(), e))
-            }
-        }?;
-        // Create versioned data
-        let vsn_db = VersionedDB::from_bytes(srl);
-        let encoded = match vsn_db.serialise() {
-            Ok(x) => Ok(x),
-            Err(e) => {
-                let msg = "Could not serialise version db";
-                log::error!("{} {:?} ({:})", msg, self.file_name(), e);
-                Err(anyhow!("{} {:?} ({:})", msg, self.file_name(), e))
-            }
-        }?;
-        // Get the hash for the versioned data
-        let store_hash = vsn_db.hash();
-        if store_hash == self.store_hash {
-            log::debug!("No change in store hash; not persisting ...");
+    // Fold the journal into the database file, as happens on close once it's
+    // grown long; the file that's replaced is backed up first.
+    pub fn compact(&mut self) -> Result<()> {
+        if self.manager.per_record() {
+            log::debug!("Records are stored separately; nothing to compact");
             return Ok(());
         }
-        // Encrypt the versioned data
-        let enc_db =
-            EncryptedDB::from_decrypted(encoded, self.file_name(), self.store_pwd(), self.salt())?;
+        log::debug!("Compacting {} journal entries ...", self.journal_entries());
+        self.store_hash = self.write_file(true)?;
+        self.unsaved.store(false, Ordering::Relaxed);
+        Ok(())
+    }
 
-        // Save the encrypted data
-        enc_db.write()
+    // Records have already been written as they changed (see `persist`); only
+    // a new database, or one whose header or every record has changed, is
+    // written in full.
+    fn close_records(&self) -> Result<()> {
+        if self.store_hash != 0 && !self.unsaved.load(Ordering::Relaxed) {
+            log::debug!("Records are up to date; not persisting ...");
+            return Ok(());
+        }
+        if self.read_only()? {
+            return Ok(());
+        }
+        self.backup_once()?;
+        self.manager
+            .write_records(&self.file_name, &self.header(), self.key(), &self.hash_map)?;
+        self.stored_records
+            .store(self.hash_map.len(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    // Back up the database before the first change made to it since it was
+    // opened (when records are written as they change, there's no single
+    // write on close to back up before).
+    fn backup_once(&self) -> Result<()> {
+        if self.backed_up.load(Ordering::Relaxed) || !self.exists()? {
+            return Ok(());
+        }
+        self.copy_backup()?;
+        self.backed_up.store(true, Ordering::Relaxed);
+        self.prune_quietly();
+        Ok(())
     }
 
     pub fn collect_decrypted(&self) -> Result<Vec<DecryptedRecord>, Error> {
         let mut decrypted: Vec<DecryptedRecord> = Vec::new();
         for i in self.iter() {
-            let record = i.value().decrypt(self.store_pwd(), self.salt())?;
+            let record = self.decrypt_record(i.value())?;
             decrypted.push(record);
         }
         Ok(decrypted)
     }
 
+    pub fn delete_backup(&self, name: &str) -> Result<()> {
+        log::debug!("Deleting backup {name} ...");
+        self.manager.delete_backup(&self.backup_dir, name)
+    }
+
+    // The plaintext header of the stored database; unlike `header`, this
+    // doesn't need the database to be opened.
+    pub fn describe(&self) -> Result<Header> {
+        self.manager.describe(&self.file_name)
+    }
+
+    pub fn decrypt_record(&self, record: &EncryptedRecord) -> Result<DecryptedRecord> {
+        record.decrypt(self.key(), self.cipher())
+    }
+
     // Added in v0.7.0
     pub fn delete(&self, key: String) -> Option<bool> {
         log::debug!("Deleting record with key {key} ...");
-        match self.hash_map.remove(&key) {
-            Some(_) => Some(true),
+        let id = self.map_key(&key);
+        match self.hash_map.remove(&id) {
+            Some(_) => {
+                self.persist(&id, None);
+                Some(true)
+            }
             None => Some(false),
         }
     }
@@ -188,20 +529,35 @@ impl DB {
         self.enabled
     }
 
-    pub fn get(&self, key: String) -> Option<DecryptedRecord> {
+    fn exists(&self) -> Result<bool> {
+        Ok(self.manager.locate(&self.file_name)?.1)
+    }
+
+    fn encrypt_record(&self, record: &DecryptedRecord) -> EncryptedRecord {
+        if self.sealed {
+            return record.seal(self.key(), self.cipher(), self.index_key());
+        }
+        record.encrypt(self.key(), self.cipher())
+    }
+
+    // A record that's there but can't be decrypted (because it's been
+    // tampered with, say) is an error, rather than None.
+    pub fn get(&self, key: String) -> Result<Option<DecryptedRecord>, Error> {
         log::trace!("Getting record with key {} ...", key);
-        self.hash_map
-            .get(&key)
-            .map(|encrypted| encrypted.decrypt(self.store_pwd(), self.salt()).unwrap())
+        match self.hash_map.get(&self.map_key(&key)) {
+            Some(encrypted) => Ok(Some(self.decrypt_record(&encrypted)?)),
+            None => Ok(None),
+        }
     }
 
-    pub fn get_metadata(&self, key: String) -> Option<Metadata> {
+    // Only the metadata is decrypted, not the record's secrets.
+    pub fn get_metadata(&self, key: String) -> Result<Option<Metadata>, Error> {
         log::trace!("Getting metadata of record with key {key} ...");
-        match self.get(key.clone()) {
-            Some(r) => Some(r.metadata()),
+        match self.hash_map.get(&self.map_key(&key)) {
+            Some(r) => Ok(Some(r.unseal_metadata(self.key(), self.cipher())?)),
             None => {
                 log::debug!("key {key} not found");
-                None
+                Ok(None)
             }
         }
     }
@@ -210,18 +566,61 @@ impl DB {
         self.hash_map.clone()
     }
 
+    fn index_key(&self) -> &Key {
+        self.index_key
+            .as_ref()
+            .expect("the database must be opened before its key can be used")
+    }
+
     pub fn insert(&self, record: DecryptedRecord) -> Option<EncryptedRecord> {
         let key = record.key();
         log::debug!("Inserting record with key {} ...", key);
-        if let Some(r) = self.get(record.key()) {
-            log::trace!("Record exists; skipping insert");
-            return Some(r.encrypt(self.store_pwd(), self.salt()));
+        match self.get(record.key()) {
+            Ok(Some(r)) => {
+                log::trace!("Record exists; skipping insert");
+                return Some(self.encrypt_record(&r));
+            }
+            Ok(None) => (),
+            Err(e) => log::warn!("Replacing record {key}, which could not be read ({e})"),
         };
-        self.hash_map
-            .insert(key, record.encrypt(self.store_pwd(), self.salt()))
+        let encrypted = self.encrypt_record(&record);
+        let replaced = self.hash_map.insert(encrypted.key(), encrypted.clone());
+        self.persist(&encrypted.key(), Some(&encrypted));
+        replaced
+    }
+
+    // The plaintext header written to the start of the database file
+    pub fn header(&self) -> Header {
+        let mut header = Header::new(self.schema_version(), self.cipher(), self.key_slots());
+        header.sealed_metadata = self.sealed;
+        header
+    }
+
+    // Append a change to a record to the journal. Once a change couldn't be,
+    // none are, since the whole file will be written on close.
+    fn append_journal(&self, id: &str, record: Option<&EncryptedRecord>) -> Result<()> {
+        if self.unsaved.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let mut journal = self.journal.lock().unwrap_or_else(|e| e.into_inner());
+        let mut bytes = Vec::new();
+        if !journal.started {
+            bytes = journal::header(&journal.base);
+        }
+        bytes.extend(journal::entry(
+            self.key(),
+            self.cipher(),
+            &journal,
+            id,
+            record,
+        )?);
+        self.manager.append_journal(&self.file_name, &bytes)?;
+        journal.started = true;
+        journal.entries += 1;
+        Ok(())
     }
 
-    pub fn iter(&self) -> dashmap::iter::Iter<String, EncryptedRecord> {
+    pub fn iter(&self) -> dashmap::iter::Iter<'_, String, EncryptedRecord> {
         self.hash_map.iter()
     }
 
@@ -229,8 +628,420 @@ impl DB {
         self.file_name.clone()
     }
 
-    pub fn salt(&self) -> String {
-        self.salt.clone().unwrap()
+    pub fn latest_backup(&self) -> Result<BackupInfo> {
+        self.manager.latest_backup(&self.backup_dir)
+    }
+
+    fn journal_entries(&self) -> u64 {
+        self.journal
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entries
+    }
+
+    pub fn kdf(&self) -> KdfParams {
+        self.kdf.clone()
+    }
+
+    fn key(&self) -> &Key {
+        self.key
+            .as_ref()
+            .expect("the database must be opened before its key can be used")
+    }
+
+    pub fn key_slot(&self, name: &str) -> Option<KeySlot> {
+        self.slots.iter().find(|s| s.name == name).cloned()
+    }
+
+    pub fn key_slots(&self) -> Vec<KeySlot> {
+        self.slots.clone()
+    }
+
+    // The hashmap key for a record key: the key itself, or, if metadata is
+    // sealed, the record's opaque ID.
+    fn map_key(&self, key: &str) -> String {
+        if self.sealed {
+            return index::id(self.index_key(), key);
+        }
+        key.to_string()
+    }
+
+    // Check a record against a query without decrypting its secrets; see
+    // crate::db::query for the caveats that apply to sealed records.
+    pub fn matches(&self, record: &EncryptedRecord, query: &Query) -> bool {
+        query.matches(record, self.index_key())
+    }
+
+    // Write a changed (or deleted) record straight away: with a backend that
+    // stores records separately, to the backend, and otherwise to the
+    // journal. If that fails, or if the whole database is going to be written
+    // on close anyway, it's left to `close`. The change has to have been made
+    // to the records in memory already.
+    fn persist(&self, id: &str, record: Option<&EncryptedRecord>) {
+        if self.store_hash == 0 {
+            return;
+        }
+        if self.lock_mode == LockMode::Shared {
+            self.unsaved.store(true, Ordering::Relaxed);
+            return;
+        }
+        let result = match self.manager.per_record() {
+            true => self
+                .backup_once()
+                .and_then(|_| match record {
+                    Some(r) => self.manager.put_record(&self.file_name, self.key(), id, r),
+                    None => self.manager.delete_record(&self.file_name, id),
+                })
+                .map(|_| {
+                    self.stored_records
+                        .store(self.hash_map.len(), Ordering::Relaxed)
+                }),
+            false => self.append_journal(id, record),
+        };
+        if let Err(e) = result {
+            log::error!(
+                "Could not write record; it will be written when the database is closed ({e})"
+            );
+            self.unsaved.store(true, Ordering::Relaxed);
+        }
+    }
+
+    // Remove the backups that the retention policy (see
+    // crate::store::retention) doesn't keep, returning their names; with
+    // `dry_run`, nothing is removed.
+    pub fn prune_backups(&self, dry_run: bool) -> Result<Vec<String>> {
+        let pruned = retention::prune(&self.retention, &self.backups()?, chrono::Local::now());
+        if !dry_run {
+            for name in &pruned {
+                self.delete_backup(name)?;
+            }
+        }
+        Ok(pruned)
+    }
+
+    // Pruning after a backup is made doesn't stop whatever made it
+    fn prune_quietly(&self) {
+        match self.prune_backups(false) {
+            Ok(pruned) if !pruned.is_empty() => log::debug!("Pruned {} backups", pruned.len()),
+            Ok(_) => (),
+            Err(e) => log::warn!("Could not prune backups ({e})"),
+        }
+    }
+
+    // The records that might match a query (see crate::db::query), found
+    // without decrypting any of them. Backends that can filter in their own
+    // storage do, as long as it's up to date with the records here;
+    // otherwise every record is checked.
+    pub fn query(&self, query: &Query) -> Vec<EncryptedRecord> {
+        let mut ids = None;
+        if self.store_hash != 0 && !self.unsaved.load(Ordering::Relaxed) {
+            ids = match self
+                .manager
+                .query_records(&self.file_name, query, self.index_key())
+            {
+                Ok(ids) => ids,
+                Err(e) => {
+                    log::warn!("Could not query the database; checking every record ({e})");
+                    None
+                }
+            };
+        }
+        let records: Vec<EncryptedRecord> = match ids {
+            Some(ids) => ids
+                .iter()
+                .filter_map(|id| self.hash_map.get(id).map(|r| r.clone()))
+                .collect(),
+            None => self.iter().map(|i| i.value().clone()).collect(),
+        };
+        records
+            .into_iter()
+            .filter(|r| self.matches(r, query))
+            .collect()
+    }
+
+    fn needs_compaction(&self) -> bool {
+        journal::too_long(self.journal_entries(), self.hash_map.len())
+    }
+
+    // Create the key for a new (or upgraded legacy) database, along with the
+    // default key slot for it.
+    fn new_key(&mut self, pwd: &str) -> Result<Key> {
+        let key = keyslot::new_dek();
+        let slot = KeySlot::new(
+            keyslot::DEFAULT_SLOT,
+            SlotKind::Password,
+            self.kdf.renew(),
+            pwd,
+            self.key_file.as_ref(),
+            &key,
+            self.cipher(),
+        )?;
+        self.slots = vec![slot];
+        self.slot = keyslot::DEFAULT_SLOT.to_string();
+        Ok(key)
+    }
+
+    // Open the database with a key recombined from Shamir shares (see
+    // `split_key`) and give its default key slot a new password. This is for
+    // when every slot's secret has been lost; the file is backed up first,
+    // and the change is persisted when the database is closed.
+    pub fn recover(&mut self, shares: &[String], new_pwd: &str) -> Result<String> {
+        log::debug!("Recovering database from {} shares ...", shares.len());
+        let key = shamir::combine(shares)?;
+        if let Err(e) = self.open_with_key(key) {
+            log::error!("{e}");
+            return Err(anyhow!(
+                "could not open the database with the recovered key (wrong or mismatched shares?)"
+            ));
+        }
+        let backup_file = match self.key_slot(keyslot::DEFAULT_SLOT) {
+            Some(slot) if slot.kind == SlotKind::Password => {
+                self.rekey_slot(keyslot::DEFAULT_SLOT, new_pwd)?
+            }
+            _ => {
+                self.slots.retain(|s| s.name != keyslot::DEFAULT_SLOT);
+                self.add_key_slot(keyslot::DEFAULT_SLOT, SlotKind::Password, new_pwd)?;
+                self.backup()?
+            }
+        };
+        self.slot = keyslot::DEFAULT_SLOT.to_string();
+        self.store_hash = 0;
+        log::info!(
+            "Recovered database; key slot {:?} has a new password",
+            self.slot
+        );
+        Ok(backup_file)
+    }
+
+    // Change the password that the database was opened with; see
+    // `rekey_slot`.
+    pub fn rekey(&mut self, new_pwd: &str) -> Result<String> {
+        self.rekey_slot(&self.slot.clone(), new_pwd)
+    }
+
+    // Change the password of a key slot: the database's key is wrapped again
+    // with one derived from the new password (and a fresh KDF salt), so no
+    // records need to be re-encrypted, and any other slots keep working. Every
+    // record is decrypted first, so that a key that doesn't unlock them isn't
+    // locked away under the new password. The database file is backed up; as with other updates, the change is
+    // only persisted once the database is closed. The slot will need the key
+    // file that is set now (if any), whatever it needed before.
+    pub fn rekey_slot(&mut self, name: &str, new_pwd: &str) -> Result<String> {
+        log::debug!("Re-keying key slot {name:?} ...");
+        let old = match self.key_slot(name) {
+            Some(slot) => slot,
+            None => return Err(anyhow!("there is no key slot named {name:?}")),
+        };
+        if old.kind != SlotKind::Password {
+            return Err(anyhow!(
+                "key slot {name:?} holds a {} key, not a password",
+                old.kind.name()
+            ));
+        }
+        for entry in self.hash_map.iter() {
+            if let Err(e) = self.decrypt_record(entry.value()) {
+                return Err(anyhow!(
+                    "record {} could not be decrypted with the database's key, so key slot {name:?} was left as it was ({e})",
+                    entry.key()
+                ));
+            }
+        }
+        let slot = KeySlot::new(
+            name,
+            SlotKind::Password,
+            old.kdf.renew(),
+            new_pwd,
+            self.key_file.as_ref(),
+            self.key(),
+            self.cipher(),
+        )?;
+        let mut backup_file = String::new();
+        if self.exists()? {
+            backup_file = self.backup()?;
+        }
+        for s in self.slots.iter_mut().filter(|s| s.name == name) {
+            *s = slot.clone();
+        }
+        // Force a write on close, even for an empty database
+        self.store_hash = 0;
+        log::info!("Re-keyed key slot {name:?}");
+        Ok(backup_file)
+    }
+
+    // Records written before schema version 0.10.0 were encrypted with the
+    // padded master password; re-encrypt them with the database's key. The
+    // change is persisted the next time the database is closed.
+    fn rekey_legacy_records(&self, legacy_key: &LegacyKey) -> Result<()> {
+        log::info!("Re-keying records from schema version {} ...", self.version);
+        for mut entry in self.hash_map.iter_mut() {
+            let rekeyed = v0100::rekey_encrypted_record_from_v090(
+                entry.value().clone(),
+                legacy_key,
+                self.key(),
+                self.cipher(),
+            )?;
+            *entry.value_mut() = rekeyed;
+        }
+        Ok(())
+    }
+
+    fn release_lock(&self) {
+        let mut lock = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        if lock.take().is_some() {
+            log::debug!("Released database lock");
+        }
+    }
+
+    // Replace the stored database with one of its backups (and drop its
+    // journal, whose changes were made to another file); the database
+    // shouldn't be open (or, at least, shouldn't be closed afterwards).
+    pub fn restore_backup(&self, name: &str) -> Result<()> {
+        log::debug!("Restoring backup {name} ...");
+        self.manager
+            .restore_backup(&self.backup_dir, name, &self.file_name)?;
+        self.manager.clear_journal(&self.file_name)
+    }
+
+    // Apply the changes in the journal to the records just loaded from the
+    // database file, whose MAC is `base`. If the journal can't all be used,
+    // the file is rewritten on close (which clears it).
+    fn replay_journal(&mut self, base: Vec<u8>) -> Result<()> {
+        let mut state = Journal {
+            base,
+            ..Default::default()
+        };
+        if !self.manager.per_record() && self.store_hash != 0 {
+            let bytes = self.manager.read_journal(&self.file_name)?;
+            let replay = journal::replay(
+                &self.file_name,
+                self.key(),
+                self.cipher,
+                &state.base,
+                &bytes,
+            )?;
+            if replay.stale || replay.torn {
+                log::warn!(
+                    "Ignoring {} part of the journal of {}",
+                    if replay.stale { "the stale" } else { "a torn" },
+                    self.file_name
+                );
+                self.store_hash = 0;
+            }
+            log::debug!("Replaying {} journal entries ...", replay.changes.len());
+            state.entries = replay.changes.len() as u64;
+            state.started = !bytes.is_empty();
+            for (id, record) in replay.changes {
+                match record {
+                    Some(r) => self.hash_map.insert(id, r),
+                    None => self.hash_map.remove(&id).map(|(_, r)| r),
+                };
+            }
+        }
+        *self.journal.get_mut().unwrap_or_else(|e| e.into_inner()) = state;
+        Ok(())
+    }
+
+    // Remove a key slot; this may be the slot the database was opened with,
+    // but not the only one left.
+    pub fn remove_key_slot(&mut self, name: &str) -> Result<()> {
+        log::debug!("Removing key slot {name:?} ...");
+        if self.key_slot(name).is_none() {
+            return Err(anyhow!("there is no key slot named {name:?}"));
+        }
+        if self.slots.len() == 1 {
+            return Err(anyhow!(
+                "can't remove the only key slot; the database couldn't be opened without it"
+            ));
+        }
+        self.slots.retain(|s| s.name != name);
+        self.store_hash = 0;
+        Ok(())
+    }
+
+    // Seal the metadata of every record in an existing database. As with
+    // `rekey`, nothing is changed if any record fails to decrypt, and the
+    // change is persisted when the database is closed.
+    pub fn seal_metadata(&mut self) -> Result<()> {
+        if self.sealed {
+            log::debug!("Metadata is already sealed");
+            return Ok(());
+        }
+        log::debug!("Sealing record metadata ...");
+        let index_key = self.index_key();
+        let sealed: records::HashMap = DashMap::new();
+        for i in self.hash_map.iter() {
+            let record = match self.decrypt_record(i.value()) {
+                Ok(r) => r,
+                Err(e) => {
+                    let msg = format!("could not decrypt record {:?}; database unchanged", i.key());
+                    log::error!("{} ({:})", msg, e);
+                    return Err(anyhow!("{} ({:})", msg, e));
+                }
+            };
+            let encrypted = record.seal(self.key(), self.cipher(), index_key);
+            sealed.insert(encrypted.key(), encrypted);
+        }
+        self.hash_map = sealed;
+        self.sealed = true;
+        self.store_hash = 0;
+        log::info!("Sealed the metadata of {} records", self.hash_map.len());
+        Ok(())
+    }
+
+    pub fn sealed_metadata(&self) -> bool {
+        self.sealed
+    }
+
+    // The key file (see crate::crypto::keyfile) to unlock the database with.
+    // Password key slots created (or re-keyed) while one is set will need it.
+    pub fn set_key_file(&mut self, key_file: Option<Key>) {
+        self.key_file = key_file;
+    }
+
+    // The key is held (in locked memory) for as long as the database is; so is
+    // the index key derived from it, which is needed for every record lookup
+    // once metadata is sealed.
+    fn set_key(&mut self, key: Key) {
+        self.index_key = Some(index::derive_key(&key));
+        self.key = Some(key);
+    }
+
+    // How the database is locked while it's open (see crate::store::lock):
+    // with a shared lock, it can be read but changes to an existing database
+    // aren't saved. If another process has a lock that conflicts, it's
+    // waited for, for up to `wait`.
+    pub fn set_lock(&mut self, mode: LockMode, wait: Duration) {
+        self.lock_mode = mode;
+        self.lock_wait = wait;
+    }
+
+    // The backend the database is read from and written to; by default, the
+    // one picked by crate::store::manager::new.
+    pub fn set_manager(&mut self, manager: Box<dyn StoreManager>) {
+        self.manager = manager;
+    }
+
+    pub fn set_retention(&mut self, policy: Policy) {
+        self.retention = policy;
+    }
+
+    // The KDF params are only used for the key slots of new (or upgraded)
+    // databases; when an existing database is opened, these are replaced
+    // with the params of the slot that was unlocked.
+    pub fn set_kdf(&mut self, kdf: KdfParams) {
+        self.kdf = kdf;
+    }
+
+    // As with the KDF params, the cipher is only used for new (or upgraded)
+    // databases; existing ones keep the cipher recorded in the file.
+    pub fn set_cipher(&mut self, cipher: Cipher) {
+        self.cipher = cipher;
+    }
+
+    // Likewise only used for new databases; use `seal_metadata` to convert an
+    // existing one.
+    pub fn set_sealed_metadata(&mut self, sealed: bool) {
+        self.sealed = sealed;
     }
 
     fn serialise(&self) -> Result<Vec<u8>> {
@@ -255,8 +1066,30 @@ impl DB {
         }
     }
 
-    pub fn store_pwd(&self) -> String {
-        self.store_pwd.clone().unwrap()
+    // Split the database's key into `shares` Shamir shares, any `threshold`
+    // of which can be given to `recover`. Since the key itself is split, the
+    // shares keep working however the key slots change.
+    pub fn split_key(&self, shares: u8, threshold: u8) -> Result<Vec<String>> {
+        log::debug!("Splitting key into {shares} shares (threshold {threshold}) ...");
+        shamir::split(self.key(), shares, threshold)
+    }
+
+    fn slot_key_file(&self, kind: SlotKind) -> Option<&Key> {
+        match kind {
+            SlotKind::Password => self.key_file.as_ref(),
+            SlotKind::Recovery => None,
+        }
+    }
+
+    // The lock is kept from one try at opening the database to the next
+    fn take_lock(&mut self) -> Result<()> {
+        let held = self.lock.get_mut().unwrap_or_else(|e| e.into_inner());
+        if held.is_none() {
+            *held = self
+                .manager
+                .lock(&self.file_name, self.lock_mode, self.lock_wait)?;
+        }
+        Ok(())
     }
 
     // Note that the key has to be passed here, even though the
@@ -277,9 +1110,20 @@ impl DB {
 
     pub fn update_metadata(&self, key: String, metadata: Metadata) {
         log::debug!("Updating metadata on record with key {key} ...");
-        match self.hash_map.try_entry(key) {
+        let id = self.map_key(&key);
+        match self.hash_map.try_entry(id.clone()) {
             Some(entry) => {
-                entry.and_modify(|r| r.metadata = metadata);
+                entry.and_modify(|r| {
+                    if r.is_sealed() {
+                        r.reseal_metadata(metadata, self.key(), self.cipher(), self.index_key())
+                    } else {
+                        r.metadata = metadata
+                    }
+                });
+                let updated = self.hash_map.get(&id).map(|r| r.clone());
+                if let Some(r) = updated {
+                    self.persist(&id, Some(&r));
+                }
                 log::trace!("updated!")
             }
             None => {
@@ -290,11 +1134,126 @@ impl DB {
         }
     }
 
+    // The name of the key slot the database was opened with
+    pub fn unlocked_slot(&self) -> String {
+        self.slot.clone()
+    }
+
     // Added in v0.7.0
     pub fn version(&self) -> versions::SemVer {
         self.version.clone()
     }
 
+    // Check that a backup could be restored (see crate::db::verify); the
+    // backup is only read.
+    pub fn verify_backup(&self, info: &BackupInfo) -> Result<Verification> {
+        let key = match &self.key {
+            Some(key) => key.clone(),
+            None => {
+                return Err(anyhow!(
+                    "the database must be opened before its backups can be verified"
+                ))
+            }
+        };
+        log::debug!("Verifying backup {} ...", info.name);
+        let read = self.read_backup(&info.name, key);
+        Ok(verify::verify(info, read))
+    }
+
+    // Backups of a legacy database made before it was upgraded are still in
+    // the old format; those are read with the legacy key, and anything else
+    // with the database's.
+    fn read_backup(&self, name: &str, key: Key) -> Result<EncryptedDB> {
+        let read = self.manager.read_backup(&self.backup_dir, name, key);
+        if read.is_err() {
+            if let Some(legacy_key) = &self.legacy_key {
+                let legacy = self
+                    .manager
+                    .read_legacy_backup(&self.backup_dir, name, legacy_key);
+                if legacy.is_ok() {
+                    return legacy;
+                }
+            }
+        }
+        read
+    }
+
+    // A database that already exists is only ever written by whoever has an
+    // exclusive lock on it; with a shared lock, any changes (including the
+    // upgrade of an old database) are left for the next writer to make.
+    fn read_only(&self) -> Result<bool> {
+        if self.lock_mode == LockMode::Shared && self.exists()? {
+            log::warn!(
+                "{} was opened read-only; not saving changes ...",
+                self.file_name
+            );
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    // Write the whole database file, which takes in (and clears) the journal,
+    // and return the hash of what's now stored. The file that's replaced is
+    // backed up first, if `backup` is set.
+    fn write_file(&self, backup: bool) -> Result<u32> {
+        // Reverse the workflow of `open` ... encode the hashmap
+        let srl = match self.serialise() {
+            Ok(x) => Ok(x),
+            Err(e) => {
+                let msg = "Could not serialise self";
+                log::error!("{} {:?} ({:})", msg, self.file_name(), e);
+                Err(anyhow!("{} {:?} ({:})", msg, self.file_name(), e))
+            }
+        }?;
+        // Create versioned data
+        let vsn_db = VersionedDB::from_bytes(srl);
+        let encoded = match vsn_db.serialise() {
+            Ok(x) => Ok(x),
+            Err(e) => {
+                let msg = "Could not serialise version db";
+                log::error!("{} {:?} ({:})", msg, self.file_name(), e);
+                Err(anyhow!("{} {:?} ({:})", msg, self.file_name(), e))
+            }
+        }?;
+        // Get the hash for the versioned data
+        let store_hash = vsn_db.hash();
+        let mut journal = self.journal.lock().unwrap_or_else(|e| e.into_inner());
+        if store_hash == self.store_hash && journal.entries == 0 {
+            log::debug!("No change in store hash; not persisting ...");
+            return Ok(store_hash);
+        }
+        if self.read_only()? {
+            return Ok(self.store_hash);
+        }
+        let backup = backup && self.exists()?;
+        if backup {
+            log::debug!("Database file exists; backing up ...");
+            self.copy_backup()?;
+        }
+        // Encrypt the versioned data
+        let enc_db = EncryptedDB::from_decrypted(
+            encoded,
+            self.file_name(),
+            self.header(),
+            self.key().clone(),
+        )?;
+
+        // Save the encrypted data; if the journal isn't cleared after, it's
+        // stale (see crate::db::journal), and ignored
+        self.manager.write(&enc_db)?;
+        self.stored_records
+            .store(self.hash_map.len(), Ordering::Relaxed);
+        self.manager.clear_journal(&self.file_name)?;
+        *journal = Journal {
+            base: journal::base(&enc_db.bytes()),
+            ..Default::default()
+        };
+        if backup {
+            self.prune_quietly();
+        }
+        Ok(store_hash)
+    }
+
     // Added in v0.10.1
     pub fn schema_version(&self) -> versions::SemVer {
         records::version()
@@ -303,10 +1262,34 @@ impl DB {
 
 #[cfg(test)]
 mod tests {
-    use rucksack_lib::time;
+    use std::path::Path;
+    use std::time::Duration;
+
+    use rucksack_lib::{file, time};
 
+    use crate::crypto::keyslot::{self, SlotKind};
+    use crate::crypto::{keyfile, legacy, shamir, Cipher, Key};
+    use crate::db::header::Header;
+    use crate::db::query::Query;
+    use crate::error::{IntegrityError, LockedError};
+    use crate::store::backend::MemoryBackend;
+    use crate::store::backup;
+    use crate::store::lock::LockMode;
     use crate::testing;
 
+    // A database in `dir` (laid out as testing::db::TempDB lays it out) with
+    // the cheap KDF costs the tests use, for the caller to open
+    fn test_db(dir: &Path, pwd: Option<&str>, salt: Option<&str>) -> super::DB {
+        let mut db = super::DB::new(
+            dir.join("data").join("secrets").display().to_string(),
+            dir.join("backups").display().to_string(),
+            pwd.map(|p| p.to_string()),
+            salt.map(|s| s.to_string()),
+        );
+        db.set_kdf(testing::data::kdf_params());
+        db
+    }
+
     #[test]
     fn db_basics() {
         let pwd = Some(testing::data::store_pwd());
@@ -314,27 +1297,25 @@ mod tests {
         let mut db_handler = testing::db::new();
         let mut r = db_handler.setup();
         assert!(r.is_ok());
-        let db_file = db_handler.file_name().unwrap();
-        let backups = db_handler.backups_path().unwrap().display().to_string();
-        println!("Got db_file: {db_file}");
-        println!("Got backups_path: {backups}");
 
         // Store data and close
-        let mut tmp_db =
-            super::DB::new(db_file.clone(), backups.clone(), pwd.clone(), salt.clone());
+        let mut tmp_db = test_db(&db_handler.base, pwd.as_deref(), salt.as_deref());
+        tmp_db.set_cipher(Cipher::XChaCha20Poly1305);
         assert!(tmp_db.open().is_ok());
         assert!(tmp_db.version() > versions::SemVer::new("0.8.0").unwrap());
-        let dpr = testing::data::plaintext_record_v090();
+        let dpr = testing::data::plaintext_record_v0100();
         tmp_db.insert(dpr.clone());
-        let re_dpr = tmp_db.get(dpr.key()).unwrap();
+        let re_dpr = tmp_db.get(dpr.key()).unwrap().unwrap();
         assert_eq!(re_dpr.secrets.user, "alice@site.com");
         assert_eq!(re_dpr.secrets.password, "6 s3kr1t");
         assert!(tmp_db.close().is_ok());
 
         // Re-open DB and check stored data
-        let mut tmp_db = super::DB::new(db_file, backups, pwd, salt);
+        let mut tmp_db = test_db(&db_handler.base, pwd.as_deref(), salt.as_deref());
         assert!(tmp_db.open().is_ok());
-        let read_dpr = tmp_db.get(dpr.key()).unwrap();
+        // The cipher is read from the file, not the (default) setting
+        assert_eq!(tmp_db.cipher(), Cipher::XChaCha20Poly1305);
+        let read_dpr = tmp_db.get(dpr.key()).unwrap().unwrap();
         assert_eq!(read_dpr.secrets.user, "alice@site.com");
         assert_eq!(read_dpr.secrets.password, "6 s3kr1t");
         assert_eq!(read_dpr.history.len(), 2);
@@ -344,4 +1325,521 @@ mod tests {
         r = db_handler.teardown();
         assert!(r.is_ok());
     }
+
+    #[test]
+    fn in_memory() {
+        let pwd = Some(testing::data::store_pwd());
+        let salt = Some(time::now());
+        // A unique name, but nothing is created there
+        let base = testing::db::new().base;
+        let new = |pwd: Option<&str>| {
+            let mut db = test_db(&base, pwd, salt.as_deref());
+            db.set_manager(Box::new(MemoryBackend::new()));
+            db
+        };
+        let mut tmp_db = new(pwd.as_deref());
+        assert_eq!(tmp_db.backend(), "memory");
+        assert!(tmp_db.open().is_ok());
+        let dpr = testing::data::plaintext_record_v0100();
+        tmp_db.insert(dpr.clone());
+        assert!(tmp_db.close().is_ok());
+        assert!(!base.exists());
+
+        // Re-open, change, and compact, which backs up what was stored
+        let mut tmp_db = new(pwd.as_deref());
+        assert!(tmp_db.open().is_ok());
+        assert_eq!(tmp_db.describe().unwrap(), tmp_db.header());
+        let read_dpr = tmp_db.get(dpr.key()).unwrap().unwrap();
+        assert_eq!(read_dpr.secrets.password, "6 s3kr1t");
+        tmp_db.delete(dpr.key());
+        assert!(tmp_db.compact().is_ok());
+        assert!(tmp_db.close().is_ok());
+        let latest = tmp_db.latest_backup().unwrap();
+        assert_eq!(latest.records, Some(1));
+        assert_eq!(tmp_db.backups().unwrap(), vec![latest.clone()]);
+        let name = latest.name;
+        assert!(new(Some("wrong")).open().is_err());
+
+        // Restoring the backup brings the record back
+        assert!(tmp_db.restore_backup("nope").is_err());
+        assert!(tmp_db.restore_backup(&name).is_ok());
+        let mut tmp_db = new(pwd.as_deref());
+        assert!(tmp_db.open().is_ok());
+        assert!(tmp_db.get(dpr.key()).unwrap().is_some());
+        assert!(tmp_db.delete_backup(&name).is_ok());
+        assert!(tmp_db.delete_backup(&name).is_err());
+        assert!(tmp_db.backups().unwrap().is_empty());
+        assert!(!base.exists());
+    }
+
+    #[test]
+    fn tampered_records() {
+        let base = testing::db::new().base;
+        let mut tmp_db = test_db(&base, Some(&testing::data::store_pwd()), None);
+        tmp_db.set_manager(Box::new(MemoryBackend::new()));
+        tmp_db.set_sealed_metadata(true);
+        assert!(tmp_db.open().is_ok());
+        let dpr = testing::data::plaintext_record_v0100();
+        tmp_db.insert(dpr.clone());
+        let id = tmp_db.map_key(&dpr.key());
+
+        // A changed secret is an error, not a panic; the (sealed) metadata
+        // can still be read
+        tmp_db.hash_map.get_mut(&id).unwrap().value[0] ^= 1;
+        match tmp_db.get(dpr.key()) {
+            Ok(_) => panic!("a tampered record was decrypted"),
+            Err(e) => assert!(e.downcast_ref::<IntegrityError>().is_some()),
+        }
+        assert!(tmp_db.get_metadata(dpr.key()).unwrap().is_some());
+        tmp_db.hash_map.get_mut(&id).unwrap().sealed[0] ^= 1;
+        assert!(tmp_db.get_metadata(dpr.key()).is_err());
+        assert!(tmp_db.get("nope".to_string()).unwrap().is_none());
+    }
+
+    #[test]
+    fn locking() {
+        let pwd = testing::data::store_pwd();
+        let salt = time::now();
+        let mut db_handler = testing::db::new();
+        assert!(db_handler.setup().is_ok());
+        let new = |mode: LockMode| {
+            let mut db = test_db(&db_handler.base, Some(&pwd), Some(&salt));
+            db.set_lock(mode, Duration::ZERO);
+            db
+        };
+        let dpr = testing::data::plaintext_record_v0100();
+        let mut writer = new(LockMode::Exclusive);
+        assert!(writer.open().is_ok());
+        writer.insert(dpr.clone());
+        for mode in [LockMode::Shared, LockMode::Exclusive] {
+            match new(mode).open() {
+                Ok(_) => panic!("opened a database that was locked"),
+                Err(e) => assert!(e.downcast_ref::<LockedError>().is_some()),
+            }
+        }
+        assert!(writer.close().is_ok());
+
+        // Readers don't get in each other's way, but can't save changes
+        let mut reader = new(LockMode::Shared);
+        assert!(reader.open().is_ok());
+        let mut other = new(LockMode::Shared);
+        assert!(other.open().is_ok());
+        assert!(new(LockMode::Exclusive).open().is_err());
+        assert!(other.get(dpr.key()).unwrap().is_some());
+        assert!(other.close().is_ok());
+        reader.delete(dpr.key());
+        assert!(reader.close().is_ok());
+        let mut writer = new(LockMode::Exclusive);
+        assert!(writer.open().is_ok());
+        assert!(writer.get(dpr.key()).unwrap().is_some());
+        assert!(db_handler.teardown().is_ok());
+    }
+
+    #[test]
+    fn journal() {
+        let pwd = testing::data::store_pwd();
+        let salt = time::now();
+        let mut db_handler = testing::db::new();
+        assert!(db_handler.setup().is_ok());
+        let db_file = db_handler.file_name().unwrap();
+        let backups = db_handler.backups_path().unwrap().display().to_string();
+        let journal_file = crate::store::manager::journal_file(&db_file);
+        let new = || test_db(&db_handler.base, Some(&pwd), Some(&salt));
+        let dpr = testing::data::plaintext_record_v0100();
+        let mut other = dpr.clone();
+        other.metadata.url = "https://other.site".to_string();
+        assert!(super::DB::init(db_file.clone(), backups.clone(), Some(pwd.clone()), None).is_ok());
+        let stored = std::fs::read(&db_file).unwrap();
+
+        // Changes go to the journal rather than the file, and are replayed
+        let mut tmp_db = new();
+        assert!(tmp_db.open().is_ok());
+        tmp_db.insert(dpr.clone());
+        assert!(tmp_db.close().is_ok());
+        assert_eq!(std::fs::read(&db_file).unwrap(), stored);
+        let mut tmp_db = new();
+        assert!(tmp_db.open().is_ok());
+        assert_eq!(
+            tmp_db.get(dpr.key()).unwrap().unwrap().secrets.password,
+            "6 s3kr1t"
+        );
+
+        // Compacting folds the journal into the file
+        assert!(tmp_db.compact().is_ok());
+        assert!(tmp_db.close().is_ok());
+        assert_ne!(std::fs::read(&db_file).unwrap(), stored);
+        assert!(!std::path::Path::new(&journal_file).exists());
+        assert_eq!(backup::list(backups.clone()).unwrap().len(), 1);
+
+        // What's left of an interrupted append is ignored
+        let mut tmp_db = new();
+        assert!(tmp_db.open().is_ok());
+        tmp_db.insert(other.clone());
+        assert!(tmp_db.close().is_ok());
+        assert!(file::append(&[0, 1], journal_file.clone()).is_ok());
+        let mut tmp_db = new();
+        assert!(tmp_db.open().is_ok());
+        assert!(tmp_db.get(other.key()).unwrap().is_some());
+        assert!(tmp_db.close().is_ok());
+        assert!(!std::path::Path::new(&journal_file).exists());
+
+        // ... but a changed entry isn't
+        let mut tmp_db = new();
+        assert!(tmp_db.open().is_ok());
+        tmp_db.delete(other.key());
+        assert!(tmp_db.close().is_ok());
+        let mut bytes = std::fs::read(&journal_file).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        std::fs::write(&journal_file, bytes).unwrap();
+        match new().open() {
+            Ok(_) => panic!("replayed a journal that was changed"),
+            Err(e) => assert!(e.downcast_ref::<IntegrityError>().is_some()),
+        }
+        assert!(db_handler.teardown().is_ok());
+    }
+
+    #[test]
+    fn rekey() {
+        let pwd = testing::data::store_pwd();
+        let salt = time::now();
+        let mut db_handler = testing::db::new();
+        assert!(db_handler.setup().is_ok());
+        let new = |pwd: &str| test_db(&db_handler.base, Some(pwd), Some(&salt));
+        let dpr = testing::data::plaintext_record_v0100();
+        let mut tmp_db = new(&pwd);
+        assert!(tmp_db.open().is_ok());
+        tmp_db.insert(dpr.clone());
+        assert!(tmp_db.close().is_ok());
+
+        // A slot that doesn't exist leaves everything as it was
+        let mut tmp_db = new(&pwd);
+        assert!(tmp_db.open().is_ok());
+        let slots = tmp_db.key_slots();
+        let stored = tmp_db.hash_map.get(&dpr.key()).unwrap().clone();
+        assert!(tmp_db.rekey_slot("nope", "n3w pwd").is_err());
+        assert_eq!(tmp_db.key_slots(), slots);
+
+        // As does a record that the key doesn't decrypt
+        let mut broken = stored.clone();
+        broken.value[0] ^= 1;
+        tmp_db.hash_map.insert(dpr.key(), broken);
+        assert!(tmp_db.rekey("n3w pwd").is_err());
+        assert_eq!(tmp_db.key_slots(), slots);
+        tmp_db.hash_map.insert(dpr.key(), stored.clone());
+
+        // Otherwise, the old password stops working once the DB is closed,
+        // though the records themselves are never re-encrypted
+        let backup_file = tmp_db.rekey("n3w pwd").unwrap();
+        assert!(std::path::Path::new(&backup_file).exists());
+        assert_ne!(tmp_db.key_slots(), slots);
+        assert!(tmp_db.close().is_ok());
+        assert!(new(&pwd).open().is_err());
+        let mut tmp_db = new("n3w pwd");
+        assert!(tmp_db.open().is_ok());
+        assert_eq!(tmp_db.hash_map.get(&dpr.key()).unwrap().value, stored.value);
+        let read_dpr = tmp_db.get(dpr.key()).unwrap().unwrap();
+        assert_eq!(read_dpr.secrets.password, "6 s3kr1t");
+        assert_eq!(read_dpr.history.len(), 2);
+        assert!(db_handler.teardown().is_ok());
+    }
+
+    #[test]
+    fn key_slots() {
+        let pwd = testing::data::store_pwd();
+        let salt = time::now();
+        let mut db_handler = testing::db::new();
+        assert!(db_handler.setup().is_ok());
+        let new = |pwd: &str| test_db(&db_handler.base, Some(pwd), Some(&salt));
+        let dpr = testing::data::plaintext_record_v0100();
+        let recovery = keyslot::new_recovery_key();
+        let mut tmp_db = new(&pwd);
+        assert!(tmp_db.open().is_ok());
+        tmp_db.insert(dpr.clone());
+        assert!(tmp_db
+            .add_key_slot("bob", SlotKind::Password, "b0b")
+            .is_ok());
+        assert!(tmp_db
+            .add_key_slot("bob", SlotKind::Password, "b0b")
+            .is_err());
+        assert!(tmp_db
+            .add_key_slot("offline", SlotKind::Recovery, &recovery)
+            .is_ok());
+        assert!(tmp_db.close().is_ok());
+
+        // Any slot opens the database
+        for (secret, slot) in [
+            (pwd.as_str(), "default"),
+            ("b0b", "bob"),
+            (&recovery, "offline"),
+        ] {
+            let mut tmp_db = new(secret);
+            assert!(tmp_db.open().is_ok());
+            assert_eq!(tmp_db.unlocked_slot(), slot);
+            assert_eq!(
+                tmp_db.get(dpr.key()).unwrap().unwrap().secrets.password,
+                "6 s3kr1t"
+            );
+        }
+
+        // Recovery keys can't be changed like passwords, but they can be used
+        // to change a password
+        let mut tmp_db = new(&recovery);
+        assert!(tmp_db.open().is_ok());
+        assert!(tmp_db.rekey("n3w pwd").is_err());
+        assert!(tmp_db.rekey_slot("default", "n3w pwd").is_ok());
+        assert!(tmp_db.remove_key_slot("bob").is_ok());
+        assert!(tmp_db.remove_key_slot("bob").is_err());
+        assert!(tmp_db.close().is_ok());
+        assert!(new(&pwd).open().is_err());
+        assert!(new("b0b").open().is_err());
+        let mut tmp_db = new("n3w pwd");
+        assert!(tmp_db.open().is_ok());
+        assert_eq!(tmp_db.key_slots().len(), 2);
+        assert!(tmp_db.remove_key_slot("offline").is_ok());
+        assert!(tmp_db.remove_key_slot("default").is_err());
+        assert!(db_handler.teardown().is_ok());
+    }
+
+    #[test]
+    fn key_file() {
+        let pwd = testing::data::store_pwd();
+        let salt = time::now();
+        let mut db_handler = testing::db::new();
+        assert!(db_handler.setup().is_ok());
+        let db_file = db_handler.file_name().unwrap();
+        let key_file = keyfile::from_bytes(keyfile::generate().as_bytes()).unwrap();
+        let new = |key_file: Option<&Key>| {
+            let mut db = test_db(&db_handler.base, Some(&pwd), Some(&salt));
+            db.set_key_file(key_file.cloned());
+            db
+        };
+        let dpr = testing::data::plaintext_record_v0100();
+        let mut tmp_db = new(Some(&key_file));
+        assert!(tmp_db.open().is_ok());
+        tmp_db.insert(dpr.clone());
+        assert!(tmp_db.close().is_ok());
+        assert!(Header::read(db_file.clone()).unwrap().slots[0].key_file);
+
+        // The password alone (or with another key file) isn't enough ...
+        let e = new(None).open().unwrap_err();
+        assert!(e.to_string().contains("key file is needed"));
+        let other = keyfile::from_bytes(b"other").unwrap();
+        assert!(new(Some(&other)).open().is_err());
+        // ... but re-keying without one means it's no longer needed
+        let mut tmp_db = new(Some(&key_file));
+        assert!(tmp_db.open().is_ok());
+        tmp_db.set_key_file(None);
+        assert!(tmp_db.rekey(&pwd).is_ok());
+        assert!(tmp_db.close().is_ok());
+        let mut tmp_db = new(None);
+        assert!(tmp_db.open().is_ok());
+        assert_eq!(
+            tmp_db.get(dpr.key()).unwrap().unwrap().secrets.password,
+            "6 s3kr1t"
+        );
+        assert!(db_handler.teardown().is_ok());
+    }
+
+    #[test]
+    fn split_and_recover() {
+        let pwd = testing::data::store_pwd();
+        let salt = time::now();
+        let mut db_handler = testing::db::new();
+        assert!(db_handler.setup().is_ok());
+        let new = |pwd: Option<&str>| test_db(&db_handler.base, pwd, Some(&salt));
+        let dpr = testing::data::plaintext_record_v0100();
+        let mut tmp_db = new(Some(&pwd));
+        assert!(tmp_db.open().is_ok());
+        tmp_db.insert(dpr.clone());
+        let shares = tmp_db.split_key(5, 3).unwrap();
+        assert!(tmp_db.close().is_ok());
+
+        // Too few shares, or shares of another key, don't recover anything
+        let mut tmp_db = new(None);
+        assert!(tmp_db.recover(&shares[..2], "n3w pwd").is_err());
+        let other = shamir::split(&keyslot::new_dek(), 3, 3).unwrap();
+        assert!(tmp_db.recover(&other, "n3w pwd").is_err());
+        let backup = tmp_db.recover(&shares[2..], "n3w pwd").unwrap();
+        assert!(!backup.is_empty());
+        assert!(tmp_db.close().is_ok());
+        assert!(new(Some(&pwd)).open().is_err());
+        let mut tmp_db = new(Some("n3w pwd"));
+        assert!(tmp_db.open().is_ok());
+        assert_eq!(tmp_db.unlocked_slot(), keyslot::DEFAULT_SLOT);
+        assert_eq!(
+            tmp_db.get(dpr.key()).unwrap().unwrap().secrets.password,
+            "6 s3kr1t"
+        );
+        assert!(db_handler.teardown().is_ok());
+    }
+
+    #[test]
+    fn sealed_metadata() {
+        let pwd = testing::data::store_pwd();
+        let salt = time::now();
+        let mut db_handler = testing::db::new();
+        assert!(db_handler.setup().is_ok());
+        let db_file = db_handler.file_name().unwrap();
+        let new = || test_db(&db_handler.base, Some(&pwd), Some(&salt));
+        let dpr = testing::data::plaintext_record_v0100();
+        let mut tmp_db = new();
+        assert!(tmp_db.open().is_ok());
+        tmp_db.insert(dpr.clone());
+        assert!(tmp_db.close().is_ok());
+
+        // Existing databases are converted explicitly ...
+        let mut tmp_db = new();
+        assert!(tmp_db.open().is_ok());
+        assert!(!tmp_db.sealed_metadata());
+        assert!(tmp_db.seal_metadata().is_ok());
+        assert!(tmp_db.close().is_ok());
+        let header = crate::db::header::Header::read(db_file.clone()).unwrap();
+        assert!(header.sealed_metadata);
+
+        // ... after which record keys and metadata are no longer stored in
+        // the clear, but records can still be found and filtered
+        let tmp_db = {
+            let mut db = new();
+            assert!(db.open().is_ok());
+            db
+        };
+        assert!(tmp_db.sealed_metadata());
+        assert!(tmp_db.iter().all(|i| i.value().is_sealed()));
+        assert!(tmp_db.hash_map().get(&dpr.key()).is_none());
+        assert_eq!(
+            tmp_db.get(dpr.key()).unwrap().unwrap().metadata,
+            dpr.metadata
+        );
+        let mut md = tmp_db.get_metadata(dpr.key()).unwrap().unwrap();
+        md.access_count += 1;
+        tmp_db.update_metadata(dpr.key(), md);
+        assert_eq!(
+            tmp_db
+                .get_metadata(dpr.key())
+                .unwrap()
+                .unwrap()
+                .access_count,
+            1
+        );
+        let record = tmp_db.iter().next().unwrap().value().clone();
+        let mut query = Query {
+            filter: Some("site.com".to_string()),
+            ..Default::default()
+        };
+        assert!(tmp_db.matches(&record, &query));
+        query.filter = Some("bob".to_string());
+        assert!(!tmp_db.matches(&record, &query));
+        assert_eq!(tmp_db.delete(dpr.key()), Some(true));
+        assert!(db_handler.teardown().is_ok());
+    }
+
+    #[test]
+    fn legacy_upgrade() {
+        let pwd = testing::data::store_pwd();
+        let salt = time::now();
+        let mut db_handler = testing::db::new();
+        assert!(db_handler.setup().is_ok());
+        let db_file = db_handler.file_name().unwrap();
+
+        // Write a 0.9.0 database the way it used to be done
+        let dpr = testing::data::plaintext_record_v090();
+        let bytes = testing::data::legacy_db_v090(&pwd, &salt);
+        assert!(file::write(bytes, db_file.clone()).is_ok());
+
+        // Opening it re-keys the records; closing writes the new format
+        let mut tmp_db = test_db(&db_handler.base, Some(&pwd), Some(&salt));
+        assert!(tmp_db.open().is_ok());
+        assert_eq!(
+            tmp_db.get(dpr.key()).unwrap().unwrap().secrets.password,
+            "6 s3kr1t"
+        );
+        assert!(tmp_db.close().is_ok());
+        let stored = file::read(db_file.clone()).unwrap();
+        assert!(legacy::decrypt(stored, &pwd, &salt).is_err());
+
+        let mut tmp_db = test_db(&db_handler.base, Some(&pwd), Some(&salt));
+        assert!(tmp_db.open().is_ok());
+        assert_eq!(tmp_db.kdf().m_cost, testing::data::kdf_params().m_cost);
+        let read_dpr = tmp_db.get(dpr.key()).unwrap().unwrap();
+        assert_eq!(read_dpr.secrets.password, "6 s3kr1t");
+        assert_eq!(read_dpr.history.len(), 2);
+        assert!(db_handler.teardown().is_ok());
+    }
+
+    #[cfg(feature = "persy")]
+    #[test]
+    fn persy_rows() {
+        per_record_rows(|| Box::new(crate::store::backend::PersyBackend::new()));
+    }
+
+    #[cfg(feature = "redb")]
+    #[test]
+    fn redb_rows() {
+        per_record_rows(|| Box::new(crate::store::backend::ReDBBackend::new()));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_rows() {
+        per_record_rows(|| Box::new(crate::store::backend::SqliteBackend::new()));
+    }
+
+    // The same checks for every backend that stores records separately
+    #[cfg(any(feature = "persy", feature = "redb", feature = "sqlite"))]
+    fn per_record_rows(manager: fn() -> Box<dyn crate::store::manager::StoreManager>) {
+        let pwd = testing::data::store_pwd();
+        let mut db_handler = testing::db::new();
+        assert!(db_handler.setup().is_ok());
+        let backups = db_handler.backups_path().unwrap().display().to_string();
+        let new = || {
+            let mut db = test_db(&db_handler.base, Some(&pwd), None);
+            db.set_manager(manager());
+            db
+        };
+        let dpr = testing::data::plaintext_record_v0100();
+        let mut tmp_db = new();
+        assert!(tmp_db.open().is_ok());
+        tmp_db.insert(dpr.clone());
+        assert!(tmp_db.close().is_ok());
+
+        // Once the database exists, changes are written without closing it
+        let mut tmp_db = new();
+        assert!(tmp_db.open().is_ok());
+        let mut md = tmp_db.get_metadata(dpr.key()).unwrap().unwrap();
+        md.access_count += 1;
+        tmp_db.update_metadata(dpr.key(), md);
+        drop(tmp_db);
+        assert_eq!(backup::list(backups.clone()).unwrap().len(), 1);
+        let mut tmp_db = new();
+        assert!(tmp_db.open().is_ok());
+        assert_eq!(
+            tmp_db
+                .get_metadata(dpr.key())
+                .unwrap()
+                .unwrap()
+                .access_count,
+            1
+        );
+        assert_eq!(
+            tmp_db.get(dpr.key()).unwrap().unwrap().secrets.password,
+            "6 s3kr1t"
+        );
+        let query = Query {
+            category: Some(dpr.metadata.category.clone()),
+            ..Default::default()
+        };
+        assert_eq!(tmp_db.query(&query).len(), 1);
+        let query = Query {
+            category: Some("elsewhere".to_string()),
+            ..Default::default()
+        };
+        assert!(tmp_db.query(&query).is_empty());
+        assert_eq!(tmp_db.delete(dpr.key()), Some(true));
+        drop(tmp_db);
+        let mut tmp_db = new();
+        assert!(tmp_db.open().is_ok());
+        assert!(tmp_db.get(dpr.key()).unwrap().is_none());
+        assert!(db_handler.teardown().is_ok());
+    }
 }
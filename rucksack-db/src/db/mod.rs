@@ -1,5 +1,10 @@
+pub mod diff;
 pub mod encrypted;
+pub mod header;
+pub mod journal;
 pub mod manager;
+pub mod query;
+pub mod verify;
 pub mod versioned;
 
 pub use manager::DB;
@@ -0,0 +1,173 @@
+// A query is a cheap, first-pass filter over encrypted records: it only looks
+// at what can be read without decrypting a record's secrets. For records with
+// plaintext metadata the match is exact; for sealed records it's done against
+// the blind index, which can give (rare) false positives but never false
+// negatives. Callers should still check the decrypted record where exactness
+// matters.
+use rucksack_lib::util;
+
+use crate::crypto::{index, Key};
+use crate::records::{EncryptedRecord, Kind};
+
+// Filters shorter than a trigram can't be checked against the blind index.
+const MIN_FILTER_LEN: usize = 3;
+
+#[derive(Clone, Debug, Default)]
+pub struct Query {
+    pub category: Option<String>,
+    pub kind: Option<Kind>,
+    pub all_tags: Vec<String>,
+    pub any_tags: Vec<String>,
+    pub filter: Option<String>,
+}
+
+impl Query {
+    pub fn new() -> Query {
+        Query {
+            ..Default::default()
+        }
+    }
+
+    pub fn matches(&self, record: &EncryptedRecord, index_key: &Key) -> bool {
+        if record.is_sealed() {
+            return self.matches_index(record, index_key);
+        }
+        let md = record.metadata();
+        if let Some(category) = &self.category {
+            if &md.category != category {
+                return false;
+            }
+        }
+        if let Some(kind) = &self.kind {
+            if &md.kind != kind {
+                return false;
+            }
+        }
+        if !self.all_tags.is_empty() && !util::all(self.all_tags.clone(), md.tag_values()) {
+            return false;
+        }
+        if !self.any_tags.is_empty() && !util::any(self.any_tags.clone(), md.tag_values()) {
+            return false;
+        }
+        match &self.filter {
+            Some(filter) => record.key().contains(filter.as_str()),
+            None => true,
+        }
+    }
+
+    fn matches_index(&self, record: &EncryptedRecord, index_key: &Key) -> bool {
+        let bi = &record.index;
+        if let Some(category) = &self.category {
+            if bi.category != index::blind(index_key, "category", category) {
+                return false;
+            }
+        }
+        if let Some(kind) = &self.kind {
+            if bi.kind != index::blind(index_key, "kind", &kind.name()) {
+                return false;
+            }
+        }
+        let tags = |values: &Vec<String>| -> Vec<u64> {
+            values
+                .iter()
+                .map(|t| index::blind(index_key, "tag", t))
+                .collect()
+        };
+        // These mirror the semantics of util::all and util::any
+        if !self.all_tags.is_empty() {
+            let all = tags(&self.all_tags);
+            if !bi.tags.iter().all(|t| all.contains(t)) {
+                return false;
+            }
+        }
+        if !self.any_tags.is_empty() {
+            let any = tags(&self.any_tags);
+            if !bi.tags.iter().any(|t| any.contains(t)) {
+                return false;
+            }
+        }
+        match &self.filter {
+            Some(filter) if filter.chars().count() >= MIN_FILTER_LEN => index::trigrams(filter)
+                .iter()
+                .all(|t| bi.terms.contains(&index::blind(index_key, "term", t))),
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::{index, Cipher};
+    use crate::records::Kind;
+    use crate::testing;
+
+    #[test]
+    fn plain_and_sealed() {
+        let key = testing::data::store_key();
+        let index_key = index::derive_key(&key);
+        let mut dpr = testing::data::plaintext_record_v0100();
+        dpr.add_tags(vec!["web".to_string(), "personal".to_string()]);
+        let records = [
+            dpr.encrypt(&key, Cipher::default()),
+            dpr.seal(&key, Cipher::default(), &index_key),
+        ];
+        let queries_and_results = vec![
+            (super::Query::new(), true),
+            (
+                super::Query {
+                    category: Some(dpr.metadata.category.clone()),
+                    kind: Some(dpr.metadata.kind.clone()),
+                    ..Default::default()
+                },
+                true,
+            ),
+            (
+                super::Query {
+                    category: Some("nope".to_string()),
+                    ..Default::default()
+                },
+                false,
+            ),
+            (
+                super::Query {
+                    kind: Some(Kind::Certificates),
+                    ..Default::default()
+                },
+                false,
+            ),
+            (
+                super::Query {
+                    any_tags: vec!["web".to_string(), "work".to_string()],
+                    ..Default::default()
+                },
+                true,
+            ),
+            (
+                super::Query {
+                    any_tags: vec!["work".to_string()],
+                    ..Default::default()
+                },
+                false,
+            ),
+            (
+                super::Query {
+                    filter: Some("site.com".to_string()),
+                    ..Default::default()
+                },
+                true,
+            ),
+            (
+                super::Query {
+                    filter: Some("bob".to_string()),
+                    ..Default::default()
+                },
+                false,
+            ),
+        ];
+        for (query, expected) in queries_and_results {
+            for record in records.iter() {
+                assert_eq!(query.matches(record, &index_key), expected, "{query:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,173 @@
+// Whether a backup could be restored: that it decrypts with the database's
+// key (or, for one made before a legacy database was upgraded, with the
+// legacy key), that its records can be decoded (and so migrated to the current schema
+// version, if it's from an older one), and that it has as many records as its
+// manifest (see crate::store::backup::BackupInfo) says, if that's known. The
+// backup is only read, the same way the database itself is when it's opened.
+//
+use anyhow::Result;
+
+use crate::db::encrypted::EncryptedDB;
+use crate::db::versioned::VersionedDB;
+use crate::records;
+use crate::store::backup::BackupInfo;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Verification {
+    pub name: String,
+    pub decrypted: bool,
+    // The schema version of the backup's records, once it's decrypted
+    pub version: Option<versions::SemVer>,
+    // Whether the records could be decoded (and migrated, if need be)
+    pub migrated: bool,
+    pub records: Option<usize>,
+    // What went wrong, if anything did
+    pub error: Option<String>,
+}
+
+impl Verification {
+    pub fn ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+// `read` is what reading the backup with the database's key gave
+pub fn verify(info: &BackupInfo, read: Result<EncryptedDB>) -> Verification {
+    let mut v = Verification {
+        name: info.name.clone(),
+        ..Default::default()
+    };
+    let enc_db = match read {
+        Ok(enc_db) => enc_db,
+        Err(e) => {
+            v.error = Some(format!("could not decrypt it ({e})"));
+            return v;
+        }
+    };
+    v.decrypted = true;
+    let vsn_db = match VersionedDB::deserialise(enc_db.decrypted()) {
+        Ok(vsn_db) => vsn_db,
+        Err(_) if enc_db.is_legacy() => VersionedDB::from_bytes(enc_db.decrypted()),
+        Err(e) => {
+            v.error = Some(format!("could not read its contents ({e})"));
+            return v;
+        }
+    };
+    v.version = Some(vsn_db.version());
+    // Legacy backups have no header to check
+    if !enc_db.is_legacy() && enc_db.header().version() != vsn_db.version() {
+        v.error = Some(format!(
+            "its header (version {}) doesn't match its contents (version {})",
+            enc_db.header().version,
+            vsn_db.version()
+        ));
+        return v;
+    }
+    let hash_map = match records::decode_hashmap(vsn_db.bytes(), vsn_db.version()) {
+        Ok(hash_map) => hash_map,
+        Err(e) => {
+            v.error = Some(format!("could not decode its records ({e})"));
+            return v;
+        }
+    };
+    v.migrated = true;
+    v.records = Some(hash_map.len());
+    match info.records {
+        Some(expected) if expected != hash_map.len() => {
+            v.error = Some(format!(
+                "it has {} records, but its manifest says {expected}",
+                hash_map.len()
+            ));
+        }
+        _ => (),
+    }
+    v
+}
+
+#[cfg(test)]
+mod tests {
+    use rucksack_lib::{file, time};
+
+    use crate::db::DB;
+    use crate::testing;
+
+    #[test]
+    fn backups() {
+        let pwd = testing::data::store_pwd();
+        let mut db_handler = testing::db::new();
+        assert!(db_handler.setup().is_ok());
+        let db_file = db_handler.file_name().unwrap();
+        let backups = db_handler.backups_path().unwrap();
+        let mut tmp_db = DB::new(
+            db_file.clone(),
+            backups.display().to_string(),
+            Some(pwd.clone()),
+            Some(time::now()),
+        );
+        tmp_db.set_kdf(testing::data::kdf_params());
+        assert!(tmp_db.open().is_ok());
+        tmp_db.insert(testing::data::plaintext_record_v0100());
+        assert!(tmp_db.close().is_ok());
+        assert!(tmp_db.backup().is_ok());
+        let info = tmp_db.latest_backup().unwrap();
+        let v = tmp_db.verify_backup(&info).unwrap();
+        assert!(v.ok(), "{:?}", v.error);
+        assert!(v.decrypted && v.migrated);
+        assert_eq!(v.records, Some(1));
+
+        // A manifest that doesn't match
+        let mut wrong = info.clone();
+        wrong.records = Some(2);
+        let v = tmp_db.verify_backup(&wrong).unwrap();
+        assert!(!v.ok() && v.migrated);
+
+        // A backup that's been changed
+        let backup_file = backups.join(&info.name);
+        let mut bytes = std::fs::read(&backup_file).unwrap();
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 1;
+        std::fs::write(&backup_file, bytes).unwrap();
+        let v = tmp_db.verify_backup(&info).unwrap();
+        assert!(!v.ok() && !v.decrypted);
+        assert!(db_handler.teardown().is_ok());
+    }
+
+    #[test]
+    fn legacy_backup() {
+        let pwd = testing::data::store_pwd();
+        let salt = time::now();
+        let mut db_handler = testing::db::new();
+        assert!(db_handler.setup().is_ok());
+        let db_file = db_handler.file_name().unwrap();
+        let backups = db_handler.backups_path().unwrap();
+
+        // A 0.9.0 database, which is backed up as it is when it's upgraded
+        let bytes = testing::data::legacy_db_v090(&pwd, &salt);
+        assert!(file::write(bytes, db_file.clone()).is_ok());
+        let mut tmp_db = DB::new(
+            db_file.clone(),
+            backups.display().to_string(),
+            Some(pwd.clone()),
+            Some(salt.clone()),
+        );
+        tmp_db.set_kdf(testing::data::kdf_params());
+        assert!(tmp_db.open().is_ok());
+        assert!(tmp_db.close().is_ok());
+        let info = tmp_db.latest_backup().unwrap();
+
+        // It's checked with the legacy key, once the database is opened
+        let mut tmp_db = DB::new(
+            db_file,
+            backups.display().to_string(),
+            Some(pwd),
+            Some(salt),
+        );
+        assert!(tmp_db.open().is_ok());
+        let v = tmp_db.verify_backup(&info).unwrap();
+        assert!(v.ok(), "{:?}", v.error);
+        assert!(v.decrypted && v.migrated);
+        assert_eq!(v.version.unwrap().to_string(), "0.9.0");
+        assert_eq!(v.records, Some(1));
+        assert!(db_handler.teardown().is_ok());
+    }
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 use anyhow::{anyhow, Result};
 use bincode::{Decode, Encode};
 use serde::{Deserialize, Serialize};
@@ -6,12 +8,23 @@ use rucksack_lib::util;
 
 use crate::records;
 
-#[derive(Clone, Debug, Default, Serialize, Deserialize, Eq, PartialEq, Encode, Decode)]
+#[derive(Clone, Default, Serialize, Deserialize, Eq, PartialEq, Encode, Decode)]
 pub struct VersionedDB {
     bytes: Vec<u8>,
     version: String,
 }
 
+// Only the size of the data is shown: it's the decrypted database, and for
+// databases from before v0.10.0, records' metadata is in the clear.
+impl fmt::Debug for VersionedDB {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VersionedDB")
+            .field("bytes", &format_args!("<{} bytes>", self.bytes.len()))
+            .field("version", &self.version)
+            .finish()
+    }
+}
+
 impl VersionedDB {
     pub fn new(bytes: Vec<u8>, version: String) -> VersionedDB {
         VersionedDB { bytes, version }
@@ -41,6 +54,10 @@ impl VersionedDB {
         self.bytes.clone()
     }
 
+    // This is only used to tell whether anything has changed since the DB was
+    // opened (so that unchanged DBs aren't re-written); it is not an integrity
+    // check. That's done with a keyed MAC over the whole file (see
+    // crate::crypto::mac).
     pub fn hash(&self) -> u32 {
         crc32fast::hash(self.bytes.as_ref())
     }
@@ -0,0 +1,60 @@
+use std::error;
+use std::fmt;
+
+// Returned (wrapped in an anyhow::Error) when a database file (or a record in
+// it) fails its integrity check, i.e., it was decrypted with the right
+// password but its contents aren't what was written. Callers can tell this
+// apart from other failures with `err.downcast_ref::<IntegrityError>()`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IntegrityError {
+    pub path: String,
+    pub reason: String,
+}
+
+impl IntegrityError {
+    pub fn new(path: String, reason: &str) -> IntegrityError {
+        IntegrityError {
+            path,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "integrity check failed for {} ({}); the file may have been tampered with",
+            self.path, self.reason
+        )
+    }
+}
+
+impl error::Error for IntegrityError {}
+
+// Returned (wrapped in an anyhow::Error) when another process holds a lock on
+// the database that conflicts with the one wanted (see crate::store::lock),
+// and it isn't released in time. The PID is that of the last process to take
+// the lock, if it could be read.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LockedError {
+    pub path: String,
+    pub pid: Option<u32>,
+}
+
+impl LockedError {
+    pub fn new(path: String, pid: Option<u32>) -> LockedError {
+        LockedError { path, pid }
+    }
+}
+
+impl fmt::Display for LockedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.pid {
+            Some(pid) => write!(f, "database is locked by PID {pid} ({})", self.path),
+            None => write!(f, "database is locked by another process ({})", self.path),
+        }
+    }
+}
+
+impl error::Error for LockedError {}
@@ -1,6 +1,7 @@
 pub mod crypto;
 pub mod csv;
 pub mod db;
+pub mod error;
 pub mod records;
 pub mod store;
 pub mod testing;
@@ -0,0 +1,542 @@
+use anyhow::{anyhow, Result};
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+use rucksack_lib::{time, util};
+
+use crate::crypto::legacy::LegacyKey;
+use crate::crypto::{decrypt_with_aad, encrypt_with_aad, index, Cipher, Key};
+use crate::error::IntegrityError;
+
+use super::shared;
+use super::v090;
+pub use super::v090::{
+    default_metadata, default_secrets, key, name_from_key, new_history, new_tag, new_tags,
+    secrets_from_user_pass, types, History, Kind, Metadata, Secrets, Status, Tag, ANY_CATEGORY,
+    DEFAULT_CATEGORY,
+};
+
+// As of this version, record secrets are encrypted with the database's key
+// (see crate::crypto::keyslot) instead of the master password itself, and
+// each encrypted field carries its own random nonce. The cipher used is a
+// property of the database as a whole (see crate::crypto::Cipher).
+//
+// Every encrypted field is bound to the record it belongs to: the record's
+// key (as stored) and the field's name are passed to the cipher as associated
+// data, so the ciphertext of one record (or field) can't be swapped for that
+// of another without it failing to decrypt.
+//
+// Records may also have their metadata sealed (encrypted along with the
+// secrets), in which case the record is stored under an opaque ID instead of
+// its key and carries a blind index (see crate::crypto::index) so that it can
+// still be filtered without being decrypted.
+pub const VERSION: &str = "0.10.0";
+
+// Hashmap - the primary store data structure
+
+pub type HashMap = dashmap::DashMap<String, EncryptedRecord>;
+
+// Note that this only changes the shape of the records; the encrypted bytes
+// still need to be re-keyed with `rekey_encrypted_record_from_v090`, since
+// that requires the legacy key (see crate::crypto::legacy).
+pub fn migrate_hashmap_from_v090(hm_v090: v090::HashMap) -> HashMap {
+    let hm: HashMap = dashmap::DashMap::new();
+    for i in hm_v090.iter() {
+        let r = i.value();
+        let _ = hm.insert(
+            i.key().to_string(),
+            migrate_encrypted_record_from_v090(r.clone()),
+        );
+    }
+    hm
+}
+
+pub fn decode_hashmap(bytes: Vec<u8>, mut version: versions::SemVer) -> Result<HashMap> {
+    log::debug!(
+        "Decoding hashmap from stored bytes (format version {:})...",
+        version
+    );
+    version = shared::trim_version(version);
+    let hm: HashMap = dashmap::DashMap::new();
+    log::trace!("Created hashmap.");
+    let sorted_vec: Vec<(String, EncryptedRecord)>;
+    log::trace!("Created vec for sorted data.");
+    if version < shared::version(VERSION) {
+        // version.
+        log::info!("Attempting to decode hashmap from previous version (0.9.0)");
+        let hm = v090::decode_hashmap(bytes, version)?;
+        return Ok(migrate_hashmap_from_v090(hm));
+    }
+    match bincode::decode_from_slice(bytes.as_ref(), util::bincode_cfg()) {
+        Ok((result, _len)) => {
+            sorted_vec = result;
+            for (key, val) in sorted_vec {
+                if hm.insert(key.clone(), val).is_some() {}
+            }
+            Ok(hm)
+        }
+        Err(e) => {
+            log::info!("couldn't deserialise bincoded hashmap bytes: {:?}", e);
+            Err(anyhow!(e))
+        }
+    }
+}
+
+// Decrypted records
+
+#[derive(Clone, Debug, Eq, PartialEq, Encode, Decode)]
+pub struct DecryptedRecord {
+    pub secrets: Secrets,
+    pub metadata: Metadata,
+    pub history: Vec<History>,
+}
+
+impl DecryptedRecord {
+    pub fn new() -> DecryptedRecord {
+        DecryptedRecord {
+            secrets: default_secrets(),
+            metadata: default_metadata(),
+            history: vec![],
+        }
+    }
+
+    pub fn add_tag(&mut self, value: String) {
+        self.metadata.add_tag(value)
+    }
+
+    pub fn add_tags(&mut self, values: Vec<String>) {
+        self.metadata.add_tags(values)
+    }
+
+    pub fn encrypt(&self, key: &Key, cipher: Cipher) -> EncryptedRecord {
+        self.encrypt_as(self.key(), key, cipher)
+    }
+
+    // Encrypt the record to be stored under the given key (or ID).
+    fn encrypt_as(&self, id: String, key: &Key, cipher: Cipher) -> EncryptedRecord {
+        let encoded_secrets = bincode::encode_to_vec(&self.secrets, util::bincode_cfg()).unwrap();
+        let encrypted_secrets = encrypt_field(encoded_secrets, &id, SECRETS, key, cipher);
+
+        let encoded_history = bincode::encode_to_vec(&self.history, util::bincode_cfg()).unwrap();
+        let encrypted_history = encrypt_field(encoded_history, &id, HISTORY, key, cipher);
+
+        EncryptedRecord {
+            key: id,
+            value: encrypted_secrets,
+            metadata: self.metadata(),
+            history: encrypted_history,
+            sealed: vec![],
+            index: BlindIndex::default(),
+        }
+    }
+
+    pub fn history(&self) -> Vec<History> {
+        self.history.clone()
+    }
+
+    // Like `encrypt`, but the metadata is encrypted too and the record's key
+    // is replaced with an opaque ID.
+    pub fn seal(&self, key: &Key, cipher: Cipher, index_key: &Key) -> EncryptedRecord {
+        let mut er = self.encrypt_as(index::id(index_key, &self.key()), key, cipher);
+        er.metadata = Metadata::default();
+        er.sealed = seal_metadata(&self.metadata, &er.key, key, cipher);
+        er.index = BlindIndex::new(index_key, &self.key(), &self.metadata);
+        er
+    }
+
+    pub fn key(&self) -> String {
+        key(
+            self.metadata.category.as_str(),
+            self.metadata.kind.clone(),
+            self.name_or_user().as_str(),
+            self.metadata.url.as_str(),
+        )
+    }
+
+    pub fn key_with_pass(&self) -> String {
+        format!("{}:{}", self.key(), self.password())
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        self.metadata.clone()
+    }
+
+    pub fn name(&self) -> String {
+        self.metadata.name.clone()
+    }
+
+    pub fn name_or_user(&self) -> String {
+        let mut name = self.name();
+        if name.is_empty() {
+            name = self.user();
+        }
+        name
+    }
+
+    pub fn password(&self) -> String {
+        self.secrets.password.clone()
+    }
+
+    pub fn set_name(&mut self, new_name: String) {
+        self.metadata.updated = time::now();
+        self.metadata.name = new_name;
+    }
+
+    pub fn set_password(&mut self, new_pwd: String) {
+        let now = time::now();
+        self.history
+            .push(new_history(self.secrets.clone(), self.metadata()));
+        self.secrets.password = new_pwd;
+        self.metadata.password_changed = now.clone();
+        self.metadata.updated = now;
+    }
+
+    pub fn set_status(&mut self, new_state: Status) {
+        self.metadata.updated = time::now();
+        self.metadata.state = new_state;
+    }
+
+    pub fn set_kind(&mut self, new_kind: Kind) {
+        self.metadata.updated = time::now();
+        self.metadata.kind = new_kind;
+    }
+
+    pub fn set_url(&mut self, new_url: String) {
+        self.metadata.updated = time::now();
+        self.metadata.url = new_url;
+    }
+
+    pub fn set_user(&mut self, new_user: String) {
+        self.metadata.updated = time::now();
+        self.secrets.user = new_user.clone();
+        self.metadata.name = new_user;
+    }
+
+    pub fn url(&self) -> String {
+        self.metadata.url.clone()
+    }
+
+    pub fn user(&self) -> String {
+        self.secrets.user.clone()
+    }
+}
+
+impl Default for DecryptedRecord {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn migrate_decrypted_record_from_v090(dr: v090::DecryptedRecord) -> DecryptedRecord {
+    DecryptedRecord {
+        secrets: dr.secrets,
+        metadata: dr.metadata,
+        history: dr.history,
+    }
+}
+
+// Blind indexes
+
+// The blinded values of a sealed record's metadata. Terms are the trigrams of
+// the record's key, which is what `list --filter` searches.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Eq, PartialEq, Encode, Decode)]
+pub struct BlindIndex {
+    pub category: u64,
+    pub kind: u64,
+    pub tags: Vec<u64>,
+    pub terms: Vec<u64>,
+}
+
+impl BlindIndex {
+    pub fn new(index_key: &Key, key: &str, metadata: &Metadata) -> BlindIndex {
+        let mut bi = BlindIndex {
+            terms: index::trigrams(key)
+                .iter()
+                .map(|t| index::blind(index_key, "term", t))
+                .collect(),
+            ..Default::default()
+        };
+        bi.update(index_key, metadata);
+        bi
+    }
+
+    pub fn update(&mut self, index_key: &Key, metadata: &Metadata) {
+        self.category = index::blind(index_key, "category", &metadata.category);
+        self.kind = index::blind(index_key, "kind", &metadata.kind.name());
+        self.tags = metadata
+            .tag_values()
+            .iter()
+            .map(|t| index::blind(index_key, "tag", t))
+            .collect();
+    }
+}
+
+fn seal_metadata(metadata: &Metadata, id: &str, key: &Key, cipher: Cipher) -> Vec<u8> {
+    let encoded = bincode::encode_to_vec(metadata, util::bincode_cfg()).unwrap();
+    encrypt_field(encoded, id, METADATA, key, cipher)
+}
+
+// Field encryption
+
+const SECRETS: &str = "secrets";
+const HISTORY: &str = "history";
+const METADATA: &str = "metadata";
+
+fn field_aad(id: &str, field: &str) -> Vec<u8> {
+    format!("{id}\0{field}").into_bytes()
+}
+
+fn encrypt_field(data: Vec<u8>, id: &str, field: &str, key: &Key, cipher: Cipher) -> Vec<u8> {
+    encrypt_with_aad(data, key, cipher, &field_aad(id, field))
+}
+
+// The database's key has already been checked by the time records are
+// decrypted, so a failure here means the ciphertext doesn't belong where it
+// was found.
+fn decrypt_field(
+    data: Vec<u8>,
+    id: &str,
+    field: &str,
+    key: &Key,
+    cipher: Cipher,
+) -> Result<Vec<u8>> {
+    decrypt_with_aad(data, key, cipher, &field_aad(id, field)).map_err(|e| {
+        log::error!("Could not decrypt {field} of record {id} ({e})");
+        anyhow!(IntegrityError::new(
+            format!("record {id}"),
+            &format!("{field} could not be decrypted for this record")
+        ))
+    })
+}
+
+// Encrypted records
+
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq, Encode, Decode)]
+pub struct EncryptedRecord {
+    pub key: String,
+    pub value: Vec<u8>,
+    pub metadata: Metadata,
+    pub history: Vec<u8>,
+    // Only set for sealed records, in which case `metadata` is empty
+    pub sealed: Vec<u8>,
+    pub index: BlindIndex,
+}
+
+impl EncryptedRecord {
+    pub fn add_tag(&mut self, value: String) {
+        self.metadata.add_tag(value)
+    }
+
+    pub fn add_tags(&mut self, values: Vec<String>) {
+        self.metadata.add_tags(values)
+    }
+
+    pub fn history(&self) -> Vec<u8> {
+        self.history.clone()
+    }
+
+    pub fn key(&self) -> String {
+        self.key.clone()
+    }
+
+    pub fn value(&self) -> Vec<u8> {
+        self.value.clone()
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        self.metadata.clone()
+    }
+
+    pub fn is_sealed(&self) -> bool {
+        !self.sealed.is_empty()
+    }
+
+    // Get the record's metadata without decrypting its secrets.
+    pub fn unseal_metadata(&self, key: &Key, cipher: Cipher) -> Result<Metadata> {
+        if !self.is_sealed() {
+            return Ok(self.metadata());
+        }
+        let decrypted = decrypt_field(self.sealed.clone(), &self.key, METADATA, key, cipher)?;
+        let (metadata, _len) = bincode::decode_from_slice(&decrypted[..], util::bincode_cfg())?;
+        Ok(metadata)
+    }
+
+    // Replace the metadata of a sealed record. The search terms are left as
+    // they are, since the record's key (and thus its ID) doesn't change.
+    pub fn reseal_metadata(
+        &mut self,
+        metadata: Metadata,
+        key: &Key,
+        cipher: Cipher,
+        index_key: &Key,
+    ) {
+        self.sealed = seal_metadata(&metadata, &self.key, key, cipher);
+        self.index.update(index_key, &metadata);
+    }
+
+    pub fn decrypt(&self, key: &Key, cipher: Cipher) -> Result<DecryptedRecord> {
+        let decrypted_secrets = decrypt_field(self.value(), &self.key, SECRETS, key, cipher)?;
+        let (decoded_secrets, _len) =
+            bincode::decode_from_slice(&decrypted_secrets[..], util::bincode_cfg()).unwrap();
+
+        let decrypted_history = decrypt_field(self.history(), &self.key, HISTORY, key, cipher)?;
+        let (decoded_history, _len) =
+            bincode::decode_from_slice(&decrypted_history[..], util::bincode_cfg()).unwrap();
+
+        Ok(DecryptedRecord {
+            secrets: decoded_secrets,
+            metadata: self.unseal_metadata(key, cipher)?,
+            history: decoded_history,
+        })
+    }
+}
+
+pub fn migrate_encrypted_record_from_v090(er: v090::EncryptedRecord) -> EncryptedRecord {
+    EncryptedRecord {
+        key: er.key(),
+        value: er.value(),
+        metadata: er.metadata(),
+        history: er.history(),
+        sealed: vec![],
+        index: BlindIndex::default(),
+    }
+}
+
+// Records stored before 0.10.0 were encrypted with the zero-padded master
+// password; this decrypts them the old way (with the legacy key it made) and re-encrypts them with the
+// database's key (binding them to their keys as it goes, as with any other
+// 0.10.0 record). Records migrated from 0.8.0 have no history bytes at all.
+pub fn rekey_encrypted_record_from_v090(
+    er: EncryptedRecord,
+    legacy_key: &LegacyKey,
+    key: &Key,
+    cipher: Cipher,
+) -> Result<EncryptedRecord> {
+    let secrets = legacy_key.decrypt(&er.value())?;
+    let history = if er.history.is_empty() {
+        bincode::encode_to_vec(Vec::<History>::new(), util::bincode_cfg())?
+    } else {
+        legacy_key.decrypt(&er.history())?
+    };
+    Ok(EncryptedRecord {
+        key: er.key(),
+        value: encrypt_field(secrets, &er.key, SECRETS, key, cipher),
+        metadata: er.metadata(),
+        history: encrypt_field(history, &er.key, HISTORY, key, cipher),
+        sealed: vec![],
+        index: BlindIndex::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::legacy::LegacyKey;
+    use crate::crypto::{index, Cipher};
+    use crate::error::IntegrityError;
+    use crate::testing;
+    use rucksack_lib::time;
+
+    #[test]
+    fn password_records() {
+        let key = testing::data::store_key();
+        let dpr = testing::data::plaintext_record_v0100();
+        assert_eq!(
+            format!("{}", dpr.secrets),
+            "Creds{user: alice@site.com, password: *****}"
+        );
+        let epr = dpr.encrypt(&key, Cipher::default());
+        // The nonce adds 12 bytes to the ciphertext
+        assert_eq!(130, epr.value.len());
+        // ... and it's different every time
+        assert_ne!(epr.value, dpr.encrypt(&key, Cipher::default()).value);
+        let re_dpr = epr.decrypt(&key, Cipher::default()).unwrap();
+        assert_eq!(re_dpr.secrets.password, "6 s3kr1t");
+        assert_eq!(re_dpr.history.len(), 2);
+    }
+
+    #[test]
+    fn swapped_ciphertexts() {
+        let key = testing::data::store_key();
+        let cipher = Cipher::default();
+        let is_integrity_err = |r: anyhow::Result<super::DecryptedRecord>| match r {
+            Ok(_) => false,
+            Err(e) => e.downcast_ref::<IntegrityError>().is_some(),
+        };
+        let dpr = testing::data::plaintext_record_v0100();
+        let mut other = dpr.clone();
+        other.set_url("https://other.com/".to_string());
+        let epr = dpr.encrypt(&key, cipher);
+        let other_epr = other.encrypt(&key, cipher);
+
+        // Secrets moved to another record
+        let mut swapped = other_epr.clone();
+        swapped.value = epr.value.clone();
+        assert!(is_integrity_err(swapped.decrypt(&key, cipher)));
+        // History moved to another record
+        let mut swapped = other_epr.clone();
+        swapped.history = epr.history.clone();
+        assert!(is_integrity_err(swapped.decrypt(&key, cipher)));
+        // Secrets and history swapped within a record
+        let mut swapped = epr.clone();
+        swapped.value = epr.history.clone();
+        assert!(is_integrity_err(swapped.decrypt(&key, cipher)));
+        // Re-keying a record means changing what it's bound to
+        let mut renamed = epr.clone();
+        renamed.key = other_epr.key();
+        assert!(is_integrity_err(renamed.decrypt(&key, cipher)));
+        assert!(epr.decrypt(&key, cipher).is_ok());
+    }
+
+    #[test]
+    fn sealed_records() {
+        let key = testing::data::store_key();
+        let index_key = index::derive_key(&key);
+        let cipher = Cipher::default();
+        let dpr = testing::data::plaintext_record_v0100();
+        let mut spr = dpr.seal(&key, cipher, &index_key);
+        assert!(spr.is_sealed());
+        assert_ne!(spr.key, dpr.key());
+        assert!(!spr.key.contains("site"));
+        assert_eq!(spr.metadata, super::Metadata::default());
+        assert_eq!(spr.unseal_metadata(&key, cipher).unwrap(), dpr.metadata);
+        assert_eq!(spr.decrypt(&key, cipher).unwrap(), dpr);
+        let term = index::blind(&index_key, "term", "sit");
+        assert!(spr.index.terms.contains(&term));
+
+        let mut md = dpr.metadata();
+        md.category = "work".to_string();
+        spr.reseal_metadata(md.clone(), &key, cipher, &index_key);
+        assert_eq!(spr.decrypt(&key, cipher).unwrap().metadata, md);
+        assert_eq!(
+            spr.index.category,
+            index::blind(&index_key, "category", "work")
+        );
+        assert!(spr.index.terms.contains(&term));
+    }
+
+    #[test]
+    fn rekey_from_v090() {
+        let pwd = testing::data::store_pwd();
+        let key = testing::data::store_key();
+        let salt = time::now();
+        let cipher = Cipher::XChaCha20Poly1305;
+        let old = testing::data::plaintext_record_v090().encrypt(pwd.clone(), salt.clone());
+        let migrated = super::migrate_encrypted_record_from_v090(old.clone());
+        // The shape changed, but the secrets are still encrypted the old way
+        assert!(migrated.decrypt(&key, cipher).is_err());
+        let legacy_key = LegacyKey::new(&pwd, &salt);
+        let rekeyed =
+            super::rekey_encrypted_record_from_v090(migrated, &legacy_key, &key, cipher).unwrap();
+        assert_ne!(rekeyed.value, old.value);
+        let dpr = rekeyed.decrypt(&key, cipher).unwrap();
+        assert_eq!(dpr.secrets.password, "6 s3kr1t");
+        assert_eq!(dpr.history.len(), 2);
+
+        // Records that came up through 0.8.0 don't have any history bytes
+        let mut no_history = super::migrate_encrypted_record_from_v090(old);
+        no_history.history = vec![];
+        let rekeyed =
+            super::rekey_encrypted_record_from_v090(no_history, &legacy_key, &key, cipher).unwrap();
+        assert_eq!(rekeyed.decrypt(&key, cipher).unwrap().history.len(), 0);
+    }
+}
@@ -5,7 +5,7 @@ use serde::{Deserialize, Serialize};
 
 use rucksack_lib::util;
 
-use crate::crypto::{decrypt, encrypt};
+use crate::crypto::legacy::{decrypt, encrypt};
 
 pub const VERSION: &str = "0.2.0";
 
@@ -93,7 +93,7 @@ impl std::fmt::Debug for Creds {
 impl DecryptedRecord {
     pub fn encrypt(&self, store_pwd: String) -> EncryptedRecord {
         let encoded = bincode::encode_to_vec(&self.value, util::bincode_cfg()).unwrap();
-        let encrypted = encrypt(encoded, store_pwd, self.metadata.updated.clone());
+        let encrypted = encrypt(encoded, &store_pwd, &self.metadata.updated);
 
         EncryptedRecord {
             key: self.key.clone(),
@@ -105,8 +105,7 @@ impl DecryptedRecord {
 
 impl EncryptedRecord {
     pub fn decrypt(&self, store_pwd: String) -> DecryptedRecord {
-        let decrypted =
-            decrypt(self.value.clone(), store_pwd, self.metadata.updated.clone()).unwrap();
+        let decrypted = decrypt(self.value.clone(), &store_pwd, &self.metadata.updated).unwrap();
         let (decoded, _len) =
             bincode::decode_from_slice(&decrypted[..], util::bincode_cfg()).unwrap();
 
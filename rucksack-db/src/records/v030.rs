@@ -4,7 +4,7 @@ use serde::{Deserialize, Serialize};
 
 use rucksack_lib::util;
 
-use crate::crypto::{decrypt, encrypt};
+use crate::crypto::legacy::{decrypt, encrypt};
 
 use super::v020;
 pub use super::v020::{Creds, Kind};
@@ -87,7 +87,7 @@ impl DecryptedRecord {
 
     pub fn encrypt(&self, store_pwd: String) -> EncryptedRecord {
         let encoded = bincode::encode_to_vec(&self.creds, util::bincode_cfg()).unwrap();
-        let encrypted = encrypt(encoded, store_pwd, self.metadata().updated);
+        let encrypted = encrypt(encoded, &store_pwd, &self.metadata().updated);
 
         EncryptedRecord {
             key: self.key(),
@@ -114,7 +114,7 @@ impl EncryptedRecord {
     }
 
     pub fn decrypt(&self, store_pwd: String) -> Result<DecryptedRecord> {
-        let decrypted = decrypt(self.value.clone(), store_pwd, self.metadata().updated)?;
+        let decrypted = decrypt(self.value.clone(), &store_pwd, &self.metadata().updated)?;
         let (decoded, _len) =
             bincode::decode_from_slice(&decrypted[..], util::bincode_cfg()).unwrap();
 
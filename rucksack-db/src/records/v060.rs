@@ -4,7 +4,7 @@ use serde::{Deserialize, Serialize};
 
 use rucksack_lib::util;
 
-use crate::crypto::{decrypt, encrypt};
+use crate::crypto::legacy::{decrypt, encrypt};
 
 use super::shared;
 use super::v050;
@@ -95,7 +95,7 @@ impl DecryptedRecord {
 
     pub fn encrypt(&self, store_pwd: String, salt: String) -> EncryptedRecord {
         let encoded = bincode::encode_to_vec(&self.creds, util::bincode_cfg()).unwrap();
-        let encrypted = encrypt(encoded, store_pwd, salt);
+        let encrypted = encrypt(encoded, &store_pwd, &salt);
 
         EncryptedRecord {
             key: self.key(),
@@ -134,7 +134,7 @@ impl EncryptedRecord {
     }
 
     pub fn decrypt(&self, store_pwd: String, salt: String) -> Result<DecryptedRecord> {
-        let decrypted = decrypt(self.value.clone(), store_pwd, salt)?;
+        let decrypted = decrypt(self.value.clone(), &store_pwd, &salt)?;
         let (decoded, _len) =
             bincode::decode_from_slice(&decrypted[..], util::bincode_cfg()).unwrap();
 
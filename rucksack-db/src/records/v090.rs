@@ -4,7 +4,7 @@ use serde::{Deserialize, Serialize};
 
 use rucksack_lib::{time, util};
 
-use crate::crypto::{decrypt, encrypt};
+use crate::crypto::legacy::{decrypt, encrypt};
 
 use super::shared;
 use super::v080;
@@ -181,10 +181,10 @@ impl DecryptedRecord {
 
     pub fn encrypt(&self, store_pwd: String, salt: String) -> EncryptedRecord {
         let encoded_secrets = bincode::encode_to_vec(&self.secrets, util::bincode_cfg()).unwrap();
-        let encrypted_secrets = encrypt(encoded_secrets, store_pwd.clone(), salt.clone());
+        let encrypted_secrets = encrypt(encoded_secrets, &store_pwd, &salt);
 
         let encoded_history = bincode::encode_to_vec(&self.history, util::bincode_cfg()).unwrap();
-        let encrypted_history = encrypt(encoded_history, store_pwd, salt);
+        let encrypted_history = encrypt(encoded_history, &store_pwd, &salt);
 
         EncryptedRecord {
             key: self.key(),
@@ -325,11 +325,11 @@ impl EncryptedRecord {
     }
 
     pub fn decrypt(&self, store_pwd: String, salt: String) -> Result<DecryptedRecord> {
-        let decrypted_secrets = decrypt(self.value.clone(), store_pwd.clone(), salt.clone())?;
+        let decrypted_secrets = decrypt(self.value.clone(), &store_pwd, &salt)?;
         let (decoded_secrets, _len) =
             bincode::decode_from_slice(&decrypted_secrets[..], util::bincode_cfg()).unwrap();
 
-        let decrypted_history = decrypt(self.history.clone(), store_pwd, salt)?;
+        let decrypted_history = decrypt(self.history.clone(), &store_pwd, &salt)?;
         let (decoded_history, _len) =
             bincode::decode_from_slice(&decrypted_history[..], util::bincode_cfg()).unwrap();
 
@@ -1,45 +1,135 @@
+// Backups of a database file are copies of it in a backup directory, each
+// named for the file and the time it was made (see `backup_name`), with a
+// manifest next to it (`<name>.toml`) that describes it (see BackupInfo).
+// Backups made before there were manifests are described as well as they can
+// be from their names and files.
+//
 use std::{fs, path};
 
 use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use rucksack_lib::{file, time};
 
-pub fn copy(src_file: String, dest_dir: String, version: String) -> Result<String> {
+const MANIFEST_EXTENSION: &str = "toml";
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct BackupInfo {
+    pub name: String,
+    pub created: DateTime<Local>,
+    // The schema version of the database that was backed up
+    pub version: String,
+    // In bytes
+    pub size: u64,
+    // The number of records in the backup, if it's known
+    pub records: Option<usize>,
+    // The SHA-256 of the backup (as hex), or empty if it's not known
+    pub checksum: String,
+}
+
+impl BackupInfo {
+    pub fn new(
+        name: String,
+        created: DateTime<Local>,
+        version: String,
+        bytes: &[u8],
+        records: Option<usize>,
+    ) -> BackupInfo {
+        BackupInfo {
+            name,
+            created,
+            version,
+            size: bytes.len() as u64,
+            records,
+            checksum: checksum(bytes),
+        }
+    }
+
+    // Whether the backup was made in the given range; either end may be left
+    // open, and the end isn't in it.
+    pub fn made_between(
+        &self,
+        since: Option<DateTime<Local>>,
+        until: Option<DateTime<Local>>,
+    ) -> bool {
+        since.is_none_or(|s| self.created >= s) && until.is_none_or(|u| self.created < u)
+    }
+}
+
+pub fn backup_name(src_file: String, version: String, created: DateTime<Local>) -> String {
+    format!("{src_file}-{}-v{version}", time::format_datetime(created))
+}
+
+pub fn checksum(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+pub fn copy(
+    src_file: String,
+    dest_dir: String,
+    version: String,
+    records: Option<usize>,
+) -> Result<BackupInfo> {
     let file_path = file::abs_path(src_file.clone())?;
     let mut bu_path = file::abs_path(dest_dir)?;
     file::create_dirs(bu_path.clone())?;
-    bu_path.push(backup_name(
+    let created = Local::now();
+    let name = backup_name(
         file_path.file_name().unwrap().to_str().unwrap().to_string(),
-        version,
-    ));
-    match fs::copy(src_file.clone(), bu_path.clone()) {
-        Ok(_) => Ok(bu_path.display().to_string()),
-        Err(e) => {
-            let msg = "Could not copy file";
-            log::error!("{msg} {src_file:?} ({e:})");
-            Err(anyhow!("{msg} {src_file:?} ({e:})"))
-        }
-    }
+        version.clone(),
+        created,
+    );
+    bu_path.push(&name);
+    let backup_file = bu_path.display().to_string();
+    file::copy_atomic(src_file, backup_file.clone())?;
+    let info = BackupInfo::new(name, created, version, &file::read(backup_file)?, records);
+    let manifest = match toml::to_string(&info) {
+        Ok(x) => x,
+        Err(e) => return Err(anyhow!("couldn't encode backup manifest ({e})")),
+    };
+    file::write_atomic(manifest.as_bytes(), manifest_path(&bu_path))?;
+    Ok(info)
 }
 
-pub fn backup_name(src_file: String, version: String) -> String {
-    format!("{src_file}-{}-v{version}", time::simple_timestamp())
+pub fn delete(backup_dir: String, name: String) -> Result<()> {
+    let backup_path = path::Path::new(&backup_dir).join(&name);
+    if !backup_path.is_file() {
+        return Err(anyhow!("backup file '{name}' does not exist"));
+    }
+    file::delete(backup_path.clone())?;
+    let manifest = path::PathBuf::from(manifest_path(&backup_path));
+    if manifest.exists() {
+        file::delete(manifest)?;
+    }
+    Ok(())
 }
 
-pub fn latest(backup_dir: String) -> Result<file::Data> {
+pub fn latest(backup_dir: String) -> Result<BackupInfo> {
     match list(backup_dir) {
         Ok(all) => match all.first() {
-            Some(data) => Ok(data.clone()),
+            Some(info) => Ok(info.clone()),
             None => Err(anyhow!("no backup files found")),
         },
         Err(e) => Err(anyhow!(e)),
     }
 }
 
-pub fn list(backup_dir: String) -> Result<file::Listing> {
-    let mut backups = file::files(backup_dir)?;
-    backups.sort();
-    backups.reverse();
+// The backups in a directory, newest first
+pub fn list(backup_dir: String) -> Result<Vec<BackupInfo>> {
+    let mut backups = Vec::new();
+    for (name, _, _) in file::files(backup_dir.clone())? {
+        // Temporary files (see file::write_atomic) start with a dot
+        if name.starts_with('.') || name.ends_with(&format!(".{MANIFEST_EXTENSION}")) {
+            continue;
+        }
+        backups.push(info(path::Path::new(&backup_dir).join(name))?);
+    }
+    sort(&mut backups);
     Ok(backups)
 }
 
@@ -55,14 +145,102 @@ pub fn restore(
         old_path.display(),
         dest_path.display()
     );
-    let old_file = old_path.display().to_string();
-    match fs::copy(old_path, dest_path) {
-        Ok(_) => (),
-        Err(e) => {
-            let msg = "Could not copy file";
-            log::error!("{msg} {old_file:?} ({e:})");
-            return Err(anyhow!("{msg} {old_file:?} ({e:})"));
+    file::copy_atomic(
+        old_path.display().to_string(),
+        dest_path.display().to_string(),
+    )
+}
+
+// Newest first
+pub fn sort(backups: &mut [BackupInfo]) {
+    backups.sort_by(|a, b| b.created.cmp(&a.created).then(b.name.cmp(&a.name)));
+}
+
+// A backup as its manifest describes it; or, for one that hasn't got one, as
+// its name and file do.
+fn info(backup_path: path::PathBuf) -> Result<BackupInfo> {
+    let manifest = manifest_path(&backup_path);
+    if path::Path::new(&manifest).exists() {
+        let bytes = file::read(manifest.clone())?;
+        match toml::from_str(&String::from_utf8_lossy(&bytes)) {
+            Ok(info) => return Ok(info),
+            Err(e) => log::warn!("Could not read backup manifest {manifest} ({e}); ignoring it"),
         }
     }
-    Ok(())
+    let name = backup_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let metadata = fs::metadata(&backup_path)?;
+    let (created, version) = match parse_name(&name) {
+        Some(parsed) => parsed,
+        None => (metadata.modified()?.into(), String::new()),
+    };
+    Ok(BackupInfo {
+        name,
+        created,
+        version,
+        size: metadata.len(),
+        records: None,
+        checksum: String::new(),
+    })
+}
+
+fn manifest_path(backup_path: &path::Path) -> String {
+    format!("{}.{MANIFEST_EXTENSION}", backup_path.display())
+}
+
+// The time and schema version in a name made by `backup_name`
+fn parse_name(name: &str) -> Option<(DateTime<Local>, String)> {
+    let (rest, version) = name.rsplit_once("-v")?;
+    let stamp = rest.get(rest.len().checked_sub(15)?..)?;
+    Some((time::parse_datetime(stamp)?, version.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Local};
+
+    use crate::testing;
+
+    #[test]
+    fn copy_and_list() {
+        let mut db_handler = testing::db::new();
+        assert!(db_handler.setup().is_ok());
+        let db_file = db_handler.file_name().unwrap();
+        let backups = db_handler.backups_path().unwrap().display().to_string();
+        std::fs::write(&db_file, b"secrets").unwrap();
+        let info = super::copy(
+            db_file.clone(),
+            backups.clone(),
+            "0.10.0".to_string(),
+            Some(3),
+        )
+        .unwrap();
+        assert_eq!(info.size, 7);
+        assert_eq!(info.checksum, super::checksum(b"secrets"));
+        assert_eq!(super::latest(backups.clone()).unwrap(), info);
+
+        // A backup from before there were manifests
+        let old = super::backup_name(
+            "secrets.db".to_string(),
+            "0.9.0".to_string(),
+            Local::now() - Duration::days(2),
+        );
+        std::fs::write(db_handler.backups_path().unwrap().join(&old), b"old").unwrap();
+        let listed = super::list(backups.clone()).unwrap();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0], info);
+        assert_eq!(listed[1].name, old);
+        assert_eq!(listed[1].version, "0.9.0");
+        assert_eq!(listed[1].records, None);
+        let yesterday = Some(Local::now() - Duration::days(1));
+        assert!(!listed[1].made_between(yesterday, None));
+        assert!(listed[1].made_between(None, yesterday));
+
+        assert!(super::delete(backups.clone(), info.name.clone()).is_ok());
+        assert!(super::delete(backups.clone(), info.name).is_err());
+        assert_eq!(super::list(backups).unwrap().len(), 1);
+        assert!(db_handler.teardown().is_ok());
+    }
 }
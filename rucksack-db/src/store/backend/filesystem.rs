@@ -1,10 +1,11 @@
 use anyhow::Result;
+use secrecy::SecretString;
 
+use crate::crypto::Key;
 use crate::db::encrypted::EncryptedDB;
+use crate::db::header::Header;
 use crate::store::manager::StoreManager;
 
-use super::backup;
-
 #[derive(Clone, Default)]
 pub struct FileSystemBackend {}
 
@@ -15,11 +16,25 @@ impl FileSystemBackend {
 }
 
 impl StoreManager for FileSystemBackend {
-    fn backup(&self, src_file: String, dest_dir: String, version: String) -> Result<String> {
-        backup::copy(src_file, dest_dir, version)
+    fn name(&self) -> &'static str {
+        "filesystem"
+    }
+
+    fn describe(&self, path: &str) -> Result<Header> {
+        Header::read(path.to_string())
+    }
+
+    fn read(
+        &self,
+        path: String,
+        pwd: &SecretString,
+        salt: &SecretString,
+        key_file: Option<Key>,
+    ) -> Result<EncryptedDB> {
+        EncryptedDB::from_file(path, pwd, salt, key_file)
     }
 
-    fn read(&self, path: String, pwd: String, salt: String) -> Result<EncryptedDB> {
-        EncryptedDB::from_file(path, pwd, salt)
+    fn read_with_key(&self, path: String, key: Key) -> Result<EncryptedDB> {
+        EncryptedDB::from_file_with_key(path, key)
     }
 }
@@ -0,0 +1,207 @@
+// Keeps databases (and their backups) in memory, as the same encrypted bytes
+// that would otherwise be written to a file, and never touches the disk. This
+// is for tests and for throwaway vaults: what's stored lasts only as long as
+// the process does. As with files, databases are found by their path, so one
+// can be closed and opened again (or backed up and restored) by any DB in the
+// same process; backup "directories" are just names, too.
+//
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::Local;
+use secrecy::SecretString;
+
+use crate::crypto::legacy::LegacyKey;
+use crate::crypto::Key;
+use crate::db::encrypted::EncryptedDB;
+use crate::db::header::Header;
+use crate::store::lock::{Lock, LockMode};
+use crate::store::manager::StoreManager;
+
+use super::backup::{self, BackupInfo};
+
+#[derive(Default)]
+struct Store {
+    dbs: HashMap<String, Vec<u8>>,
+    // Journals, by database path
+    journals: HashMap<String, Vec<u8>>,
+    // Backups, by directory
+    backups: HashMap<String, Vec<Backup>>,
+}
+
+struct Backup {
+    info: BackupInfo,
+    bytes: Vec<u8>,
+}
+
+static STORE: OnceLock<Mutex<Store>> = OnceLock::new();
+
+#[derive(Clone, Default)]
+pub struct MemoryBackend {}
+
+impl MemoryBackend {
+    pub fn new() -> MemoryBackend {
+        MemoryBackend {}
+    }
+
+    fn store(&self) -> MutexGuard<'_, Store> {
+        let store = STORE.get_or_init(|| Mutex::new(Store::default()));
+        // Nothing is left half-changed if a holder of the lock panics
+        store.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn bytes(&self, path: &str) -> Result<Vec<u8>> {
+        match self.store().dbs.get(path) {
+            Some(bytes) => Ok(bytes.clone()),
+            None => Err(anyhow!("no database found at {path}")),
+        }
+    }
+
+    fn backup_bytes(&self, backup_dir: &str, name: &str) -> Result<Vec<u8>> {
+        match self
+            .store()
+            .backups
+            .get(backup_dir)
+            .and_then(|backups| backups.iter().find(|b| b.info.name == name))
+        {
+            Some(backup) => Ok(backup.bytes.clone()),
+            None => Err(anyhow!("backup file '{name}' does not exist")),
+        }
+    }
+}
+
+impl StoreManager for MemoryBackend {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn locate(&self, path: &str) -> Result<(String, bool)> {
+        Ok((path.to_string(), self.store().dbs.contains_key(path)))
+    }
+
+    // Only this process can get at the store, and it's behind a mutex
+    fn lock(&self, _path: &str, _mode: LockMode, _wait: Duration) -> Result<Option<Lock>> {
+        Ok(None)
+    }
+
+    fn describe(&self, path: &str) -> Result<Header> {
+        Ok(Header::decode(&self.bytes(path)?)?.0)
+    }
+
+    fn read(
+        &self,
+        path: String,
+        pwd: &SecretString,
+        salt: &SecretString,
+        key_file: Option<Key>,
+    ) -> Result<EncryptedDB> {
+        EncryptedDB::from_bytes(self.bytes(&path)?, path, pwd, salt, key_file)
+    }
+
+    fn read_with_key(&self, path: String, key: Key) -> Result<EncryptedDB> {
+        EncryptedDB::from_bytes_with_key(self.bytes(&path)?, path, key)
+    }
+
+    fn write(&self, enc_db: &EncryptedDB) -> Result<()> {
+        log::debug!("Keeping encrypted DB in memory ...");
+        self.store().dbs.insert(enc_db.path(), enc_db.bytes());
+        Ok(())
+    }
+
+    fn append_journal(&self, path: &str, bytes: &[u8]) -> Result<()> {
+        let mut store = self.store();
+        store
+            .journals
+            .entry(path.to_string())
+            .or_default()
+            .extend(bytes);
+        Ok(())
+    }
+
+    fn read_journal(&self, path: &str) -> Result<Vec<u8>> {
+        Ok(self.store().journals.get(path).cloned().unwrap_or_default())
+    }
+
+    fn clear_journal(&self, path: &str) -> Result<()> {
+        self.store().journals.remove(path);
+        Ok(())
+    }
+
+    fn backup(
+        &self,
+        src_file: String,
+        dest_dir: String,
+        version: String,
+        records: Option<usize>,
+    ) -> Result<BackupInfo> {
+        let bytes = self.bytes(&src_file)?;
+        let src_name = match Path::new(&src_file).file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => src_file.clone(),
+        };
+        let created = Local::now();
+        let name = backup::backup_name(src_name, version.clone(), created);
+        let info = BackupInfo::new(name, created, version, &bytes, records);
+        let mut store = self.store();
+        let backups = store.backups.entry(dest_dir).or_default();
+        // As with files, a backup made in the same second replaces the last
+        backups.retain(|b| b.info.name != info.name);
+        backups.push(Backup {
+            info: info.clone(),
+            bytes,
+        });
+        Ok(info)
+    }
+
+    fn list_backups(&self, backup_dir: &str) -> Result<Vec<BackupInfo>> {
+        let mut backups: Vec<BackupInfo> = match self.store().backups.get(backup_dir) {
+            Some(backups) => backups.iter().map(|b| b.info.clone()).collect(),
+            None => Vec::new(),
+        };
+        backup::sort(&mut backups);
+        Ok(backups)
+    }
+
+    fn latest_backup(&self, backup_dir: &str) -> Result<BackupInfo> {
+        match self.list_backups(backup_dir)?.first() {
+            Some(info) => Ok(info.clone()),
+            None => Err(anyhow!("no backup files found")),
+        }
+    }
+
+    fn read_backup(&self, backup_dir: &str, name: &str, key: Key) -> Result<EncryptedDB> {
+        let bytes = self.backup_bytes(backup_dir, name)?;
+        EncryptedDB::from_bytes_with_key(bytes, name.to_string(), key)
+    }
+
+    fn read_legacy_backup(
+        &self,
+        backup_dir: &str,
+        name: &str,
+        key: &LegacyKey,
+    ) -> Result<EncryptedDB> {
+        let bytes = self.backup_bytes(backup_dir, name)?;
+        EncryptedDB::from_legacy(bytes, name.to_string(), key)
+    }
+
+    fn restore_backup(&self, backup_dir: &str, name: &str, path: &str) -> Result<()> {
+        log::debug!("Restoring backup {name} in memory ...");
+        let bytes = self.backup_bytes(backup_dir, name)?;
+        self.store().dbs.insert(path.to_string(), bytes);
+        Ok(())
+    }
+
+    fn delete_backup(&self, backup_dir: &str, name: &str) -> Result<()> {
+        let mut store = self.store();
+        let backups = store.backups.entry(backup_dir.to_string()).or_default();
+        let count = backups.len();
+        backups.retain(|b| b.info.name != name);
+        if backups.len() == count {
+            return Err(anyhow!("backup file '{name}' does not exist"));
+        }
+        Ok(())
+    }
+}
@@ -1,12 +1,22 @@
 pub mod backup;
 #[cfg(feature = "filesystem")]
 pub mod filesystem;
+pub mod memory;
 #[cfg(feature = "persy")]
 pub mod persy;
 #[cfg(feature = "redb")]
 pub mod redb;
+#[cfg(any(feature = "persy", feature = "redb", feature = "sqlite"))]
+pub mod rows;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 
 #[cfg(feature = "filesystem")]
 pub use crate::store::backend::filesystem::FileSystemBackend;
+pub use crate::store::backend::memory::MemoryBackend;
+#[cfg(feature = "persy")]
+pub use crate::store::backend::persy::PersyBackend;
 #[cfg(feature = "redb")]
 pub use crate::store::backend::redb::ReDBBackend;
+#[cfg(feature = "sqlite")]
+pub use crate::store::backend::sqlite::SqliteBackend;
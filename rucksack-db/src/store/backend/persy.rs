@@ -0,0 +1,406 @@
+// A persy database holds the same header (and so the same key slots) as a
+// database file, and keeps each encrypted record in the `records` segment,
+// found by the record's key (or its opaque ID, if metadata is sealed) in the
+// `ids` index. Records are also indexed by category and kind: in plaintext,
+// or blinded (see crate::crypto::index) if the record is sealed, so that
+// `query_records` can narrow down which records to check.
+//
+// As with redb, changes to records are written as they're made, each in its
+// own transaction, and the header and rows are signed (see super::rows). The
+// category and kind indexes aren't covered by the signatures, but they're
+// only ever used to narrow down which records to check; the records
+// themselves are.
+//
+use std::collections::BTreeSet;
+
+use anyhow::{anyhow, Result};
+use persy::{ByteVec, Config, IndexType, Persy, PersyId, Transaction, ValueMode};
+use secrecy::SecretString;
+
+use crate::crypto::{index, mac, Key};
+use crate::db::encrypted::EncryptedDB;
+use crate::db::header::Header;
+use crate::db::query::Query;
+use crate::records::{EncryptedRecord, HashMap};
+use crate::store::manager::StoreManager;
+
+use super::rows;
+
+const META: &str = "meta";
+const RECORDS: &str = "records";
+const IDS: &str = "ids";
+const CATEGORY: &str = "category";
+const KIND: &str = "kind";
+const BLIND_CATEGORY: &str = "blind_category";
+const BLIND_KIND: &str = "blind_kind";
+
+const HEADER: &str = "header";
+const HEADER_MAC: &str = "header-mac";
+
+#[derive(Clone, Default)]
+pub struct PersyBackend {}
+
+impl PersyBackend {
+    pub fn new() -> PersyBackend {
+        PersyBackend {}
+    }
+}
+
+impl StoreManager for PersyBackend {
+    fn name(&self) -> &'static str {
+        "persy"
+    }
+
+    fn describe(&self, path: &str) -> Result<Header> {
+        read_header(&open(path)?, path)
+    }
+
+    fn read(
+        &self,
+        path: String,
+        pwd: &SecretString,
+        _salt: &SecretString,
+        key_file: Option<Key>,
+    ) -> Result<EncryptedDB> {
+        let db = open(&path)?;
+        let header = read_header(&db, &path)?;
+        let (key, slot) = rows::unlock(&header, pwd, key_file.as_ref())?;
+        read_records(&db, path, header, key, slot)
+    }
+
+    fn read_with_key(&self, path: String, key: Key) -> Result<EncryptedDB> {
+        let db = open(&path)?;
+        let header = read_header(&db, &path)?;
+        read_records(&db, path, header, key, String::new())
+    }
+
+    fn per_record(&self) -> bool {
+        true
+    }
+
+    fn put_record(&self, path: &str, key: &Key, id: &str, record: &EncryptedRecord) -> Result<()> {
+        log::debug!("Writing record row ...");
+        let db = create(path)?;
+        let mut txn = db.begin()?;
+        remove(&mut txn, path, id)?;
+        insert(&mut txn, &mac::derive_key(key), id, record)?;
+        txn.prepare()?.commit()?;
+        Ok(())
+    }
+
+    fn delete_record(&self, path: &str, id: &str) -> Result<()> {
+        log::debug!("Deleting record row ...");
+        let db = create(path)?;
+        let mut txn = db.begin()?;
+        remove(&mut txn, path, id)?;
+        txn.prepare()?.commit()?;
+        Ok(())
+    }
+
+    // Replace everything: the header and all the rows, in one transaction.
+    fn write_records(
+        &self,
+        path: &str,
+        header: &Header,
+        key: &Key,
+        records: &HashMap,
+    ) -> Result<()> {
+        log::debug!("Writing header and {} record rows ...", records.len());
+        let mac_key = mac::derive_key(key);
+        let header_bytes = header.encode()?;
+        let db = create(path)?;
+        let mut txn = db.begin()?;
+        let ids: Vec<String> = txn
+            .range::<String, PersyId, _>(IDS, ..)?
+            .map(|(id, _)| id)
+            .collect();
+        for id in ids {
+            remove(&mut txn, path, &id)?;
+        }
+        for i in records.iter() {
+            insert(&mut txn, &mac_key, i.key(), i.value())?;
+        }
+        txn.put::<String, ByteVec>(META, HEADER.to_string(), header_bytes.clone().into())?;
+        txn.put::<String, ByteVec>(
+            META,
+            HEADER_MAC.to_string(),
+            mac::sign(&mac_key, &header_bytes).into(),
+        )?;
+        txn.prepare()?.commit()?;
+        Ok(())
+    }
+
+    // Only the category and kind are indexed; the rest of the query is left
+    // to Query::matches.
+    fn query_records(
+        &self,
+        path: &str,
+        query: &Query,
+        index_key: &Key,
+    ) -> Result<Option<Vec<String>>> {
+        let db = open(path)?;
+        let mut found: Option<BTreeSet<String>> = None;
+        let mut narrow = |ids: BTreeSet<String>| {
+            found = Some(match found.take() {
+                Some(found) => found.intersection(&ids).cloned().collect(),
+                None => ids,
+            })
+        };
+        if let Some(category) = &query.category {
+            narrow(lookup(
+                &db,
+                (CATEGORY, BLIND_CATEGORY),
+                index_key,
+                category,
+            )?);
+        }
+        if let Some(kind) = &query.kind {
+            narrow(lookup(&db, (KIND, BLIND_KIND), index_key, &kind.name())?);
+        }
+        Ok(found.map(|ids| ids.into_iter().collect()))
+    }
+}
+
+// Copy a database that was read from a file (see EncryptedDB::from_file) into
+// a persy database at `path`, keeping its header, key slots, and records as
+// they are.
+pub fn import(enc_db: &EncryptedDB, path: &str) -> Result<()> {
+    let records = rows::from_file(enc_db)?;
+    PersyBackend::new().write_records(path, &enc_db.header(), enc_db.key()?, &records)
+}
+
+// The reverse of `import`: write a database read from persy to a file at
+// `dest`.
+pub fn export(enc_db: &EncryptedDB, dest: &str) -> Result<()> {
+    rows::to_file(enc_db, dest)
+}
+
+fn read_header(db: &Persy, path: &str) -> Result<Header> {
+    if !db.exists_index(META)? {
+        return Err(anyhow!("{path} is not a rucksack database (no meta index)"));
+    }
+    match db.one::<String, ByteVec>(META, &HEADER.to_string())? {
+        Some(bytes) => Ok(Header::decode(&bytes)?.0),
+        None => Err(anyhow!(
+            "{path} is not a rucksack database (missing header)"
+        )),
+    }
+}
+
+fn read_records(
+    db: &Persy,
+    path: String,
+    header: Header,
+    key: Key,
+    slot: String,
+) -> Result<EncryptedDB> {
+    log::debug!("Reading record rows ...");
+    let mac_key = mac::derive_key(&key);
+    let tag = db.one::<String, ByteVec>(META, &HEADER_MAC.to_string())?;
+    rows::verify_header(
+        &path,
+        &mac_key,
+        &header.encode()?,
+        tag.as_ref().map(|t| &t[..]),
+    )?;
+    let mut data: Vec<(String, EncryptedRecord)> = Vec::new();
+    for (id, mut row) in db.range::<String, PersyId, _>(IDS, ..)? {
+        let bytes = match row.next() {
+            Some(row) => db.read(RECORDS, &row)?,
+            None => None,
+        };
+        let Some(bytes) = bytes else {
+            return Err(anyhow!("{path} has a missing record row ({id:?})"));
+        };
+        let record = rows::decode(&path, &mac_key, &id, &bytes)?;
+        data.push((id, record));
+    }
+    rows::unlocked(path, header, key, slot, data)
+}
+
+fn insert(txn: &mut Transaction, mac_key: &Key, id: &str, record: &EncryptedRecord) -> Result<()> {
+    let row = txn.insert(RECORDS, &rows::encode(mac_key, id, record)?)?;
+    txn.put::<String, PersyId>(IDS, id.to_string(), row)?;
+    if record.is_sealed() {
+        txn.put::<u64, String>(BLIND_CATEGORY, record.index.category, id.to_string())?;
+        txn.put::<u64, String>(BLIND_KIND, record.index.kind, id.to_string())?;
+    } else {
+        let md = &record.metadata;
+        txn.put::<String, String>(CATEGORY, md.category.clone(), id.to_string())?;
+        txn.put::<String, String>(KIND, md.kind.name(), id.to_string())?;
+    }
+    Ok(())
+}
+
+// Remove the row with the given ID (if there is one), and take it out of the
+// indexes.
+fn remove(txn: &mut Transaction, path: &str, id: &str) -> Result<()> {
+    let Some(row) = txn.one::<String, PersyId>(IDS, &id.to_string())? else {
+        return Ok(());
+    };
+    if let Some(bytes) = txn.read(RECORDS, &row)? {
+        let record = rows::record(path, id, &bytes)?;
+        if record.is_sealed() {
+            unindex(txn, BLIND_CATEGORY, record.index.category, id)?;
+            unindex(txn, BLIND_KIND, record.index.kind, id)?;
+        } else {
+            unindex(txn, CATEGORY, record.metadata.category.clone(), id)?;
+            unindex(txn, KIND, record.metadata.kind.name(), id)?;
+        }
+        txn.delete(RECORDS, &row)?;
+    }
+    txn.remove::<String, PersyId>(IDS, id.to_string(), None)?;
+    Ok(())
+}
+
+fn unindex<K: IndexType>(txn: &mut Transaction, name: &str, value: K, id: &str) -> Result<()> {
+    txn.remove::<K, String>(name, value, Some(id.to_string()))?;
+    Ok(())
+}
+
+// The IDs of the records with the given value in either of a pair of
+// indexes: (plaintext, blinded)
+fn lookup(
+    db: &Persy,
+    (plain, blind): (&str, &str),
+    index_key: &Key,
+    value: &str,
+) -> Result<BTreeSet<String>> {
+    let mut ids: BTreeSet<String> = db
+        .get::<String, String>(plain, &value.to_string())?
+        .collect();
+    ids.extend(db.get::<u64, String>(blind, &index::blind(index_key, plain, value))?);
+    Ok(ids)
+}
+
+fn open(path: &str) -> Result<Persy> {
+    match Persy::open(path, Config::new()) {
+        Ok(db) => Ok(db),
+        Err(e) => Err(anyhow!("could not open persy database {path} ({e})")),
+    }
+}
+
+// Open the database at `path`, creating it (with its segment and indexes) if
+// it doesn't exist yet.
+fn create(path: &str) -> Result<Persy> {
+    let db = Persy::open_or_create_with(path, Config::new(), |db| {
+        let mut txn = db.begin()?;
+        txn.create_segment(RECORDS)?;
+        txn.create_index::<String, ByteVec>(META, ValueMode::Replace)?;
+        txn.create_index::<String, PersyId>(IDS, ValueMode::Replace)?;
+        txn.create_index::<String, String>(CATEGORY, ValueMode::Cluster)?;
+        txn.create_index::<String, String>(KIND, ValueMode::Cluster)?;
+        txn.create_index::<u64, String>(BLIND_CATEGORY, ValueMode::Cluster)?;
+        txn.create_index::<u64, String>(BLIND_KIND, ValueMode::Cluster)?;
+        txn.prepare()?.commit()?;
+        Ok(())
+    });
+    match db {
+        Ok(db) => Ok(db),
+        Err(e) => Err(anyhow!("could not create persy database {path} ({e})")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::SecretString;
+
+    use crate::crypto::{index, keyslot, Cipher};
+    use crate::db::encrypted::EncryptedDB;
+    use crate::db::header::Header;
+    use crate::db::query::Query;
+    use crate::error::IntegrityError;
+    use crate::store::manager::StoreManager;
+    use crate::testing;
+
+    #[test]
+    fn rows_and_queries() {
+        let mut db_handler = testing::db::new();
+        assert!(db_handler.setup().is_ok());
+        let db_file = db_handler.file_name().unwrap();
+        let persy_file = format!("{db_file}.persy");
+        let exported = format!("{db_file}.exported");
+        let pwd = SecretString::new(testing::data::store_pwd());
+        let salt = SecretString::new(String::new());
+        let key = keyslot::new_dek();
+        let index_key = index::derive_key(&key);
+        let cipher = Cipher::default();
+        let header = Header::new(
+            crate::db::version(),
+            cipher,
+            vec![testing::data::key_slot(&key, cipher)],
+        );
+        let dpr = testing::data::plaintext_record_v0100();
+        let plain = dpr.encrypt(&key, cipher);
+        let sealed = dpr.seal(&key, cipher, &index_key);
+        let records = crate::records::HashMap::new();
+        records.insert(plain.key(), plain.clone());
+        records.insert(sealed.key(), sealed.clone());
+        let backend = super::PersyBackend::new();
+        backend
+            .write_records(&db_file, &header, &key, &records)
+            .unwrap();
+        let from_persy = backend.read(db_file.clone(), &pwd, &salt, None).unwrap();
+        assert_eq!(from_persy.header(), header);
+        assert!(backend
+            .read(
+                db_file.clone(),
+                &SecretString::new("wrong".to_string()),
+                &salt,
+                None
+            )
+            .is_err());
+
+        // Queries find both kinds of record, and follow changes to them
+        let query = |query: Query| backend.query_records(&db_file, &query, &index_key).unwrap();
+        let mut both = vec![plain.key(), sealed.key()];
+        both.sort();
+        assert_eq!(query(Query::new()), None);
+        let category = Query {
+            category: Some(dpr.metadata.category.clone()),
+            kind: Some(dpr.metadata.kind.clone()),
+            ..Default::default()
+        };
+        assert_eq!(query(category.clone()), Some(both));
+        let other = Query {
+            category: Some("elsewhere".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query(other.clone()), Some(Vec::new()));
+        let mut moved = dpr.clone();
+        moved.metadata.category = "elsewhere".to_string();
+        backend
+            .put_record(&db_file, &key, &plain.key(), &moved.encrypt(&key, cipher))
+            .unwrap();
+        assert_eq!(query(other), Some(vec![plain.key()]));
+        backend.delete_record(&db_file, &sealed.key()).unwrap();
+        assert_eq!(query(category), Some(Vec::new()));
+        backend
+            .write_records(&db_file, &header, &key, &records)
+            .unwrap();
+
+        // persy -> file -> persy changes nothing
+        super::export(&from_persy, &exported).unwrap();
+        let from_file = EncryptedDB::from_file(exported.clone(), &pwd, &salt, None).unwrap();
+        assert_eq!(from_file.decrypted(), from_persy.decrypted());
+        super::import(&from_file, &persy_file).unwrap();
+        let again = backend.read(persy_file.clone(), &pwd, &salt, None).unwrap();
+        assert_eq!(again.decrypted(), from_persy.decrypted());
+        assert_eq!(again.header(), header);
+
+        // A row that's been changed (or moved) is caught
+        let db = super::create(&persy_file).unwrap();
+        let mut txn = db.begin().unwrap();
+        let row = super::rows::encode(&super::mac::derive_key(&key), &plain.key(), &plain);
+        let row = txn.insert(super::RECORDS, &row.unwrap()).unwrap();
+        txn.put::<String, persy::PersyId>(super::IDS, "someone-else".to_string(), row)
+            .unwrap();
+        txn.prepare().unwrap().commit().unwrap();
+        drop(db);
+        match backend.read(persy_file, &pwd, &salt, None) {
+            Ok(_) => panic!("a moved row wasn't caught"),
+            Err(e) => assert!(e.downcast_ref::<IntegrityError>().is_some()),
+        }
+        assert!(db_handler.teardown().is_ok());
+    }
+}
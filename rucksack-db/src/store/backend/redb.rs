@@ -1,9 +1,27 @@
-use anyhow::Result;
+// A redb database holds the same header (and so the same key slots) as a
+// database file, but keeps each encrypted record in its own row, keyed by the
+// record's key (or its opaque ID, if metadata is sealed). Changes to records
+// are written as they're made, each in its own transaction, rather than the
+// whole database being re-encrypted on close. See super::rows for how the
+// header and rows are signed.
+//
+use anyhow::{anyhow, Result};
+use redb::{Database, ReadableTable, TableDefinition};
+use secrecy::SecretString;
 
+use crate::crypto::{mac, Key};
 use crate::db::encrypted::EncryptedDB;
+use crate::db::header::Header;
+use crate::records::{EncryptedRecord, HashMap};
 use crate::store::manager::StoreManager;
 
-use super::backup;
+use super::rows;
+
+const META: TableDefinition<&str, &[u8]> = TableDefinition::new("meta");
+const RECORDS: TableDefinition<&str, &[u8]> = TableDefinition::new("records");
+
+const HEADER: &str = "header";
+const HEADER_MAC: &str = "header-mac";
 
 #[derive(Clone, Default)]
 pub struct ReDBBackend {}
@@ -15,11 +33,228 @@ impl ReDBBackend {
 }
 
 impl StoreManager for ReDBBackend {
-    fn backup(&self, src_file: String, dest_dir: String, version: String) -> Result<String> {
-        backup::copy(src_file, dest_dir, version)
+    fn name(&self) -> &'static str {
+        "redb"
     }
 
-    fn read(&self, _path: String, _pwd: String, _salt: String) -> Result<EncryptedDB> {
-        todo!()
+    fn describe(&self, path: &str) -> Result<Header> {
+        read_header(path)
+    }
+
+    fn read(
+        &self,
+        path: String,
+        pwd: &SecretString,
+        _salt: &SecretString,
+        key_file: Option<Key>,
+    ) -> Result<EncryptedDB> {
+        let header = read_header(&path)?;
+        let (key, slot) = rows::unlock(&header, pwd, key_file.as_ref())?;
+        read_records(path, header, key, slot)
+    }
+
+    fn read_with_key(&self, path: String, key: Key) -> Result<EncryptedDB> {
+        let header = read_header(&path)?;
+        read_records(path, header, key, String::new())
+    }
+
+    fn per_record(&self) -> bool {
+        true
+    }
+
+    fn put_record(&self, path: &str, key: &Key, id: &str, record: &EncryptedRecord) -> Result<()> {
+        log::debug!("Writing record row ...");
+        let db = Database::create(path)?;
+        let txn = db.begin_write()?;
+        {
+            let mut table = txn.open_table(RECORDS)?;
+            table.insert(
+                id,
+                rows::encode(&mac::derive_key(key), id, record)?.as_slice(),
+            )?;
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    fn delete_record(&self, path: &str, id: &str) -> Result<()> {
+        log::debug!("Deleting record row ...");
+        let db = Database::create(path)?;
+        let txn = db.begin_write()?;
+        {
+            let mut table = txn.open_table(RECORDS)?;
+            table.remove(id)?;
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    // Replace everything: the header and all the rows, in one transaction.
+    fn write_records(
+        &self,
+        path: &str,
+        header: &Header,
+        key: &Key,
+        records: &HashMap,
+    ) -> Result<()> {
+        log::debug!("Writing header and {} record rows ...", records.len());
+        let mac_key = mac::derive_key(key);
+        let header_bytes = header.encode()?;
+        let db = Database::create(path)?;
+        let txn = db.begin_write()?;
+        {
+            txn.delete_table(RECORDS)?;
+            let mut table = txn.open_table(RECORDS)?;
+            for i in records.iter() {
+                table.insert(
+                    i.key().as_str(),
+                    rows::encode(&mac_key, i.key(), i.value())?.as_slice(),
+                )?;
+            }
+            let mut meta = txn.open_table(META)?;
+            meta.insert(HEADER, header_bytes.as_slice())?;
+            meta.insert(HEADER_MAC, mac::sign(&mac_key, &header_bytes).as_slice())?;
+        }
+        txn.commit()?;
+        Ok(())
+    }
+}
+
+// Copy a database that was read from a file (see EncryptedDB::from_file) into
+// a redb database at `path`, keeping its header, key slots, and records as
+// they are.
+pub fn import(enc_db: &EncryptedDB, path: &str) -> Result<()> {
+    let records = rows::from_file(enc_db)?;
+    ReDBBackend::new().write_records(path, &enc_db.header(), enc_db.key()?, &records)
+}
+
+// The reverse of `import`: write a database read from redb to a file at
+// `dest`.
+pub fn export(enc_db: &EncryptedDB, dest: &str) -> Result<()> {
+    rows::to_file(enc_db, dest)
+}
+
+fn read_header(path: &str) -> Result<Header> {
+    let db = open(path)?;
+    let txn = db.begin_read()?;
+    let meta = match txn.open_table(META) {
+        Ok(meta) => meta,
+        Err(e) => return Err(anyhow!("{path} is not a rucksack database ({e})")),
+    };
+    let header = match meta.get(HEADER)? {
+        Some(bytes) => Header::decode(bytes.value())?.0,
+        None => {
+            return Err(anyhow!(
+                "{path} is not a rucksack database (missing header)"
+            ))
+        }
+    };
+    Ok(header)
+}
+
+fn read_records(path: String, header: Header, key: Key, slot: String) -> Result<EncryptedDB> {
+    log::debug!("Reading record rows ...");
+    let mac_key = mac::derive_key(&key);
+    let db = open(&path)?;
+    let txn = db.begin_read()?;
+    let meta = txn.open_table(META)?;
+    let tag = meta.get(HEADER_MAC)?;
+    rows::verify_header(
+        &path,
+        &mac_key,
+        &header.encode()?,
+        tag.as_ref().map(|t| t.value()),
+    )?;
+    let mut data: Vec<(String, EncryptedRecord)> = Vec::new();
+    let table = txn.open_table(RECORDS)?;
+    for row in table.iter()? {
+        let (id, value) = row?;
+        let id = id.value().to_string();
+        let record = rows::decode(&path, &mac_key, &id, value.value())?;
+        data.push((id, record));
+    }
+    rows::unlocked(path, header, key, slot, data)
+}
+
+fn open(path: &str) -> Result<Database> {
+    match Database::open(path) {
+        Ok(db) => Ok(db),
+        Err(e) => Err(anyhow!("could not open redb database {path} ({e})")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::SecretString;
+
+    use crate::crypto::{keyslot, Cipher};
+    use crate::db::encrypted::EncryptedDB;
+    use crate::db::header::Header;
+    use crate::error::IntegrityError;
+    use crate::store::manager::StoreManager;
+    use crate::testing;
+
+    #[test]
+    fn import_and_export() {
+        let mut db_handler = testing::db::new();
+        assert!(db_handler.setup().is_ok());
+        let db_file = db_handler.file_name().unwrap();
+        let redb_file = format!("{db_file}.redb");
+        let exported = format!("{db_file}.exported");
+        let pwd = SecretString::new(testing::data::store_pwd());
+        let salt = SecretString::new(String::new());
+        let key = keyslot::new_dek();
+        let cipher = Cipher::default();
+        let header = Header::new(
+            crate::db::version(),
+            cipher,
+            vec![testing::data::key_slot(&key, cipher)],
+        );
+        let dpr = testing::data::plaintext_record_v0100();
+        let records = crate::records::HashMap::new();
+        let encrypted = dpr.encrypt(&key, cipher);
+        records.insert(encrypted.key(), encrypted);
+        let backend = super::ReDBBackend::new();
+        backend
+            .write_records(&db_file, &header, &key, &records)
+            .unwrap();
+        let from_redb = backend.read(db_file.clone(), &pwd, &salt, None).unwrap();
+        assert_eq!(from_redb.header(), header);
+
+        // redb -> file -> redb changes nothing
+        super::export(&from_redb, &exported).unwrap();
+        let from_file = EncryptedDB::from_file(exported.clone(), &pwd, &salt, None).unwrap();
+        assert_eq!(from_file.decrypted(), from_redb.decrypted());
+        super::import(&from_file, &redb_file).unwrap();
+        let again = backend.read(redb_file.clone(), &pwd, &salt, None).unwrap();
+        assert_eq!(again.decrypted(), from_redb.decrypted());
+        assert_eq!(again.header(), header);
+        assert!(backend
+            .read(
+                redb_file.clone(),
+                &SecretString::new("wrong".to_string()),
+                &salt,
+                None
+            )
+            .is_err());
+
+        // A row that's been changed (or moved) is caught
+        let moved = dpr.encrypt(&key, cipher);
+        let db = redb::Database::create(&redb_file).unwrap();
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(super::RECORDS).unwrap();
+            let row = super::rows::encode(&super::mac::derive_key(&key), &dpr.key(), &moved);
+            table
+                .insert("someone-else", row.unwrap().as_slice())
+                .unwrap();
+        }
+        txn.commit().unwrap();
+        drop(db);
+        match backend.read(redb_file, &pwd, &salt, None) {
+            Ok(_) => panic!("a moved row wasn't caught"),
+            Err(e) => assert!(e.downcast_ref::<IntegrityError>().is_some()),
+        }
+        assert!(db_handler.teardown().is_ok());
     }
 }
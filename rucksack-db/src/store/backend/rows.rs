@@ -0,0 +1,171 @@
+// What the backends that keep each record in its own row (see
+// crate::store::manager::StoreManager::per_record) have in common: the header
+// and every row are signed with the MAC key (see crate::crypto::mac), the
+// rows along with their IDs, so that neither can be changed (or a row moved to
+// another ID) without that being detected when the database is read. Unlike
+// the MAC over a whole file, this can't tell that a row has been removed.
+//
+use anyhow::{anyhow, Result};
+use secrecy::{ExposeSecret, SecretString};
+
+use rucksack_lib::util;
+
+use crate::crypto::{mac, Key};
+use crate::db::encrypted::EncryptedDB;
+use crate::db::header::Header;
+use crate::db::versioned::VersionedDB;
+use crate::error::IntegrityError;
+use crate::records::{self, EncryptedRecord, HashMap};
+
+// Get the database's key from the header's key slots, failing just as
+// opening a file with the wrong password does.
+pub fn unlock(
+    header: &Header,
+    pwd: &SecretString,
+    key_file: Option<&Key>,
+) -> Result<(Key, String)> {
+    match header.unlock(pwd.expose_secret(), key_file) {
+        Ok(unlocked) => Ok(unlocked),
+        Err(e) => {
+            let msg = "Could not decrypt data; the password (or key file) is wrong";
+            log::error!("{} ({:?})", msg, e);
+            Err(anyhow!("{}", msg))
+        }
+    }
+}
+
+pub fn verify_header(path: &str, mac_key: &Key, header: &[u8], tag: Option<&[u8]>) -> Result<()> {
+    match tag {
+        Some(tag) if mac::verify(mac_key, header, tag).is_ok() => Ok(()),
+        _ => {
+            log::error!("Header MAC mismatch for {path}");
+            Err(anyhow!(IntegrityError::new(
+                path.to_string(),
+                "header MAC mismatch"
+            )))
+        }
+    }
+}
+
+pub fn sign(mac_key: &Key, id: &str, record: &EncryptedRecord) -> Result<Vec<u8>> {
+    let bytes = bincode::encode_to_vec(record, util::bincode_cfg())?;
+    Ok(mac::sign(mac_key, &signed(id, &bytes)))
+}
+
+pub fn verify(
+    path: &str,
+    mac_key: &Key,
+    id: &str,
+    record: &EncryptedRecord,
+    tag: &[u8],
+) -> Result<()> {
+    let bytes = bincode::encode_to_vec(record, util::bincode_cfg())?;
+    if let Err(e) = mac::verify(mac_key, &signed(id, &bytes), tag) {
+        log::error!("Could not verify record row {id:?} ({e})");
+        return Err(anyhow!(IntegrityError::new(
+            path.to_string(),
+            "record row MAC mismatch"
+        )));
+    }
+    Ok(())
+}
+
+// A row as a single value: the bincoded record followed by its MAC
+pub fn encode(mac_key: &Key, id: &str, record: &EncryptedRecord) -> Result<Vec<u8>> {
+    let mut bytes = bincode::encode_to_vec(record, util::bincode_cfg())?;
+    bytes.extend(mac::sign(mac_key, &signed(id, &bytes)));
+    Ok(bytes)
+}
+
+pub fn decode(path: &str, mac_key: &Key, id: &str, bytes: &[u8]) -> Result<EncryptedRecord> {
+    if bytes.len() < mac::MAC_SIZE {
+        return Err(anyhow!(IntegrityError::new(
+            path.to_string(),
+            "record row is truncated"
+        )));
+    }
+    let (bytes, tag) = bytes.split_at(bytes.len() - mac::MAC_SIZE);
+    let record = unverified(path, id, bytes)?;
+    verify(path, mac_key, id, &record, tag)?;
+    Ok(record)
+}
+
+// The record in a row, without checking its MAC: only for finding what a
+// backend has to tidy up (e.g., its indexes) when the row is replaced or
+// removed, never for reading the database.
+pub fn record(path: &str, id: &str, bytes: &[u8]) -> Result<EncryptedRecord> {
+    unverified(
+        path,
+        id,
+        &bytes[..bytes.len().saturating_sub(mac::MAC_SIZE)],
+    )
+}
+
+// Put the (verified) rows together as they would be in a decrypted file, so
+// that the database is loaded the same way whatever it was read from.
+pub fn unlocked(
+    path: String,
+    header: Header,
+    key: Key,
+    slot: String,
+    mut data: Vec<(String, EncryptedRecord)>,
+) -> Result<EncryptedDB> {
+    log::trace!("Read {} record rows", data.len());
+    data.sort_by(|a, b| a.0.cmp(&b.0));
+    let bytes = bincode::encode_to_vec(data, util::bincode_cfg())?;
+    let encoded = VersionedDB::new(bytes, header.version.clone()).serialise()?;
+    Ok(EncryptedDB::from_unlocked(encoded, path, header, key, slot))
+}
+
+// The records of a database read from a file (see EncryptedDB::from_file),
+// for importing into a backend that stores them as rows. Only files with the
+// current schema can be imported, since rows always have the current layout.
+pub fn from_file(enc_db: &EncryptedDB) -> Result<HashMap> {
+    if enc_db.is_legacy() {
+        return Err(anyhow!(
+            "legacy databases can't be imported; open and close it with this version of rucksack first"
+        ));
+    }
+    let vsn_db = VersionedDB::deserialise(enc_db.decrypted())?;
+    if vsn_db.version() != records::version() {
+        return Err(anyhow!(
+            "only databases with schema version {} can be imported (this is {}); open and close it with this version of rucksack first",
+            records::version(),
+            vsn_db.version()
+        ));
+    }
+    records::decode_hashmap(vsn_db.bytes(), vsn_db.version())
+}
+
+// The reverse of `from_file`: write a database read from rows to a file at
+// `dest`.
+pub fn to_file(enc_db: &EncryptedDB, dest: &str) -> Result<()> {
+    EncryptedDB::from_decrypted(
+        enc_db.decrypted(),
+        dest.to_string(),
+        enc_db.header(),
+        enc_db.key()?.clone(),
+    )?
+    .write()
+}
+
+fn unverified(path: &str, id: &str, bytes: &[u8]) -> Result<EncryptedRecord> {
+    match bincode::decode_from_slice(bytes, util::bincode_cfg()) {
+        Ok((record, _)) => Ok(record),
+        Err(e) => {
+            log::error!("Could not decode record row {id:?} ({e})");
+            Err(anyhow!(IntegrityError::new(
+                path.to_string(),
+                "record row is corrupt"
+            )))
+        }
+    }
+}
+
+// A row's MAC covers its ID as well as the record
+fn signed(id: &str, bytes: &[u8]) -> Vec<u8> {
+    let mut signed = id.as_bytes().to_vec();
+    signed.push(0);
+    signed.extend(bytes);
+    signed
+}
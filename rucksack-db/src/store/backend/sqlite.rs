@@ -0,0 +1,509 @@
+// A SQLite database holds the same header (and so the same key slots) as a
+// database file, and one row per encrypted record. Besides the record itself
+// (its encrypted value and history, and its metadata), each row has its
+// metadata in columns that can be queried: in plaintext, or blinded (see
+// crate::crypto::index) if the record is sealed. Tags are kept in a table of
+// their own. This lets `query_records` filter in SQL, and lets other tools
+// read the database without knowing anything about bincode.
+//
+// As with redb, changes to records are written as they're made, and the
+// header and rows are signed (see super::rows). The query columns aren't
+// covered by the signatures, but they're only ever used to narrow down which
+// records to check; the records themselves are.
+//
+// `PRAGMA user_version` is the layout of the tables; the schema version of
+// the records they hold (crate::records::version) is kept in the meta table,
+// and a database with any other schema version has to be exported and opened
+// as a file to be migrated.
+//
+use anyhow::{anyhow, Result};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OpenFlags, OptionalExtension, Transaction};
+use secrecy::SecretString;
+
+use rucksack_lib::util;
+
+use crate::crypto::{index, mac, Key};
+use crate::db::encrypted::EncryptedDB;
+use crate::db::header::Header;
+use crate::db::query::Query;
+use crate::records::{self, EncryptedRecord, HashMap};
+use crate::store::manager::StoreManager;
+
+use super::rows;
+
+const LAYOUT: i64 = 1;
+
+const TABLES: &str = "
+    CREATE TABLE meta (
+        name TEXT PRIMARY KEY,
+        value BLOB NOT NULL
+    );
+    CREATE TABLE records (
+        id TEXT PRIMARY KEY,
+        sealed INTEGER NOT NULL,
+        kind TEXT,
+        category TEXT,
+        name TEXT,
+        url TEXT,
+        status TEXT,
+        blind_kind INTEGER,
+        blind_category INTEGER,
+        metadata BLOB NOT NULL,
+        value BLOB NOT NULL,
+        history BLOB NOT NULL,
+        sealed_metadata BLOB NOT NULL,
+        blind_index BLOB NOT NULL,
+        mac BLOB NOT NULL
+    );
+    CREATE INDEX records_category ON records (category, kind);
+    CREATE INDEX records_blind_category ON records (blind_category, blind_kind);
+    CREATE TABLE tags (
+        id TEXT NOT NULL,
+        value TEXT,
+        blind INTEGER
+    );
+    CREATE INDEX tags_id ON tags (id);
+";
+
+// A record's tags, in a query
+const TAGS: &str = "SELECT 1 FROM tags t WHERE t.id = records.id";
+
+const HEADER: &str = "header";
+const HEADER_MAC: &str = "header-mac";
+const SCHEMA_VERSION: &str = "schema-version";
+
+#[derive(Clone, Default)]
+pub struct SqliteBackend {}
+
+impl SqliteBackend {
+    pub fn new() -> SqliteBackend {
+        SqliteBackend {}
+    }
+}
+
+impl StoreManager for SqliteBackend {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn describe(&self, path: &str) -> Result<Header> {
+        read_header(&open(path)?, path)
+    }
+
+    fn read(
+        &self,
+        path: String,
+        pwd: &SecretString,
+        _salt: &SecretString,
+        key_file: Option<Key>,
+    ) -> Result<EncryptedDB> {
+        let conn = open(&path)?;
+        let header = read_header(&conn, &path)?;
+        let (key, slot) = rows::unlock(&header, pwd, key_file.as_ref())?;
+        read_records(&conn, path, header, key, slot)
+    }
+
+    fn read_with_key(&self, path: String, key: Key) -> Result<EncryptedDB> {
+        let conn = open(&path)?;
+        let header = read_header(&conn, &path)?;
+        read_records(&conn, path, header, key, String::new())
+    }
+
+    fn per_record(&self) -> bool {
+        true
+    }
+
+    fn put_record(&self, path: &str, key: &Key, id: &str, record: &EncryptedRecord) -> Result<()> {
+        log::debug!("Writing record row ...");
+        let mut conn = connect(path)?;
+        let txn = conn.transaction()?;
+        insert(&txn, &mac::derive_key(key), id, record)?;
+        txn.commit()?;
+        Ok(())
+    }
+
+    fn delete_record(&self, path: &str, id: &str) -> Result<()> {
+        log::debug!("Deleting record row ...");
+        let mut conn = connect(path)?;
+        let txn = conn.transaction()?;
+        txn.execute("DELETE FROM records WHERE id = ?1", [id])?;
+        txn.execute("DELETE FROM tags WHERE id = ?1", [id])?;
+        txn.commit()?;
+        Ok(())
+    }
+
+    // Replace everything: the header and all the rows, in one transaction.
+    fn write_records(
+        &self,
+        path: &str,
+        header: &Header,
+        key: &Key,
+        records: &HashMap,
+    ) -> Result<()> {
+        log::debug!("Writing header and {} record rows ...", records.len());
+        let mac_key = mac::derive_key(key);
+        let header_bytes = header.encode()?;
+        let mut conn = connect(path)?;
+        let txn = conn.transaction()?;
+        txn.execute("DELETE FROM records", [])?;
+        txn.execute("DELETE FROM tags", [])?;
+        for i in records.iter() {
+            insert(&txn, &mac_key, i.key(), i.value())?;
+        }
+        let mut meta = txn.prepare("INSERT OR REPLACE INTO meta (name, value) VALUES (?1, ?2)")?;
+        meta.execute(params![HEADER, header_bytes])?;
+        meta.execute(params![HEADER_MAC, mac::sign(&mac_key, &header_bytes)])?;
+        meta.execute(params![
+            SCHEMA_VERSION,
+            records::version().to_string().into_bytes()
+        ])?;
+        drop(meta);
+        txn.commit()?;
+        Ok(())
+    }
+
+    // Plaintext rows are checked against the plaintext columns, and sealed
+    // ones against the blinded columns; a sealed row's key terms aren't
+    // stored, so any sealed row can match a filter.
+    fn query_records(
+        &self,
+        path: &str,
+        query: &Query,
+        index_key: &Key,
+    ) -> Result<Option<Vec<String>>> {
+        let mut values: Vec<Value> = Vec::new();
+        let mut plain = vec!["sealed = 0".to_string()];
+        let mut blind = vec!["sealed = 1".to_string()];
+        if let Some(category) = &query.category {
+            let p = param(&mut values, Value::Text(category.clone()));
+            plain.push(format!("category = {p}"));
+            let p = param(&mut values, blinded(index_key, "category", category));
+            blind.push(format!("blind_category = {p}"));
+        }
+        if let Some(kind) = &query.kind {
+            let p = param(&mut values, Value::Text(kind.name()));
+            plain.push(format!("kind = {p}"));
+            let p = param(&mut values, blinded(index_key, "kind", &kind.name()));
+            blind.push(format!("blind_kind = {p}"));
+        }
+        // These mirror the semantics of util::all and util::any
+        let tags = |values: &mut Vec<Value>, column: &str, tags: &Vec<String>| {
+            let ps: Vec<String> = tags
+                .iter()
+                .map(|t| match column {
+                    "blind" => param(values, blinded(index_key, "tag", t)),
+                    _ => param(values, Value::Text(t.clone())),
+                })
+                .collect();
+            format!("t.{column} IN ({})", ps.join(", "))
+        };
+        if !query.all_tags.is_empty() {
+            let all = tags(&mut values, "value", &query.all_tags);
+            plain.push(format!("NOT EXISTS ({TAGS} AND NOT {all})"));
+            let all = tags(&mut values, "blind", &query.all_tags);
+            blind.push(format!("NOT EXISTS ({TAGS} AND NOT {all})"));
+        }
+        if !query.any_tags.is_empty() {
+            let any = tags(&mut values, "value", &query.any_tags);
+            plain.push(format!("EXISTS ({TAGS} AND {any})"));
+            let any = tags(&mut values, "blind", &query.any_tags);
+            blind.push(format!("EXISTS ({TAGS} AND {any})"));
+        }
+        if let Some(filter) = &query.filter {
+            let p = param(&mut values, Value::Text(filter.clone()));
+            plain.push(format!("instr(id, {p}) > 0"));
+        }
+        let sql = format!(
+            "SELECT id FROM records WHERE ({}) OR ({}) ORDER BY id",
+            plain.join(" AND "),
+            blind.join(" AND ")
+        );
+        log::trace!("Querying records: {sql}");
+        let conn = open(path)?;
+        let mut stmt = conn.prepare(&sql)?;
+        let ids = stmt
+            .query_map(params_from_iter(values), |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(Some(ids))
+    }
+}
+
+// Copy a database that was read from a file (see EncryptedDB::from_file) into
+// a SQLite database at `path`, keeping its header, key slots, and records as
+// they are.
+pub fn import(enc_db: &EncryptedDB, path: &str) -> Result<()> {
+    let records = rows::from_file(enc_db)?;
+    SqliteBackend::new().write_records(path, &enc_db.header(), enc_db.key()?, &records)
+}
+
+// The reverse of `import`: write a database read from SQLite to a file at
+// `dest`.
+pub fn export(enc_db: &EncryptedDB, dest: &str) -> Result<()> {
+    rows::to_file(enc_db, dest)
+}
+
+// For writing: the tables are created if the database is new.
+fn connect(path: &str) -> Result<Connection> {
+    let conn = Connection::open(path)?;
+    match layout(&conn)? {
+        0 => {
+            log::debug!("Creating tables ...");
+            conn.execute_batch(TABLES)?;
+            conn.pragma_update(None, "user_version", LAYOUT)?;
+        }
+        LAYOUT => (),
+        n => return Err(anyhow!("{path} has an unsupported table layout ({n})")),
+    }
+    Ok(conn)
+}
+
+// For reading: the database must already exist, and have the right layout.
+fn open(path: &str) -> Result<Connection> {
+    let conn = match Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY) {
+        Ok(conn) => conn,
+        Err(e) => return Err(anyhow!("could not open SQLite database {path} ({e})")),
+    };
+    match layout(&conn) {
+        Ok(LAYOUT) => Ok(conn),
+        Ok(n) => Err(anyhow!(
+            "{path} is not a rucksack database (table layout {n})"
+        )),
+        Err(e) => Err(anyhow!("{path} is not a rucksack database ({e})")),
+    }
+}
+
+fn layout(conn: &Connection) -> Result<i64> {
+    Ok(conn.pragma_query_value(None, "user_version", |row| row.get(0))?)
+}
+
+fn meta(conn: &Connection, name: &str) -> Result<Option<Vec<u8>>> {
+    let value = conn
+        .query_row("SELECT value FROM meta WHERE name = ?1", [name], |row| {
+            row.get(0)
+        })
+        .optional()?;
+    Ok(value)
+}
+
+fn read_header(conn: &Connection, path: &str) -> Result<Header> {
+    match meta(conn, HEADER)? {
+        Some(bytes) => Ok(Header::decode(&bytes)?.0),
+        None => Err(anyhow!(
+            "{path} is not a rucksack database (missing header)"
+        )),
+    }
+}
+
+fn read_records(
+    conn: &Connection,
+    path: String,
+    header: Header,
+    key: Key,
+    slot: String,
+) -> Result<EncryptedDB> {
+    log::debug!("Reading record rows ...");
+    let mac_key = mac::derive_key(&key);
+    let tag = meta(conn, HEADER_MAC)?;
+    rows::verify_header(&path, &mac_key, &header.encode()?, tag.as_deref())?;
+    let schema = match meta(conn, SCHEMA_VERSION)? {
+        Some(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+        None => String::new(),
+    };
+    if schema != records::version().to_string() || schema != header.version {
+        return Err(anyhow!(
+            "{path} holds records with schema version {schema:?}; only {} can be read from SQLite",
+            records::version()
+        ));
+    }
+    let mut stmt = conn.prepare(
+        "SELECT id, metadata, value, history, sealed_metadata, blind_index, mac FROM records",
+    )?;
+    let mut query = stmt.query([])?;
+    let mut data: Vec<(String, EncryptedRecord)> = Vec::new();
+    while let Some(row) = query.next()? {
+        let id: String = row.get(0)?;
+        let record = EncryptedRecord {
+            key: id.clone(),
+            metadata: decode(&path, &row.get::<_, Vec<u8>>(1)?)?,
+            value: row.get(2)?,
+            history: row.get(3)?,
+            sealed: row.get(4)?,
+            index: decode(&path, &row.get::<_, Vec<u8>>(5)?)?,
+        };
+        rows::verify(&path, &mac_key, &id, &record, &row.get::<_, Vec<u8>>(6)?)?;
+        data.push((id, record));
+    }
+    rows::unlocked(path, header, key, slot, data)
+}
+
+fn insert(txn: &Transaction, mac_key: &Key, id: &str, record: &EncryptedRecord) -> Result<()> {
+    let md = &record.metadata;
+    let sealed = record.is_sealed();
+    let plain = |value: String| if sealed { None } else { Some(value) };
+    let blind = |value: u64| if sealed { Some(value as i64) } else { None };
+    txn.execute(
+        "INSERT OR REPLACE INTO records (id, sealed, kind, category, name, url, status,
+            blind_kind, blind_category, metadata, value, history, sealed_metadata,
+            blind_index, mac)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+        params![
+            id,
+            sealed,
+            plain(md.kind.name()),
+            plain(md.category.clone()),
+            plain(md.name.clone()),
+            plain(md.url.clone()),
+            plain(md.status().to_string()),
+            blind(record.index.kind),
+            blind(record.index.category),
+            encode(md)?,
+            record.value,
+            record.history,
+            record.sealed,
+            encode(&record.index)?,
+            rows::sign(mac_key, id, record)?,
+        ],
+    )?;
+    txn.execute("DELETE FROM tags WHERE id = ?1", [id])?;
+    let mut tags = txn.prepare("INSERT INTO tags (id, value, blind) VALUES (?1, ?2, ?3)")?;
+    if sealed {
+        for t in &record.index.tags {
+            tags.execute(params![id, None::<String>, *t as i64])?;
+        }
+    } else {
+        for t in md.tag_values() {
+            tags.execute(params![id, t, None::<i64>])?;
+        }
+    }
+    Ok(())
+}
+
+fn encode<T: bincode::Encode>(value: &T) -> Result<Vec<u8>> {
+    Ok(bincode::encode_to_vec(value, util::bincode_cfg())?)
+}
+
+fn decode<T: bincode::Decode>(path: &str, bytes: &[u8]) -> Result<T> {
+    match bincode::decode_from_slice(bytes, util::bincode_cfg()) {
+        Ok((value, _)) => Ok(value),
+        Err(e) => Err(anyhow!("{path} has a corrupt record row ({e})")),
+    }
+}
+
+// Blinded values are u64s; SQLite only has signed integers, so they're stored
+// with the same bits.
+fn blinded(index_key: &Key, field: &str, value: &str) -> Value {
+    Value::Integer(index::blind(index_key, field, value) as i64)
+}
+
+fn param(values: &mut Vec<Value>, value: Value) -> String {
+    values.push(value);
+    format!("?{}", values.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::SecretString;
+
+    use crate::crypto::{index, keyslot, Cipher};
+    use crate::db::encrypted::EncryptedDB;
+    use crate::db::header::Header;
+    use crate::db::query::Query;
+    use crate::error::IntegrityError;
+    use crate::store::manager::StoreManager;
+    use crate::testing;
+
+    #[test]
+    fn rows_and_queries() {
+        let mut db_handler = testing::db::new();
+        assert!(db_handler.setup().is_ok());
+        let db_file = db_handler.file_name().unwrap();
+        let exported = format!("{db_file}.exported");
+        let imported = format!("{db_file}.imported");
+        let pwd = SecretString::new(testing::data::store_pwd());
+        let salt = SecretString::new(String::new());
+        let key = keyslot::new_dek();
+        let index_key = index::derive_key(&key);
+        let cipher = Cipher::default();
+        let header = Header::new(
+            crate::db::version(),
+            cipher,
+            vec![testing::data::key_slot(&key, cipher)],
+        );
+        let mut dpr = testing::data::plaintext_record_v0100();
+        dpr.add_tags(vec!["web".to_string()]);
+        let plain = dpr.encrypt(&key, cipher);
+        let sealed = dpr.seal(&key, cipher, &index_key);
+        let records = crate::records::HashMap::new();
+        records.insert(plain.key(), plain.clone());
+        records.insert(sealed.key(), sealed.clone());
+        let backend = super::SqliteBackend::new();
+        backend
+            .write_records(&db_file, &header, &key, &records)
+            .unwrap();
+        let from_sqlite = backend.read(db_file.clone(), &pwd, &salt, None).unwrap();
+        assert_eq!(from_sqlite.header(), header);
+        assert!(backend
+            .read(
+                db_file.clone(),
+                &SecretString::new("wrong".to_string()),
+                &salt,
+                None
+            )
+            .is_err());
+
+        // Queries match both kinds of row
+        let query = |query: Query| {
+            backend
+                .query_records(&db_file, &query, &index_key)
+                .unwrap()
+                .unwrap()
+        };
+        let mut both = vec![plain.key(), sealed.key()];
+        both.sort();
+        assert_eq!(query(Query::new()), both);
+        let category = Query {
+            category: Some(dpr.metadata.category.clone()),
+            any_tags: vec!["web".to_string(), "work".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(query(category), both);
+        let other = Query {
+            category: Some("elsewhere".to_string()),
+            ..Default::default()
+        };
+        assert!(query(other).is_empty());
+        let all_tags = Query {
+            all_tags: vec!["work".to_string()],
+            ..Default::default()
+        };
+        assert!(query(all_tags).is_empty());
+        let filter = Query {
+            filter: Some("no such record".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query(filter), vec![sealed.key()]);
+
+        // SQLite -> file -> SQLite changes nothing
+        super::export(&from_sqlite, &exported).unwrap();
+        let from_file = EncryptedDB::from_file(exported.clone(), &pwd, &salt, None).unwrap();
+        assert_eq!(from_file.decrypted(), from_sqlite.decrypted());
+        super::import(&from_file, &imported).unwrap();
+        let again = backend.read(imported.clone(), &pwd, &salt, None).unwrap();
+        assert_eq!(again.decrypted(), from_sqlite.decrypted());
+
+        // Changes to a record's columns are caught
+        let conn = rusqlite::Connection::open(&imported).unwrap();
+        conn.execute(
+            "UPDATE records SET value = x'00' WHERE id = ?1",
+            [plain.key()],
+        )
+        .unwrap();
+        drop(conn);
+        match backend.read(imported, &pwd, &salt, None) {
+            Ok(_) => panic!("a changed row wasn't caught"),
+            Err(e) => assert!(e.downcast_ref::<IntegrityError>().is_some()),
+        }
+        assert!(db_handler.teardown().is_ok());
+    }
+}
@@ -0,0 +1,195 @@
+// Advisory locks that keep rucksack processes from losing each other's
+// changes: whatever opens a database to change it takes an exclusive lock,
+// and whatever only reads it takes a shared one, from when it's opened until
+// it's closed. The lock is on a file next to the database (`<path>.lock`),
+// rather than on the database itself, since that is replaced (not written in
+// place) when it's saved. Whoever takes an exclusive lock writes their PID to
+// the file, so that a process that can't get one can say who has it. Shared
+// locks leave the file alone, since any number of readers can hold one at
+// once and none of them could say it's the only one.
+//
+use std::fs;
+use std::io::{Read, Seek, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+// Called as FileExt::..., since newer versions of std have methods of the
+// same names on File
+use fs4::FileExt;
+
+use rucksack_lib::file;
+
+use crate::error::LockedError;
+
+// How often a contended lock is tried again, while waiting for it
+const RETRY: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum LockMode {
+    Shared,
+    #[default]
+    Exclusive,
+}
+
+// A held lock; it's released when dropped.
+#[derive(Debug)]
+pub struct Lock {
+    file: fs::File,
+    mode: LockMode,
+}
+
+impl Lock {
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        // So that nobody's told a process that's finished with the database
+        // still has it
+        if self.mode == LockMode::Exclusive {
+            let _ = self.file.set_len(0);
+        }
+        if let Err(e) = FileExt::unlock(&self.file) {
+            log::warn!("Could not release database lock ({e})");
+        }
+    }
+}
+
+pub fn lock_file(path: &str) -> String {
+    format!("{path}.lock")
+}
+
+// Lock the database at `path`, trying for up to `wait` if another process
+// has a lock that conflicts.
+pub fn acquire(path: &str, mode: LockMode, wait: Duration) -> Result<Lock> {
+    let lock_path = file::create_parents(lock_file(path))?;
+    log::debug!("Taking {mode:?} lock on {} ...", lock_path.display());
+    let mut f = match fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&lock_path)
+    {
+        Ok(f) => f,
+        Err(e) => {
+            let msg = "Could not open lock file";
+            log::error!("{msg} {} ({e:})", lock_path.display());
+            return Err(anyhow!("{msg} {} ({e:})", lock_path.display()));
+        }
+    };
+    let started = Instant::now();
+    loop {
+        let r = match mode {
+            LockMode::Shared => FileExt::try_lock_shared(&f),
+            LockMode::Exclusive => FileExt::try_lock_exclusive(&f),
+        };
+        match r {
+            Ok(_) => break,
+            Err(e) if e.kind() != fs4::lock_contended_error().kind() => {
+                return Err(anyhow!("could not lock {path} ({e})"));
+            }
+            Err(_) if started.elapsed() < wait => thread::sleep(RETRY),
+            Err(_) => {
+                let pid = holder(&mut f);
+                log::debug!("Lock on {path} is held by {pid:?}");
+                return Err(anyhow!(LockedError::new(path.to_string(), pid)));
+            }
+        }
+    }
+    if mode == LockMode::Exclusive {
+        f.set_len(0)?;
+        f.rewind()?;
+        f.write_all(std::process::id().to_string().as_bytes())?;
+    }
+    Ok(Lock { file: f, mode })
+}
+
+fn holder(f: &mut fs::File) -> Option<u32> {
+    let mut pid = String::new();
+    f.rewind().ok()?;
+    f.read_to_string(&mut pid).ok()?;
+    pid.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::error::LockedError;
+    use crate::testing;
+
+    use super::LockMode::{Exclusive, Shared};
+
+    #[test]
+    fn shared_and_exclusive() {
+        let mut db_handler = testing::db::new();
+        assert!(db_handler.setup().is_ok());
+        let db_file = db_handler.file_name().unwrap();
+        let none = Duration::ZERO;
+        let held = super::acquire(&db_file, Exclusive, none).unwrap();
+        for mode in [Shared, Exclusive] {
+            match super::acquire(&db_file, mode, none) {
+                Ok(_) => panic!("took a {mode:?} lock while an exclusive one was held"),
+                Err(e) => {
+                    let locked = e.downcast_ref::<LockedError>().unwrap();
+                    assert_eq!(locked.pid, Some(std::process::id()));
+                    assert!(e.to_string().starts_with("database is locked by PID "));
+                }
+            }
+        }
+        drop(held);
+
+        // Any number of shared locks, but then no exclusive one
+        let readers = [
+            super::acquire(&db_file, Shared, none).unwrap(),
+            super::acquire(&db_file, Shared, none).unwrap(),
+        ];
+        assert!(super::acquire(&db_file, Exclusive, none).is_err());
+
+        // ... unless it's waited for
+        let released = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            drop(readers);
+        });
+        let lock = super::acquire(&db_file, Exclusive, Duration::from_secs(5)).unwrap();
+        assert_eq!(lock.mode(), Exclusive);
+        released.join().unwrap();
+        assert!(db_handler.teardown().is_ok());
+    }
+
+    #[test]
+    fn shared_locks_leave_the_pid_alone() {
+        let mut db_handler = testing::db::new();
+        assert!(db_handler.setup().is_ok());
+        let db_file = db_handler.file_name().unwrap();
+        let lock_file = super::lock_file(&db_file);
+        let none = Duration::ZERO;
+        drop(super::acquire(&db_file, Exclusive, none).unwrap());
+        assert_eq!(fs::read_to_string(&lock_file).unwrap(), "");
+
+        // Neither reader claims the lock as its own, so an exclusive lock
+        // can't be blamed on either
+        let first = super::acquire(&db_file, Shared, none).unwrap();
+        let second = super::acquire(&db_file, Shared, none).unwrap();
+        assert_eq!(fs::read_to_string(&lock_file).unwrap(), "");
+        match super::acquire(&db_file, Exclusive, none) {
+            Ok(_) => panic!("took an exclusive lock while shared ones were held"),
+            Err(e) => assert_eq!(e.downcast_ref::<LockedError>().unwrap().pid, None),
+        }
+
+        // One reader finishing doesn't touch the file the other still has
+        // locked
+        fs::write(&lock_file, "sentinel").unwrap();
+        drop(first);
+        assert_eq!(fs::read_to_string(&lock_file).unwrap(), "sentinel");
+        drop(second);
+        assert_eq!(fs::read_to_string(&lock_file).unwrap(), "sentinel");
+        assert!(db_handler.teardown().is_ok());
+    }
+}
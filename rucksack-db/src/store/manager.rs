@@ -1,22 +1,254 @@
-use anyhow::Result;
+// Everything the database (crate::db::DB) needs from wherever it's stored.
+// The defaults are for backends that keep the database in a single file, with
+// backups as copies of that file in a directory (see super::backup).
+use std::path;
+use std::time::Duration;
 
+use anyhow::{anyhow, Result};
+use secrecy::SecretString;
+
+use rucksack_lib::file;
+
+use crate::crypto::legacy::LegacyKey;
+use crate::crypto::Key;
 use crate::db::encrypted::EncryptedDB;
+use crate::db::header::Header;
+use crate::db::query::Query;
+use crate::records::{EncryptedRecord, HashMap};
+
+use super::backup::{self, BackupInfo};
+use super::lock::{self, Lock, LockMode};
 
 pub trait StoreManager {
-    fn backup(&self, src_file: String, dest_dir: String, version: String) -> Result<String>;
-    fn read(&self, path: String, pwd: String, salt: String) -> Result<EncryptedDB>;
+    // The backend's name, as given in the config (`[db] backend`)
+    fn name(&self) -> &'static str;
+
+    // Where the database at `path` is kept (its parent directories are
+    // created, if need be), and whether it exists yet.
+    fn locate(&self, path: &str) -> Result<(String, bool)> {
+        let file_path = file::create_parents(path.to_string())?;
+        Ok((file_path.display().to_string(), file_path.exists()))
+    }
+
+    // Lock the database at `path` (see super::lock) for as long as it's open;
+    // backends that nothing else can get at don't need to.
+    fn lock(&self, path: &str, mode: LockMode, wait: Duration) -> Result<Option<Lock>> {
+        Ok(Some(lock::acquire(path, mode, wait)?))
+    }
+
+    // The database's plaintext header, read without a password
+    fn describe(&self, path: &str) -> Result<Header>;
+
+    fn read(
+        &self,
+        path: String,
+        pwd: &SecretString,
+        salt: &SecretString,
+        key_file: Option<Key>,
+    ) -> Result<EncryptedDB>;
+    fn read_with_key(&self, path: String, key: Key) -> Result<EncryptedDB>;
+
+    // Write the whole (encrypted) database
+    fn write(&self, enc_db: &EncryptedDB) -> Result<()> {
+        enc_db.write()
+    }
+
+    // Backends that keep the database as a whole file don't rewrite it for
+    // every change: changes are appended to a journal next to it (see
+    // crate::db::journal) until the file is next written, which clears it.
+    // There's no journal until something is appended; reading a missing one
+    // gives no bytes.
+    fn append_journal(&self, path: &str, bytes: &[u8]) -> Result<()> {
+        file::append(bytes, journal_file(path))
+    }
+
+    fn read_journal(&self, path: &str) -> Result<Vec<u8>> {
+        let journal = journal_file(path);
+        if !path::Path::new(&journal).exists() {
+            return Ok(Vec::new());
+        }
+        file::read(journal)
+    }
+
+    fn clear_journal(&self, path: &str) -> Result<()> {
+        let journal = path::PathBuf::from(journal_file(path));
+        if !journal.exists() {
+            return Ok(());
+        }
+        file::delete(journal)
+    }
+
+    // Backends that store each record separately (rather than the whole
+    // database as one encrypted file) persist changes to records as they're
+    // made, with `put_record` and `delete_record`; the whole database is only
+    // written (with `write_records`) when it's new, or when a change affects
+    // every record or the header. The key is the database's, for backends
+    // that sign what they store.
+    fn per_record(&self) -> bool {
+        false
+    }
+
+    fn put_record(
+        &self,
+        _path: &str,
+        _key: &Key,
+        _id: &str,
+        _record: &EncryptedRecord,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    fn delete_record(&self, _path: &str, _id: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_records(
+        &self,
+        _path: &str,
+        _header: &Header,
+        _key: &Key,
+        _records: &HashMap,
+    ) -> Result<()> {
+        Err(anyhow!("this backend only stores whole database files"))
+    }
+
+    // Backends that can filter records themselves return the IDs of those
+    // that might match a query (as with Query::matches, this may include
+    // some that don't); the others return None, and every record is checked.
+    fn query_records(
+        &self,
+        _path: &str,
+        _query: &Query,
+        _index_key: &Key,
+    ) -> Result<Option<Vec<String>>> {
+        Ok(None)
+    }
+
+    // Backups are named for the database and the time they were made (see
+    // backup::backup_name), described by a BackupInfo, and listed newest
+    // first. `version` is the database's schema version, and `records` the
+    // number of records in it, if that's known.
+    fn backup(
+        &self,
+        src_file: String,
+        dest_dir: String,
+        version: String,
+        records: Option<usize>,
+    ) -> Result<BackupInfo> {
+        backup::copy(src_file, dest_dir, version, records)
+    }
+
+    fn list_backups(&self, backup_dir: &str) -> Result<Vec<BackupInfo>> {
+        backup::list(backup_dir.to_string())
+    }
+
+    fn latest_backup(&self, backup_dir: &str) -> Result<BackupInfo> {
+        backup::latest(backup_dir.to_string())
+    }
+
+    // A backup, read with the database's key the way `read_with_key` reads
+    // the database
+    fn read_backup(&self, backup_dir: &str, name: &str, key: Key) -> Result<EncryptedDB> {
+        let backup_path = path::Path::new(backup_dir).join(name);
+        if !backup_path.is_file() {
+            return Err(anyhow!("backup file '{name}' does not exist"));
+        }
+        self.read_with_key(backup_path.display().to_string(), key)
+    }
+
+    // A backup of a legacy database (see crate::crypto::legacy), made before
+    // it was upgraded; these are only ever the bytes of the old file
+    fn read_legacy_backup(
+        &self,
+        backup_dir: &str,
+        name: &str,
+        key: &LegacyKey,
+    ) -> Result<EncryptedDB> {
+        let backup_path = path::Path::new(backup_dir).join(name);
+        let bytes = file::read(backup_path.display().to_string())?;
+        EncryptedDB::from_legacy(bytes, backup_path.display().to_string(), key)
+    }
+
+    fn restore_backup(&self, backup_dir: &str, name: &str, path: &str) -> Result<()> {
+        backup::restore(
+            path::PathBuf::from(backup_dir),
+            name.to_string(),
+            path::PathBuf::from(path),
+        )
+    }
+
+    fn delete_backup(&self, backup_dir: &str, name: &str) -> Result<()> {
+        backup::delete(backup_dir.to_string(), name.to_string())
+    }
+}
+
+pub fn journal_file(path: &str) -> String {
+    format!("{path}.journal")
+}
+
+const KNOWN: &[&str] = &["filesystem", "persy", "redb", "sqlite", "memory"];
+
+// The backends this build of rucksack-db has, in order of preference: the
+// first is the default. The in-memory backend (see super::backend::memory)
+// is always there, but last.
+pub fn backends() -> Vec<&'static str> {
+    let mut names = Vec::new();
+    if cfg!(feature = "filesystem") {
+        names.push("filesystem");
+    }
+    if cfg!(feature = "persy") {
+        names.push("persy");
+    }
+    if cfg!(feature = "redb") {
+        names.push("redb");
+    }
+    if cfg!(feature = "sqlite") {
+        names.push("sqlite");
+    }
+    names.push("memory");
+    names
 }
 
 pub fn new() -> Box<dyn StoreManager> {
-    cfg_if::cfg_if! {
-        if #[cfg(feature = "filesystem")] {
-            Box::new(super::backend::FileSystemBackend::new())
-        } else if #[cfg(feature = "persy")] {
-            Box::new(super::backend::PersyBackend::new())
-        } else if #[cfg(feature = "redb")] {
-            Box::new(super::backend::ReDBBackend::new())
-        } else {
-            todo!()
+    from_name(backends()[0]).unwrap()
+}
+
+// Pick a backend by name, e.g., from the config; an empty name means the
+// default.
+pub fn from_name(name: &str) -> Result<Box<dyn StoreManager>> {
+    log::debug!("Using the {name:?} storage backend ...");
+    match name {
+        "" => Ok(new()),
+        #[cfg(feature = "filesystem")]
+        "filesystem" => Ok(Box::new(super::backend::FileSystemBackend::new())),
+        #[cfg(feature = "persy")]
+        "persy" => Ok(Box::new(super::backend::PersyBackend::new())),
+        #[cfg(feature = "redb")]
+        "redb" => Ok(Box::new(super::backend::ReDBBackend::new())),
+        #[cfg(feature = "sqlite")]
+        "sqlite" => Ok(Box::new(super::backend::SqliteBackend::new())),
+        "memory" => Ok(Box::new(super::backend::MemoryBackend::new())),
+        _ if KNOWN.contains(&name) => Err(anyhow!(
+            "this build doesn't include the {name:?} storage backend (available: {})",
+            backends().join(", ")
+        )),
+        _ => Err(anyhow!(
+            "unknown storage backend {name:?} (available: {})",
+            backends().join(", ")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn backends() {
+        let names = super::backends();
+        assert!(!names.is_empty());
+        for name in &names {
+            assert_eq!(super::from_name(name).unwrap().name(), *name);
         }
+        assert_eq!(super::from_name("").unwrap().name(), names[0]);
+        assert!(super::from_name("nope").is_err());
     }
 }
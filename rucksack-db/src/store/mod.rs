@@ -1,4 +1,6 @@
 pub mod backend;
+pub mod lock;
 pub mod manager;
+pub mod retention;
 
 pub use backend::backup;
@@ -0,0 +1,140 @@
+// Which backups of a database to keep, and which to prune. The rules are
+// like those of most backup tools:
+// * `keep_last`: the newest N backups
+// * `keep_daily`: the newest backup of each of the last N days that have any
+// * `keep_weekly`: the same, for (ISO) weeks
+// * `max_age`: nothing older than N days, whatever the other rules say
+//
+// A backup is kept if any of the `keep_*` rules keeps it; with none of them
+// set, every backup is (up to `max_age`). The newest backup is always kept,
+// and a policy with no rules set prunes nothing (which is the default).
+//
+use chrono::{DateTime, Datelike, Duration, Local};
+
+use super::backup::{self, BackupInfo};
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Policy {
+    pub keep_last: u32,
+    pub keep_daily: u32,
+    pub keep_weekly: u32,
+    // In days
+    pub max_age: u32,
+}
+
+impl Policy {
+    pub fn is_empty(&self) -> bool {
+        *self == Policy::default()
+    }
+
+    fn keeps_some(&self) -> bool {
+        self.keep_last > 0 || self.keep_daily > 0 || self.keep_weekly > 0
+    }
+}
+
+// The names of the backups that `policy` doesn't keep, as of `now`
+pub fn prune(policy: &Policy, backups: &[BackupInfo], now: DateTime<Local>) -> Vec<String> {
+    if policy.is_empty() {
+        return Vec::new();
+    }
+    let mut backups = backups.to_vec();
+    backup::sort(&mut backups);
+    let mut days = Vec::new();
+    let mut weeks = Vec::new();
+    let mut pruned = Vec::new();
+    for (i, info) in backups.into_iter().enumerate() {
+        let dt = info.created;
+        let day = dt.date_naive();
+        let week = dt.iso_week();
+        let mut keep = !policy.keeps_some() || i < policy.keep_last as usize;
+        if !days.contains(&day) && days.len() < policy.keep_daily as usize {
+            days.push(day);
+            keep = true;
+        }
+        if !weeks.contains(&week) && weeks.len() < policy.keep_weekly as usize {
+            weeks.push(week);
+            keep = true;
+        }
+        if policy.max_age > 0 && now - dt > Duration::days(policy.max_age as i64) {
+            keep = false;
+        }
+        if !keep && i > 0 {
+            pruned.push(info.name);
+        }
+    }
+    pruned
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Local, TimeZone};
+
+    use crate::store::backup::BackupInfo;
+
+    use super::Policy;
+
+    // A backup every six hours over 30 days, the newest first
+    fn backups() -> Vec<BackupInfo> {
+        let now = Local.with_ymd_and_hms(2024, 3, 31, 23, 0, 0).unwrap();
+        (0..120)
+            .map(|i| {
+                let dt = now - Duration::hours(6 * i);
+                BackupInfo::new(format!("b{i:03}"), dt, "0.10.0".to_string(), b"", None)
+            })
+            .collect()
+    }
+
+    fn prune(policy: Policy) -> Vec<String> {
+        let now = Local.with_ymd_and_hms(2024, 4, 1, 0, 0, 0).unwrap();
+        super::prune(&policy, &backups(), now)
+    }
+
+    #[test]
+    fn policies() {
+        assert!(prune(Policy::default()).is_empty());
+        let last = Policy {
+            keep_last: 5,
+            ..Default::default()
+        };
+        assert_eq!(prune(last).len(), 115);
+        assert!(!prune(last).contains(&"b004".to_string()));
+
+        // The newest backup of each day (23:00), and of each week
+        let daily = Policy {
+            keep_daily: 7,
+            keep_weekly: 4,
+            ..Default::default()
+        };
+        let kept: Vec<String> = (0..120)
+            .map(|i| format!("b{i:03}"))
+            .filter(|name| !prune(daily).contains(name))
+            .collect();
+        // 2024-03-31 is a Sunday, so the weeks start with that day's backup,
+        // and then those of the Sundays before it
+        assert_eq!(
+            kept,
+            vec!["b000", "b004", "b008", "b012", "b016", "b020", "b024", "b028", "b056", "b084"]
+        );
+
+        // Nothing older than 10 days, but never the newest backup
+        let young = Policy {
+            max_age: 10,
+            ..Default::default()
+        };
+        assert_eq!(prune(young).len(), 120 - 40);
+        let recent = Policy {
+            keep_last: 3,
+            max_age: 1,
+            ..Default::default()
+        };
+        assert_eq!(prune(recent).len(), 117);
+        let gone = Policy {
+            max_age: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            super::prune(&gone, &backups()[100..], Local::now()).len(),
+            19
+        );
+    }
+}
@@ -1,11 +1,37 @@
-use rucksack_lib::time;
+use rucksack_lib::{time, util};
 
-use crate::records::{v040, v060, v070, v080, v090};
+use crate::crypto::keyslot::{KeySlot, SlotKind, DEFAULT_SLOT};
+use crate::crypto::{legacy, Cipher, KdfParams, Key};
+use crate::db::versioned::VersionedDB;
+use crate::records::{v0100, v040, v060, v070, v080, v090};
 
 pub fn store_pwd() -> String {
     "abc123".to_string()
 }
 
+// Cheap Argon2 costs, so that tests don't spend their time deriving keys
+pub fn kdf_params() -> KdfParams {
+    KdfParams::with_costs(1024, 1, 1)
+}
+
+pub fn store_key() -> Key {
+    kdf_params().derive(&store_pwd()).unwrap()
+}
+
+// A password slot for the given DEK, unlocked by `store_pwd()`
+pub fn key_slot(dek: &Key, cipher: Cipher) -> KeySlot {
+    KeySlot::new(
+        DEFAULT_SLOT,
+        SlotKind::Password,
+        kdf_params(),
+        &store_pwd(),
+        None,
+        dek,
+        cipher,
+    )
+    .unwrap()
+}
+
 pub fn plaintext_record_v040() -> v040::DecryptedRecord {
     let now = time::now();
     let epoch_zero = time::epoch_zero();
@@ -67,3 +93,17 @@ pub fn plaintext_record_v090() -> v090::DecryptedRecord {
     dr.set_password("6 s3kr1t".to_string());
     dr
 }
+
+pub fn plaintext_record_v0100() -> v0100::DecryptedRecord {
+    v0100::migrate_decrypted_record_from_v090(plaintext_record_v090())
+}
+
+// A 0.9.0 database file holding `plaintext_record_v090`, written the way it
+// used to be
+pub fn legacy_db_v090(pwd: &str, salt: &str) -> Vec<u8> {
+    let dpr = plaintext_record_v090();
+    let sorted = vec![(dpr.key(), dpr.encrypt(pwd.to_string(), salt.to_string()))];
+    let encoded = bincode::encode_to_vec(sorted, util::bincode_cfg()).unwrap();
+    let vsn_db = VersionedDB::new(encoded, "0.9.0".to_string());
+    legacy::encrypt(vsn_db.serialise().unwrap(), pwd, salt)
+}
@@ -26,6 +26,27 @@ pub fn abs_path(path_name: String) -> io::Result<path::PathBuf> {
     Ok(absolute_path)
 }
 
+// Add to the end of a file (creating it, if need be), and sync it, so that
+// what's appended survives a crash once this returns; a crash before then may
+// leave only some of it there.
+pub fn append(data: &[u8], path: String) -> Result<()> {
+    let ap = create_parents(path.clone())?;
+    log::debug!("Appending to file {:?} ...", ap);
+    let r = (|| {
+        let mut file = fs::OpenOptions::new().append(true).create(true).open(&ap)?;
+        file.write_all(data)?;
+        file.sync_data()
+    })();
+    match r {
+        Ok(x) => Ok(x),
+        Err(e) => {
+            let msg = "Could not append to file";
+            log::error!("{} {} ({:})", msg, path, e);
+            Err(anyhow!("{} {} ({:})", msg, path, e))
+        }
+    }
+}
+
 pub fn backup_dir(project: &str) -> path::PathBuf {
     let mut path = dirs::data_dir().unwrap();
     path.push(project);
@@ -46,6 +67,23 @@ pub fn config_file(project: &str) -> String {
     path.to_str().unwrap().to_string()
 }
 
+// Copy a file the way `write_atomic` writes one; the copy has the same
+// permissions as the original.
+pub fn copy_atomic(src: String, dest: String) -> Result<()> {
+    let mut from = match fs::File::open(expanded_name(src.clone())) {
+        Ok(x) => x,
+        Err(e) => {
+            let msg = "Could not copy file";
+            log::error!("{msg} {src:?} ({e:})");
+            return Err(anyhow!("{msg} {src:?} ({e:})"));
+        }
+    };
+    replace(dest, |file| {
+        file.set_permissions(from.metadata()?.permissions())?;
+        io::copy(&mut from, file).map(|_| ())
+    })
+}
+
 pub fn create_parents(path: String) -> Result<path::PathBuf> {
     // Make sure the path is created
     log::debug!("Attempting to create parent directory of {path} ...");
@@ -137,6 +175,7 @@ pub fn write(data: Vec<u8>, path: String) -> Result<()> {
     let mut file = match std::fs::OpenOptions::new()
         .write(true)
         .create(true)
+        .truncate(true)
         .open(ap)
     {
         Ok(x) => Ok(x),
@@ -156,3 +195,128 @@ pub fn write(data: Vec<u8>, path: String) -> Result<()> {
         }
     }
 }
+
+// Write a file so that it's never left half-written (by a crash or a full
+// disk, say): the data goes to a temporary file in the same directory, which
+// is synced and then renamed over the original, and then the directory is
+// synced so that the rename is, too. If anything fails, the original (if
+// there is one) is left as it was.
+pub fn write_atomic(data: &[u8], path: String) -> Result<()> {
+    replace(path, |file| file.write_all(data))
+}
+
+// The guts of `write_atomic`, with the writing of the temporary file left to
+// `fill`
+fn replace<F>(path: String, fill: F) -> Result<()>
+where
+    F: FnOnce(&mut fs::File) -> io::Result<()>,
+{
+    let ap = create_parents(path.clone())?;
+    let tmp = temp_path(&ap);
+    log::debug!("Writing file {:?} (by way of {:?}) ...", ap, tmp);
+    let r = (|| {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp)?;
+        if let Ok(metadata) = fs::metadata(&ap) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        fill(&mut file)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &ap)?;
+        match ap.parent() {
+            Some(dir) => fs::File::open(dir)?.sync_all(),
+            None => Ok(()),
+        }
+    })();
+    if let Err(e) = r {
+        let _ = fs::remove_file(&tmp);
+        let msg = "Could not write file";
+        log::error!("{} {} ({:})", msg, path, e);
+        return Err(anyhow!("{} {} ({:})", msg, path, e));
+    }
+    Ok(())
+}
+
+// A hidden file next to `path`, with a name that won't clash with another
+// write's
+fn temp_path(path: &path::Path) -> path::PathBuf {
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => String::new(),
+    };
+    path.with_file_name(format!(".{name}.{}.tmp", uuid::Uuid::new_v4()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io, path};
+
+    fn temp_dir() -> path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("rucksack-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn names(dir: &path::Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn write_and_copy_atomic() {
+        let dir = temp_dir();
+        let file = dir.join("secrets.db").display().to_string();
+        let copy = dir.join("copy.db").display().to_string();
+        assert!(super::write_atomic(b"one", file.clone()).is_ok());
+        assert!(super::write_atomic(b"two", file.clone()).is_ok());
+        assert_eq!(fs::read(&file).unwrap(), b"two");
+        assert!(super::copy_atomic(file.clone(), copy.clone()).is_ok());
+        assert_eq!(fs::read(&copy).unwrap(), b"two");
+        assert!(super::copy_atomic(dir.join("nope").display().to_string(), copy.clone()).is_err());
+        assert_eq!(names(&dir), vec!["copy.db", "secrets.db"]);
+        assert!(super::append(b"three", file.clone()).is_ok());
+        assert_eq!(fs::read(&file).unwrap(), b"twothree");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn write_truncates() {
+        let dir = temp_dir();
+        let file = dir.join("secrets.db").display().to_string();
+        assert!(super::write(b"a longer original".to_vec(), file.clone()).is_ok());
+        // Nothing of the original is left past the end of what's written
+        assert!(super::write(b"short".to_vec(), file.clone()).is_ok());
+        assert_eq!(fs::read(&file).unwrap(), b"short");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failed_writes() {
+        let dir = temp_dir();
+        let file = dir.join("secrets.db").display().to_string();
+        assert!(super::write_atomic(b"original", file.clone()).is_ok());
+
+        // The disk fills up partway through
+        let r = super::replace(file.clone(), |f| {
+            io::Write::write_all(f, b"trunc")?;
+            Err(io::Error::other("no space left on device"))
+        });
+        assert!(r.is_err());
+        assert_eq!(fs::read(&file).unwrap(), b"original");
+        assert_eq!(names(&dir), vec!["secrets.db"]);
+
+        // The rename fails (there's a directory in the way)
+        let blocked = dir.join("blocked");
+        fs::create_dir_all(blocked.join("inside")).unwrap();
+        assert!(super::write_atomic(b"new", blocked.display().to_string()).is_err());
+        assert!(blocked.join("inside").is_dir());
+        assert_eq!(names(&dir), vec!["blocked", "secrets.db"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
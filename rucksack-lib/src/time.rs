@@ -1,5 +1,5 @@
 use chrono::offset::Local;
-use chrono::{DateTime, TimeZone, Utc};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
 
 pub fn simple_timestamp() -> String {
     format_datetime(chrono::offset::Local::now())
@@ -9,6 +9,20 @@ pub fn format_datetime(dt: DateTime<Local>) -> String {
     dt.format("%Y%m%d-%H%M%S").to_string()
 }
 
+// The inverse of `format_datetime`
+pub fn parse_datetime(stamp: &str) -> Option<DateTime<Local>> {
+    let naive = NaiveDateTime::parse_from_str(stamp, "%Y%m%d-%H%M%S").ok()?;
+    Local.from_local_datetime(&naive).earliest()
+}
+
+// The start of a day given as YYYY-MM-DD, in local time
+pub fn parse_date(date: &str) -> Option<DateTime<Local>> {
+    let naive = NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d").ok()?;
+    Local
+        .from_local_datetime(&naive.and_hms_opt(0, 0, 0)?)
+        .earliest()
+}
+
 pub fn now() -> String {
     Local::now().to_rfc3339()
 }
@@ -38,4 +52,15 @@ mod tests {
     fn epoch_zero() {
         assert_eq!(super::epoch_zero(), "1970-01-01T00:00:00+00:00");
     }
+
+    #[test]
+    fn parse_datetime() {
+        let stamp = super::simple_timestamp();
+        let dt = super::parse_datetime(&stamp).unwrap();
+        assert_eq!(super::format_datetime(dt), stamp);
+        assert!(super::parse_datetime("secrets.db").is_none());
+        let day = super::parse_date("2024-03-31").unwrap();
+        assert_eq!(super::format_datetime(day), "20240331-000000");
+        assert!(super::parse_date("2024-02-30").is_none());
+    }
 }
@@ -3,7 +3,9 @@ use std::path;
 use anyhow::Result;
 use clap::ArgMatches;
 
+use rucksack_db::crypto::{keyfile, Key};
 use rucksack_db::db::DB;
+use rucksack_db::store::manager::{self, StoreManager};
 use rucksack_lib::file;
 
 use crate::command;
@@ -40,7 +42,7 @@ impl App {
     pub fn config_path(&self) -> path::PathBuf {
         let mut path = path::PathBuf::new();
         path.push(self.inputs.config_file());
-        return path.parent().unwrap().to_path_buf();
+        path.parent().unwrap().to_path_buf()
     }
 
     pub fn data_dir(&self) -> String {
@@ -71,7 +73,7 @@ impl App {
 
     pub fn run(&self, matches: &ArgMatches) -> Result<()> {
         log::info!("Executing rucksack command ...");
-        if !self.backup_path().exists() {
+        if !self.inputs.ephemeral() && !self.backup_path().exists() {
             log::debug!("Checking for backup dir {:?} ...", self.backup_dir());
             file::create_dirs(self.backup_path())?;
             log::info!("Created backup dir.");
@@ -103,7 +105,9 @@ pub fn setup_db(inputs: &Inputs, cmd: String) -> Result<DB> {
             "Database not needed for the '{}' command; skipping load ...",
             cmd
         );
-        return Ok(DB::new(inputs.db_file(), inputs.backup_dir(), None, None));
+        let mut db = DB::new(inputs.db_file(), inputs.backup_dir(), None, None);
+        db.set_manager(store_manager(inputs)?);
+        return Ok(db);
     }
     log::debug!("Database is needed; preparing for read ...");
     let mut db = DB::new(
@@ -112,6 +116,26 @@ pub fn setup_db(inputs: &Inputs, cmd: String) -> Result<DB> {
         Some(inputs.db_passwd()),
         Some(inputs.salt()),
     );
+    db.set_manager(store_manager(inputs)?);
+    db.set_lock(inputs.lock_mode(), inputs.lock_wait());
+    db.set_retention(inputs.backup_policy());
+    db.set_kdf(inputs.kdf_params());
+    db.set_cipher(inputs.cipher()?);
+    db.set_sealed_metadata(inputs.db.seal_metadata);
+    db.set_key_file(key_file(inputs)?);
     db.open()?;
     Ok(db)
 }
+
+// The storage backend named in the config (`[db] backend`), or the default;
+// `--ephemeral` always means the in-memory one.
+pub fn store_manager(inputs: &Inputs) -> Result<Box<dyn StoreManager>> {
+    manager::from_name(&inputs.backend())
+}
+
+pub fn key_file(inputs: &Inputs) -> Result<Option<Key>> {
+    match inputs.key_file() {
+        Some(path) => Ok(Some(keyfile::read(&path)?)),
+        None => Ok(None),
+    }
+}
@@ -17,6 +17,14 @@ pub fn pwd() -> Arg {
         .global(true)
 }
 
+pub fn key_file() -> Arg {
+    Arg::new("key-file")
+        .help("A key file that is needed, along with the password, to unlock the database")
+        .long("key-file")
+        .env("RUXAK_KEY_FILE")
+        .global(true)
+}
+
 pub fn salt() -> Arg {
     Arg::new("salt")
         .help("The salt to use for encrypting the database")
@@ -26,6 +34,43 @@ pub fn salt() -> Arg {
         .global(true)
 }
 
+pub fn new_pwd() -> Arg {
+    Arg::new("new-db-pass")
+        .help("The new password to encrypt the database with")
+        .long("new-db-pass")
+        .env("RUXAK_NEW_DB_PASS")
+}
+
+pub fn slot() -> Arg {
+    Arg::new("slot")
+        .help("The name of a key slot (a password or recovery key that unlocks the database)")
+        .long("slot")
+        .env("RUXAK_SLOT")
+}
+
+pub fn shares() -> Arg {
+    Arg::new("shares")
+        .help("The number of shares to split the database's key into")
+        .long("shares")
+        .value_parser(clap::value_parser!(u8).range(2..))
+        .default_value("5")
+}
+
+pub fn threshold() -> Arg {
+    Arg::new("threshold")
+        .help("The number of shares needed to recover the database's key")
+        .long("threshold")
+        .value_parser(clap::value_parser!(u8).range(2..))
+        .default_value("3")
+}
+
+pub fn share() -> Arg {
+    Arg::new("share")
+        .help("A share of the database's key (may be given more than once; prompted for if not given)")
+        .long("share")
+        .action(clap::ArgAction::Append)
+}
+
 pub fn backup_dir() -> Arg {
     Arg::new("backup-dir")
         .help("Path for database backups")
@@ -54,6 +99,17 @@ pub fn needed() -> Arg {
         .global(true)
 }
 
+// For commands that only read the database, which is then opened with a
+// shared lock rather than an exclusive one
+pub fn read_only() -> Arg {
+    Arg::new("db-read-only")
+        .hide(true)
+        .long("db-read-only")
+        .value_parser(clap::builder::BoolValueParser::new())
+        .default_value("true")
+        .global(true)
+}
+
 pub fn serialised_format() -> Arg {
     Arg::new("format")
         .help("the de/serialisation format to use for import/export")
@@ -70,7 +70,6 @@ pub fn user() -> Arg {
         .short('u')
         .long("user")
         .env("RUXAK_USER")
-        .global(true)
 }
 
 pub fn user_old() -> Arg {
@@ -83,7 +82,6 @@ pub fn user_old() -> Arg {
 pub fn user_new() -> Arg {
     Arg::new("new-user")
         .help("The new user login name to use")
-        .short('u')
         .long("new-user")
 }
 
@@ -99,7 +97,6 @@ pub fn url() -> Arg {
         .help("the login URL")
         .long("url")
         .env("RUXAK_URL")
-        .global(true)
 }
 
 pub fn url_old() -> Arg {
@@ -1,4 +1,4 @@
-use clap::Arg;
+use clap::{Arg, ArgAction};
 
 #[doc(hidden)]
 pub fn config() -> Arg {
@@ -9,6 +9,26 @@ pub fn config() -> Arg {
         .global(true)
 }
 
+#[doc(hidden)]
+pub fn ephemeral() -> Arg {
+    Arg::new("ephemeral")
+        .help("Keep the database (and its backups) in memory only; nothing is saved once rucksack exits")
+        .long("ephemeral")
+        .env("RUXAK_EPHEMERAL")
+        .action(ArgAction::SetTrue)
+        .global(true)
+}
+
+#[doc(hidden)]
+pub fn lock_wait() -> Arg {
+    Arg::new("lock-wait")
+        .help("How long (in seconds) to wait for another rucksack process to unlock the database")
+        .long("lock-wait")
+        .env("RUXAK_LOCK_WAIT")
+        .value_parser(clap::value_parser!(u64))
+        .global(true)
+}
+
 #[doc(hidden)]
 pub fn log_level() -> Arg {
     Arg::new("log-level")
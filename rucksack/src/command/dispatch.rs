@@ -3,19 +3,24 @@ use clap::ArgMatches;
 
 use crate::app::App;
 
-use super::handlers::{add, backup, config, dedupe, delete, export, gen, import, list, set, show};
+use super::handlers::{
+    add, backup, config, db, dedupe, delete, export, gen, import, list, set, show,
+};
 
 pub fn run(app: &App, matches: &ArgMatches) -> Result<()> {
     match matches.subcommand() {
         Some(("add", add_matches)) => add::new(add_matches, app),
         Some(("backup", backup_matches)) => match backup_matches.subcommand() {
             Some(("delete", delete_matches)) => backup::delete(delete_matches, app),
+            Some(("diff", diff_matches)) => backup::diff(diff_matches, app),
             Some(("restore", restore_matches)) => backup::restore(restore_matches, app),
+            Some(("verify", verify_matches)) => backup::verify(verify_matches, app),
             Some((&_, _)) => todo!(),
             None => backup::run(backup_matches, app),
         },
         Some(("backups", backup_matches)) => match backup_matches.subcommand() {
             Some(("list", list_matches)) => backup::list(list_matches, app),
+            Some(("prune", prune_matches)) => backup::prune(prune_matches, app),
             Some((&_, _)) => todo!(),
             None => todo!(),
         },
@@ -24,6 +29,22 @@ pub fn run(app: &App, matches: &ArgMatches) -> Result<()> {
             Some((&_, _)) => todo!(),
             None => todo!(),
         },
+        Some(("db", db_matches)) => match db_matches.subcommand() {
+            Some(("compact", compact_matches)) => db::compact(compact_matches, app),
+            Some(("rekey", rekey_matches)) => db::rekey(rekey_matches, app),
+            Some(("keyslot", keyslot_matches)) => match keyslot_matches.subcommand() {
+                Some(("add", add_matches)) => db::keyslot_add(add_matches, app),
+                Some(("list", list_matches)) => db::keyslot_list(list_matches, app),
+                Some(("remove", remove_matches)) => db::keyslot_remove(remove_matches, app),
+                Some((&_, _)) => todo!(),
+                None => todo!(),
+            },
+            Some(("seal-metadata", seal_matches)) => db::seal_metadata(seal_matches, app),
+            Some(("split", split_matches)) => db::split(split_matches, app),
+            Some(("recover", recover_matches)) => db::recover(recover_matches, app),
+            Some((&_, _)) => todo!(),
+            None => todo!(),
+        },
         Some(("dedupe", dedupe_matches)) => dedupe::new(dedupe_matches, app),
         Some(("delete", delete_matches)) => delete::one(delete_matches, app),
         Some(("export", export_matches)) => export::new(export_matches, app),
@@ -54,6 +75,7 @@ pub fn run(app: &App, matches: &ArgMatches) -> Result<()> {
             Some(("config", cfg_matches)) => show::config(cfg_matches, app),
             Some(("data-dir", datadir_matches)) => show::data_dir(datadir_matches, app),
             Some(("db-file", dbfile_matches)) => show::db_file(dbfile_matches, app),
+            Some(("db-info", dbinfo_matches)) => show::db_info(dbinfo_matches, app),
             Some(("db-version", dbvsn_matches)) => show::db_version(dbvsn_matches, app),
             Some(("tags", tag_matches)) => show::tags(tag_matches, app),
             Some(("types", type_matches)) => show::types(type_matches, app),
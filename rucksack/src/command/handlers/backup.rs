@@ -18,37 +18,131 @@
 //! rucksack backups list --latest
 //! ```
 //!
+//! Or just those made between two dates (both included; either may be left
+//! out):
+//!
+//! ```shell
+//! rucksack backups list --since 2024-03-01 --until 2024-03-31
+//! ```
+//!
+//! Each backup is listed with when it was made, the schema version of the DB
+//! it was made from, its size, how many records it has, and (the start of)
+//! its SHA-256 checksum. These come from a manifest file kept next to each
+//! backup (`<name>.toml`); for backups made before there were manifests, the
+//! number of records and the checksum aren't known.
+//!
 //! Delete a specific backup:
 //!
 //! ```shell
 //! rucksack backup delete <name from list command>
 //! ```
 //!
+//! See what restoring a backup would change, before restoring it: the records
+//! added since it was made (which restoring it would lose), those removed
+//! since (which it would bring back), and those changed since, with the fields
+//! that changed. Changed secrets are masked unless `--reveal` is given. Give
+//! the name of another backup to compare the two instead:
+//!
+//! ```shell
+//! rucksack backup diff <name from list command> [<other name>]
+//! ```
+//!
 //! Restore the DB from a previous backup:
 //!
 //! ```shell
 //! rucksack backup restore <name from list command>
 //! ```
 //!
+//! Check that backups could be restored: that each decrypts with the DB's
+//! key, that its records can be read (and migrated to the current schema
+//! version, if they're older), and that it has as many records as its
+//! manifest says. Give a name to check just that backup. If any backup fails,
+//! the command exits with an error, so it can be run from cron:
+//!
+//! ```shell
+//! rucksack backup verify
+//! ```
+//!
+//! Backups are made whenever the DB file is rewritten, and nothing removes
+//! them unless the `[backups]` section of the config file says which to keep:
+//!
+//! ```toml
+//! [backups]
+//! keep_last = 10     # the newest 10
+//! keep_daily = 7     # the newest of each of the last 7 days with any
+//! keep_weekly = 4    # the newest of each of the last 4 weeks with any
+//! max_age_days = 90  # none older than this, whatever the above keep
+//! ```
+//!
+//! A backup is kept if any of the `keep_` rules keeps it (the newest one
+//! always is). With any of these set, backups are pruned after each new one is
+//! made; to prune them now (or, with `--dry-run`, see what would be pruned):
+//!
+//! ```shell
+//! rucksack backups prune --dry-run
+//! ```
+//!
 use anyhow::{anyhow, Result};
 use clap::ArgMatches;
 
-use rucksack_db::store::backup;
-use rucksack_lib::file;
+use rucksack_db::db::diff;
+use rucksack_db::store::backup::BackupInfo;
 
 use crate::app::App;
 use crate::input::options;
 use crate::output::{result, table, Column, Opts};
 
+// How many hex digits of each backup's checksum `list` shows
+const CHECKSUM_SHOWN: usize = 12;
+// What `diff` shows for a changed secret, unless asked to reveal it
+const MASKED: &str = "**********";
+
 pub fn delete(matches: &ArgMatches, app: &App) -> Result<()> {
-    let backup_path = app.backup_path();
     let backup_name = options::backup_name(matches);
     log::debug!("Preparing to delete backup DB file '{}'", backup_name);
-    if !backup_path.exists() {
-        log::error!("Cannot find file {}", backup_path.display());
-        return Err(anyhow!("backup file '{}' does not exist", backup_name));
+    app.db.delete_backup(&backup_name)
+}
+
+pub fn diff(matches: &ArgMatches, app: &App) -> Result<()> {
+    let backup_name = options::backup_name(matches);
+    let old = app.db.backup_records(&backup_name)?;
+    let (new, other) = match options::backup_other(matches) {
+        Some(other) => (app.db.backup_records(&other)?, other),
+        None => (
+            app.db.collect_decrypted()?,
+            "the DB as it is now".to_string(),
+        ),
+    };
+    let reveal = options::reveal(matches);
+    let d = diff::diff(&old, &new);
+    println!("\nChanges from {backup_name} to {other}:\n");
+    if d.is_empty() {
+        println!("None\n");
+        return Ok(());
+    }
+    for key in &d.added {
+        println!("+ {key}");
+    }
+    for key in &d.removed {
+        println!("- {key}");
+    }
+    for (key, changes) in &d.modified {
+        println!("~ {key}");
+        for c in changes {
+            let (old, new) = match c.secret && !reveal {
+                true => (MASKED.to_string(), MASKED.to_string()),
+                false => (c.old.clone(), c.new.clone()),
+            };
+            println!("    {}: {old} -> {new}", c.field);
+        }
     }
-    file::delete(backup_path)
+    println!(
+        "\n{} added, {} removed, {} modified\n",
+        d.added.len(),
+        d.removed.len(),
+        d.modified.len()
+    );
+    Ok(())
 }
 
 pub fn list(matches: &ArgMatches, app: &App) -> Result<()> {
@@ -59,18 +153,33 @@ pub fn list(matches: &ArgMatches, app: &App) -> Result<()> {
         latest_only: options::latest(matches),
         ..Default::default()
     };
-    let backups: file::Listing = if opts.latest_only {
-        vec![backup::latest(backup_dir)?]
-    } else {
-        backup::list(backup_dir)?
-    };
+    let (since, until) = (options::since(matches), options::until(matches));
+    let mut backups: Vec<BackupInfo> = app
+        .db
+        .backups()?
+        .into_iter()
+        .filter(|b| b.made_between(since, until))
+        .collect();
+    if opts.latest_only {
+        backups.truncate(1);
+    }
     let mut results: Vec<result::ResultRow> = Vec::new();
-    for (name, _, perms) in backups {
+    for info in backups {
         let mut r = result::ResultRow {
             ..Default::default()
         };
-        r.add(Column::Name, name);
-        r.add(Column::Permissions, perms);
+        r.add(Column::Name, info.name);
+        r.add(
+            Column::Created,
+            info.created.format("%Y-%m-%d %H:%M:%S").to_string(),
+        );
+        r.add(Column::Version, info.version);
+        r.add(Column::Size, info.size.to_string());
+        r.add(
+            Column::Records,
+            info.records.map_or("-".to_string(), |n| n.to_string()),
+        );
+        r.add(Column::Checksum, short_checksum(&info.checksum));
         results.push(r);
     }
     let mut t = table::new(results.to_owned(), opts);
@@ -79,33 +188,116 @@ pub fn list(matches: &ArgMatches, app: &App) -> Result<()> {
     Ok(())
 }
 
+pub fn prune(matches: &ArgMatches, app: &App) -> Result<()> {
+    let dry_run = options::dry_run(matches);
+    if app.inputs.backup_policy().is_empty() {
+        println!("\nNo backup retention rules are configured; nothing to prune\n");
+        return Ok(());
+    }
+    let pruned = app.db.prune_backups(dry_run)?;
+    let verb = if dry_run { "Would delete" } else { "Deleted" };
+    println!("\n{verb} {} backup files", pruned.len());
+    for name in &pruned {
+        println!("    {name}");
+    }
+    println!();
+    Ok(())
+}
+
 pub fn restore(matches: &ArgMatches, app: &App) -> Result<()> {
-    let backup_dir = app.backup_dir();
     let mut backup_name = options::backup_name(matches);
     if backup_name.is_empty() {
-        let (_, latest, _) = backup::latest(backup_dir)?;
-        backup_name = latest;
+        backup_name = app.db.latest_backup()?.name;
     }
-    // Do a backup before we go any further
-    run(matches, app)?;
-    backup::restore(app.backup_path(), backup_name.clone(), app.db_path())?;
+    // Do a backup before we go any further, but don't prune the backups until
+    // the one being restored has been
+    let backup_file = app.db.backup_unpruned()?;
+    log::debug!("Backed up database to {backup_file}");
+    app.db.restore_backup(&backup_name)?;
+    app.db.prune_backups(false)?;
     log::info!("Successfully restored {backup_name} to {}", app.db_file());
     Ok(())
 }
 
+// Enough of a checksum to tell backups apart at a glance
+fn short_checksum(checksum: &str) -> String {
+    if checksum.is_empty() {
+        return "-".to_string();
+    }
+    checksum.chars().take(CHECKSUM_SHOWN).collect()
+}
+
 pub fn run(_matches: &ArgMatches, app: &App) -> Result<()> {
     log::debug!("Backing up database ...");
-    let r = backup::copy(
-        app.db_file(),
-        app.backup_dir(),
-        app.db_version().to_string(),
-    );
-    let backup_file: String = match r {
-        Ok(b) => b,
-        Err(e) => {
-            return Err(anyhow!(e));
+    let backup_file = app.db.backup()?;
+    log::debug!("Backed up database to {backup_file}");
+    Ok(())
+}
+
+pub fn verify(matches: &ArgMatches, app: &App) -> Result<()> {
+    let backup_name = options::backup_name(matches);
+    let mut backups = app.db.backups()?;
+    if !backup_name.is_empty() {
+        backups.retain(|b| b.name == backup_name);
+        if backups.is_empty() {
+            return Err(anyhow!("backup file '{backup_name}' does not exist"));
         }
+    }
+    let opts = Opts {
+        backup_verify: true,
+        ..Default::default()
     };
-    log::debug!("Backed up database to {backup_file}");
+    let mut results: Vec<result::ResultRow> = Vec::new();
+    let mut failed = Vec::new();
+    for info in &backups {
+        let v = app.db.verify_backup(info)?;
+        let done = |step: bool| if step { "ok" } else { "failed" }.to_string();
+        let mut r = result::ResultRow {
+            ..Default::default()
+        };
+        r.add(Column::Name, v.name.clone());
+        r.add(
+            Column::Version,
+            v.version
+                .as_ref()
+                .map_or("-".to_string(), |vsn| vsn.to_string()),
+        );
+        r.add(Column::Decrypted, done(v.decrypted));
+        r.add(
+            Column::Migrated,
+            if v.decrypted {
+                done(v.migrated)
+            } else {
+                "-".to_string()
+            },
+        );
+        r.add(
+            Column::Records,
+            v.records.map_or("-".to_string(), |n| n.to_string()),
+        );
+        r.add(
+            Column::Status,
+            if v.ok() { "OK" } else { "FAILED" }.to_string(),
+        );
+        results.push(r);
+        if let Some(e) = v.error {
+            failed.push((v.name, e));
+        }
+    }
+    let mut t = table::new(results, opts);
+    t.display();
+    println!();
+    for (name, e) in &failed {
+        println!("{name}: {e}");
+    }
+    if !failed.is_empty() {
+        println!();
+        return Err(anyhow!(
+            "{} of {} backups failed verification",
+            failed.len(),
+            backups.len()
+        ));
+    }
+    println!("{} backups verified\n", backups.len());
     Ok(())
 }
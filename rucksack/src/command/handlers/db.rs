@@ -0,0 +1,246 @@
+//! # Working with the Secrets Database
+//!
+//! Change the password used to encrypt the database:
+//!
+//! ```shell
+//! rucksack db rekey
+//! ```
+//!
+//! You will be prompted for the current password and then the new one (or
+//! they may be given with `--db-pass` and `--new-db-pass`). The database is
+//! backed up before anything is changed.
+//!
+//! A database can be unlocked by more than one password, or by a recovery key
+//! (a 24-word phrase that rucksack generates); each of these is a "key slot".
+//! Add a password, or a recovery key, and list or remove them:
+//!
+//! ```shell
+//! rucksack db keyslot add --slot alice
+//! rucksack db keyslot add --slot offline --recovery
+//! rucksack db keyslot list
+//! rucksack db keyslot remove --slot alice
+//! ```
+//!
+//! A recovery key is only displayed when it's created. To use it, give it in
+//! place of the password; with it, a forgotten password can be reset:
+//!
+//! ```shell
+//! rucksack db rekey --slot default
+//! ```
+//!
+//! None of these re-encrypt the records in the database, and the
+//! last remaining key slot can't be removed.
+//!
+//! A key file can be required along with a password: create one with
+//! `rucksack gen --type keyfile --output <path>`, and give it with
+//! `--key-file` (or `RUXAK_KEY_FILE`, or `key_file` in the `[db]` section of
+//! the config file). Password key slots created or re-keyed while a key file
+//! is given will need it from then on:
+//!
+//! ```shell
+//! rucksack db rekey --key-file ~/.rucksack.key
+//! ```
+//!
+//! To stop needing it, re-key with both `--key-file` and `--no-key-file`.
+//!
+//! Encrypt the metadata (name, URL, category, tags, etc.) of every record,
+//! and store records under opaque IDs instead of their keys:
+//!
+//! ```shell
+//! rucksack db seal-metadata
+//! ```
+//!
+//! Filtering with `list` keeps working, by way of a keyed blind index. New
+//! databases can be created this way by setting `seal_metadata = true` in the
+//! `[db]` section of the config file.
+//!
+//! For emergencies (e.g., every password and recovery key has been lost), the
+//! database's key itself can be split into Shamir shares, any threshold of
+//! which recover the database:
+//!
+//! ```shell
+//! rucksack db split --shares 5 --threshold 3
+//! rucksack db recover
+//! ```
+//!
+//! `recover` prompts for shares until an empty one is entered (or they may be
+//! given with `--share`, once for each), and then for a new password for the
+//! `default` key slot. Fewer shares than the threshold reveal nothing about
+//! the key; shares stay valid until the database's key itself changes.
+//!
+//! Changes to records aren't written to the database file itself, but to a
+//! journal next to it, which is folded into the file (backing up the file it
+//! replaces) once it grows long, and whenever the database is backed up. To
+//! fold it in now:
+//!
+//! ```shell
+//! rucksack db compact
+//! ```
+//!
+use anyhow::{anyhow, Result};
+use clap::ArgMatches;
+use secrecy::ExposeSecret;
+
+use rucksack_db::crypto::keyslot::{self, SlotKind};
+use rucksack_db::db::DB;
+use rucksack_db::store::lock::LockMode;
+
+use crate::app::{self, App};
+use crate::input::options;
+
+pub fn rekey(matches: &ArgMatches, app: &App) -> Result<()> {
+    let mut db = open(app)?;
+    let new_pwd = options::new_db_pwd(matches)?;
+    if new_pwd.expose_secret().is_empty() {
+        return Err(anyhow!("the new database password cannot be empty"));
+    }
+    if matches.get_flag("no-key-file") {
+        db.set_key_file(None);
+    }
+    let backup_file = match options::slot(matches) {
+        Some(name) => db.rekey_slot(&name, new_pwd.expose_secret())?,
+        None => db.rekey(new_pwd.expose_secret())?,
+    };
+    db.close()?;
+    log::debug!("Backed up database to {backup_file} before re-keying");
+    println!("\nRe-keyed {}\n", app.db_file());
+    Ok(())
+}
+
+pub fn compact(_matches: &ArgMatches, app: &App) -> Result<()> {
+    let mut db = open(app)?;
+    db.compact()?;
+    db.close()?;
+    println!("\nCompacted {}\n", app.db_file());
+    Ok(())
+}
+
+pub fn keyslot_add(matches: &ArgMatches, app: &App) -> Result<()> {
+    let mut db = open(app)?;
+    let name = options::slot(matches).unwrap_or_default();
+    if options::recovery(matches) {
+        let recovery_key = keyslot::new_recovery_key();
+        db.add_key_slot(&name, SlotKind::Recovery, &recovery_key)?;
+        db.close()?;
+        println!("\nAdded recovery key slot {name:?}. The recovery key is:\n");
+        println!("    {recovery_key}\n");
+        println!("Keep it somewhere safe (and offline); it won't be shown again.\n");
+        return Ok(());
+    }
+    let pwd = options::new_db_pwd(matches)?;
+    if pwd.expose_secret().is_empty() {
+        return Err(anyhow!("the key slot's password cannot be empty"));
+    }
+    db.add_key_slot(&name, SlotKind::Password, pwd.expose_secret())?;
+    db.close()?;
+    println!("\nAdded password key slot {name:?}\n");
+    Ok(())
+}
+
+pub fn keyslot_list(_matches: &ArgMatches, app: &App) -> Result<()> {
+    let header = app.db.describe()?;
+    println!();
+    for slot in header.slots {
+        println!(
+            "{:<16} {:<9} {:<11} created {} (argon2id, memory: {} KiB, iterations: {}, parallelism: {})",
+            slot.name,
+            slot.kind.name(),
+            if slot.key_file { "+ key file" } else { "" },
+            slot.created,
+            slot.kdf.m_cost,
+            slot.kdf.t_cost,
+            slot.kdf.p_cost
+        );
+    }
+    println!();
+    Ok(())
+}
+
+pub fn keyslot_remove(matches: &ArgMatches, app: &App) -> Result<()> {
+    let mut db = open(app)?;
+    let name = options::slot(matches).unwrap_or_default();
+    db.remove_key_slot(&name)?;
+    db.close()?;
+    println!("\nRemoved key slot {name:?}\n");
+    Ok(())
+}
+
+pub fn seal_metadata(_matches: &ArgMatches, app: &App) -> Result<()> {
+    let mut db = open(app)?;
+    if db.sealed_metadata() {
+        println!("\nRecord metadata in {} is already sealed\n", app.db_file());
+        return Ok(());
+    }
+    db.seal_metadata()?;
+    db.close()?;
+    println!("\nSealed record metadata in {}\n", app.db_file());
+    Ok(())
+}
+
+pub fn split(matches: &ArgMatches, app: &App) -> Result<()> {
+    let db = open(app)?;
+    let threshold = options::threshold(matches);
+    let shares = db.split_key(options::shares(matches), threshold)?;
+    println!(
+        "\nSplit the key for {} into {} shares; any {threshold} of them recover it:\n",
+        app.db_file(),
+        shares.len()
+    );
+    for (i, share) in shares.iter().enumerate() {
+        println!("    {}. {share}", i + 1);
+    }
+    println!("\nGive each share to a different person (or keep each in a different place);");
+    println!("the shares won't be shown again.\n");
+    Ok(())
+}
+
+pub fn recover(matches: &ArgMatches, app: &App) -> Result<()> {
+    let manager = app::store_manager(&app.inputs)?;
+    if !manager.locate(&app.db_file())?.1 {
+        return Err(anyhow!("no database found at {}", app.db_file()));
+    }
+    let shares = options::key_shares(matches);
+    if shares.is_empty() {
+        return Err(anyhow!("no key shares were given"));
+    }
+    let new_pwd = options::new_db_pwd(matches)?;
+    if new_pwd.expose_secret().is_empty() {
+        return Err(anyhow!("the new database password cannot be empty"));
+    }
+    let mut db = DB::new(app.db_file(), app.backup_dir(), None, None);
+    db.set_manager(manager);
+    db.set_lock(LockMode::Exclusive, app.inputs.lock_wait());
+    db.set_retention(app.inputs.backup_policy());
+    db.set_kdf(app.inputs.kdf_params());
+    db.set_key_file(app::key_file(&app.inputs)?);
+    let backup_file = db.recover(&shares, new_pwd.expose_secret())?;
+    db.close()?;
+    log::debug!("Backed up database to {backup_file} before recovering");
+    println!(
+        "\nRecovered {}; key slot {:?} has the new password\n",
+        app.db_file(),
+        keyslot::DEFAULT_SLOT
+    );
+    Ok(())
+}
+
+// The top-level command doesn't load the DB, since the current password is
+// only needed by the subcommands
+fn open(app: &App) -> Result<DB> {
+    let manager = app::store_manager(&app.inputs)?;
+    if !manager.locate(&app.db_file())?.1 {
+        return Err(anyhow!("no database found at {}", app.db_file()));
+    }
+    let mut db = DB::new(
+        app.db_file(),
+        app.backup_dir(),
+        Some(app.inputs.db_passwd()),
+        Some(app.inputs.salt()),
+    );
+    db.set_manager(manager);
+    db.set_lock(LockMode::Exclusive, app.inputs.lock_wait());
+    db.set_retention(app.inputs.backup_policy());
+    db.set_key_file(app::key_file(&app.inputs)?);
+    db.open()?;
+    Ok(db)
+}
@@ -45,6 +45,16 @@
 //! Password score: 100.00
 //! ```
 //!
+//! A key file, to be needed along with the database password (see `rucksack
+//! db`). It's only readable by its owner, and an existing file is only replaced
+//! if `--force` is given:
+//!
+//! ```shell
+//! rucksack gen --type keyfile --output ~/.rucksack.key
+//!
+//! Wrote new key file: /home/alice/.rucksack.key
+//! ```
+//!
 //! Or how about a long random token, chock-a-block with tasty entropy?
 //!
 //! ```shell
@@ -58,14 +68,19 @@
 //! Password score: 100.00
 //! ```
 //!
-use anyhow::Result;
+use std::io::Write;
+
+use anyhow::{anyhow, Result};
 use clap::ArgMatches;
 
+use rucksack_db::crypto::keyfile;
+use rucksack_lib::file;
 use rucksack_lib::generator::{password, uuid};
 
 pub fn new(matches: &ArgMatches) -> Result<()> {
     let encode = matches.get_one::<bool>("encode");
     match matches.get_one::<String>("type").map(|s| s.as_str()) {
+        Some("keyfile") => generate_key_file(matches),
         Some("lipsum") => generate_pwd_lipsum(matches, encode),
         Some("random") => generate_pwd(matches, encode),
         Some("uuid") => generate_pwd_uuid(encode),
@@ -78,6 +93,42 @@ pub fn new(matches: &ArgMatches) -> Result<()> {
 
 // Generator type dispatch functions
 
+fn generate_key_file(matches: &ArgMatches) -> Result<()> {
+    let path = match matches.get_one::<String>("output") {
+        Some(path) => file::expanded_name(path.to_string()),
+        None => {
+            return Err(anyhow!(
+                "a key file needs a path to be written to (--output)"
+            ))
+        }
+    };
+    let exists = std::path::Path::new(&path).exists();
+    if exists && !matches.get_flag("force") {
+        return Err(anyhow!(
+            "{path} already exists; not overwriting it (use --force to replace it)"
+        ));
+    }
+    let ap = file::create_parents(path.clone())?;
+    if exists {
+        // Rather than truncating it, so that the new file never has the old
+        // one's permissions
+        std::fs::remove_file(&ap)?;
+    }
+    // The file is only ever readable by its owner, from the moment it's created
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut f = options.open(&ap)?;
+    f.write_all(keyfile::generate().as_bytes())?;
+    f.sync_all()?;
+    println!("\nWrote new key file: {path}\n");
+    Ok(())
+}
+
 fn generate_pwd(matches: &ArgMatches, encode: Option<&bool>) -> Result<()> {
     let length = matches.get_one::<usize>("length").unwrap();
     password::display_scored(password::rand(length), encode)
@@ -128,6 +128,7 @@ use clap::ArgMatches;
 use passwords::{analyzer, scorer};
 use sha2::{Digest, Sha256};
 
+use rucksack_db::db::query::Query;
 use rucksack_db::records;
 use rucksack_db::Status;
 use rucksack_lib::time;
@@ -225,7 +226,7 @@ pub fn passwords(matches: &ArgMatches, app: &App) -> Result<()> {
     opts.reveal = options::reveal(matches);
     opts.decrypted = options::decrypt(matches);
     let mut results: Vec<result::ResultRow> = Vec::new();
-    let record = query::record(app)?;
+    let record = query::subcommand_record(app, matches)?;
     let analyzed = analyzer::analyze(record.password());
     let score = scorer::score(&analyzed).trunc() as i64;
     let md = record.metadata();
@@ -320,7 +321,7 @@ fn process_records(matches: &ArgMatches, app: &App, mut opts: Opts) -> Result<()
     // now update all the records whose passwords were revealed:
     for r in results {
         if opts.reveal {
-            if let Some(mut metadata) = app.db.get_metadata(r.id()) {
+            if let Some(mut metadata) = app.db.get_metadata(r.id())? {
                 metadata.last_used = time::now();
                 metadata.access_count += 1;
                 app.db.update_metadata(r.id(), metadata);
@@ -342,9 +343,11 @@ fn extract_results(
     let exclude = matches.get_one::<String>("exclude");
     let max_score = matches.get_one::<f64>("max-score");
     let min_score = matches.get_one::<f64>("min-score");
+    let prefilter = prefilter(&opts, filter);
 
-    for i in app.db.iter() {
-        let record = i.value().decrypt(app.db.store_pwd(), app.inputs.salt())?;
+    // Skip records that can't match before paying for their decryption
+    for encrypted in app.db.query(&prefilter) {
+        let record = app.db.decrypt_record(&encrypted)?;
         let analyzed = analyzer::analyze(record.password());
         let score = scorer::score(&analyzed);
         let mut result = result::new(record.key(), record.name_or_user(), record.metadata().url);
@@ -375,12 +378,12 @@ fn extract_results(
             }
         }
         if let Some(check) = filter {
-            if !i.key().contains(check) {
+            if !record.key().contains(check) {
                 continue;
             }
         }
         if let Some(check) = exclude {
-            if i.key().contains(check) {
+            if record.key().contains(check) {
                 continue;
             }
         }
@@ -432,6 +435,23 @@ fn extract_results(
     Ok(result::ResultsAndGroups { results, groups })
 }
 
+// The filters that can be applied to records without decrypting them; the
+// checks made on the decrypted record are still needed, since blind index
+// matches aren't exact.
+fn prefilter(opts: &Opts, filter: Option<&String>) -> Query {
+    let mut query = Query::new();
+    if opts.category != *records::ANY_CATEGORY {
+        query.category = Some(opts.category.clone());
+    }
+    if opts.kind != records::Kind::Any {
+        query.kind = Some(opts.kind.clone());
+    }
+    query.all_tags = opts.all_tags.clone().unwrap_or_default();
+    query.any_tags = opts.any_tags.clone().unwrap_or_default();
+    query.filter = filter.cloned();
+    query
+}
+
 fn print_password_group(
     groups: result::GroupByString,
     sort_by: Option<&str>,
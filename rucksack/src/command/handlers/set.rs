@@ -65,7 +65,7 @@ use crate::input::{options, query, Flag};
 
 pub fn record_type(matches: &ArgMatches, app: &App) -> Result<()> {
     log::debug!("Setting record type ...");
-    let mut record = query::record(app)?;
+    let mut record = query::subcommand_record(app, matches)?;
     record.set_kind(options::record_kind(matches));
     app.db.insert(record);
     app.db.close()?;
@@ -74,7 +74,7 @@ pub fn record_type(matches: &ArgMatches, app: &App) -> Result<()> {
 
 pub fn password(matches: &ArgMatches, app: &App) -> Result<()> {
     log::debug!("Setting record password ...");
-    let mut record = query::record(app)?;
+    let mut record = query::subcommand_record(app, matches)?;
     let key = record.key();
     record.set_password(options::record_pwd_revealed(matches));
     app.db.update(key, record);
@@ -84,7 +84,7 @@ pub fn password(matches: &ArgMatches, app: &App) -> Result<()> {
 
 pub fn status(matches: &ArgMatches, app: &App) -> Result<()> {
     log::debug!("Setting record status ...");
-    let mut record = query::record(app)?;
+    let mut record = query::subcommand_record(app, matches)?;
     record.set_status(options::record_state(matches));
     app.db.insert(record);
     app.db.close()?;
@@ -1,6 +1,7 @@
 //! # Displaying Miscellaneous Data / Metadata
 //!
-//! If you need to see what version of the database file format you're currently using:
+//! If you need to see what version of the database file format you're currently
+//! using (this is read from the file's header, so no password is needed):
 //!
 //! ```shell
 //! rucksack show db-version
@@ -10,6 +11,14 @@
 //! rather it will correspond to the version of rucksack that was used when
 //! your secrets database was last updated.
 //!
+//! To see the schema version along with the cipher and key slots the
+//! database was written with, and each slot's key derivation (Argon2id)
+//! costs (also without a password):
+//!
+//! ```shell
+//! rucksack show db-info
+//! ```
+//!
 //! Display the default location of the config file:
 //!
 //! ```shell
@@ -79,15 +88,38 @@ pub fn db_file(_matches: &ArgMatches, app: &App) -> Result<()> {
     Ok(())
 }
 
+pub fn db_info(_matches: &ArgMatches, app: &App) -> Result<()> {
+    let header = app.db.describe()?;
+    println!();
+    println!("File:    {}", app.db_file());
+    println!("Backend: {}", app.db.backend());
+    println!("Schema:  {}", header.version);
+    println!("Cipher:  {}", header.cipher);
+    println!("Slots:");
+    for s in &header.slots {
+        let kind = match s.key_file {
+            true => format!("{} + key file", s.kind.name()),
+            false => s.kind.name().to_string(),
+        };
+        println!(
+            "    {} ({kind}): argon2id, memory: {} KiB, iterations: {}, parallelism: {}",
+            s.name, s.kdf.m_cost, s.kdf.t_cost, s.kdf.p_cost
+        );
+    }
+    println!();
+    Ok(())
+}
+
 pub fn db_version(_matches: &ArgMatches, app: &App) -> Result<()> {
-    println!("\n{}\n", app.db_version());
+    let header = app.db.describe()?;
+    println!("\n{}\n", header.version());
     Ok(())
 }
 
 pub fn categories(_matches: &ArgMatches, app: &App) -> Result<()> {
     let mut results: HashMap<String, bool> = HashMap::new();
     for i in app.db.iter() {
-        let dr = i.value().decrypt(app.db.store_pwd(), app.inputs.salt())?;
+        let dr = app.db.decrypt_record(i.value())?;
         results.insert(dr.metadata().category, true);
     }
     let mut cats: Vec<&String> = results.keys().clone().collect();
@@ -107,7 +139,7 @@ pub fn categories(_matches: &ArgMatches, app: &App) -> Result<()> {
 pub fn tags(_matches: &ArgMatches, app: &App) -> Result<()> {
     let mut results: HashMap<String, bool> = HashMap::new();
     for i in app.db.iter() {
-        let dr = i.value().decrypt(app.db.store_pwd(), app.inputs.salt())?;
+        let dr = app.db.decrypt_record(i.value())?;
         for t in dr.metadata().tags {
             results.insert(t.display_or_value(), true);
         }
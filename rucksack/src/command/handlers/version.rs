@@ -3,5 +3,5 @@ use anyhow::Result;
 use rucksack_lib::util;
 
 pub fn version() -> Result<()> {
-    return util::display(crate::version().to_string().as_str());
+    util::display(crate::version().to_string().as_str())
 }
@@ -1,7 +1,9 @@
+use chrono::{DateTime, Local};
 use clap::builder::EnumValueParser;
 use clap::{Arg, ArgAction, Command};
 
 use rucksack_db::records;
+use rucksack_lib::time;
 
 use crate::input::constant;
 
@@ -16,6 +18,8 @@ pub fn run() -> Command {
     .about(format!("{}: {}", constant::NAME, constant::DESC))
     .arg_required_else_help(true)
     .arg(top::config())
+    .arg(top::ephemeral())
+    .arg(top::lock_wait())
     .arg(top::log_level())
     .arg(
         Arg::new("completions")
@@ -49,6 +53,7 @@ pub fn run() -> Command {
             .arg(record::url().required(true))
             .arg(db::path())
             .arg(db::pwd())
+            .arg(db::key_file())
             .arg(db::salt())
             .arg(db::backup_dir())
     )
@@ -57,6 +62,7 @@ pub fn run() -> Command {
             .about("Operations related to the a single backup of the secrets DB; used with no subcommand, perform a backup")
             .arg(db::path())
             .arg(db::pwd())
+            .arg(db::key_file())
             .arg(db::salt())
             .arg(db::backup_dir())
             .subcommand(
@@ -65,19 +71,42 @@ pub fn run() -> Command {
                     .arg(Arg::new("name")
                         .help("The name of the backup to delete (get the name from the 'list' command)")
                         .required(true)))
+            .subcommand(
+                Command::new("diff")
+                    .about("List the records added, removed, and changed between a backup and another (or the DB as it is now)")
+                    .arg(db::read_only())
+                    .arg(Arg::new("name")
+                        .help("The name of the backup to compare from (get the name from the 'list' command)")
+                        .required(true))
+                    .arg(Arg::new("other")
+                        .help("The name of the backup to compare to (the DB as it is now, if not given)"))
+                    .arg(
+                        Arg::new("reveal")
+                            .help("Display the changed secrets, rather than masking them")
+                            .long("reveal")
+                            .action(ArgAction::SetTrue),
+                    ))
             .subcommand(
                 Command::new("restore")
                     .about("Restore the DB from a backup ")
                     .arg(Arg::new("name")
                         .help("The name of the backup to restore")))
+            .subcommand(
+                Command::new("verify")
+                    .about("Check that backups decrypt and that their records can be read; exits with an error if any can't")
+                    .arg(db::read_only())
+                    .arg(Arg::new("name")
+                        .help("The name of the backup to verify (all of them, if not given)")))
     )
     .subcommand(
         Command::new("backups")
             .about("Operations related to multiple backups of the secrets DB")
             .arg(db::path())
             .arg(db::pwd())
+            .arg(db::key_file())
             .arg(db::salt())
             .arg(db::backup_dir())
+            .arg(db::read_only())
             .subcommand(
                 Command::new("list")
                     .about("List all the backup files")
@@ -86,6 +115,27 @@ pub fn run() -> Command {
                             .help("List only the most recent backup file")
                             .long("latest")
                             .action(ArgAction::SetTrue),
+                    )
+                    .arg(
+                        Arg::new("since")
+                            .help("List only the backup files made on or after this date (YYYY-MM-DD)")
+                            .long("since")
+                            .value_parser(date),
+                    )
+                    .arg(
+                        Arg::new("until")
+                            .help("List only the backup files made on or before this date (YYYY-MM-DD)")
+                            .long("until")
+                            .value_parser(date),
+                    ))
+            .subcommand(
+                Command::new("prune")
+                    .about("Delete the backup files that the [backups] retention rules in the config don't keep")
+                    .arg(
+                        Arg::new("dry-run")
+                            .help("Only list the backup files that would be deleted")
+                            .long("dry-run")
+                            .action(ArgAction::SetTrue),
                     ))
     )
     .subcommand(
@@ -96,11 +146,70 @@ pub fn run() -> Command {
                 Command::new("re-init")
                     .about("Re-initialise (overwrite) the rucksack config"))
     )
+    .subcommand(
+        Command::new("db")
+            .about("Operations related to the secrets DB itself")
+            .arg(db::not_needed())
+            .arg(db::path())
+            .arg(db::pwd())
+            .arg(db::key_file())
+            .arg(db::salt())
+            .arg(db::backup_dir())
+            .subcommand(
+                Command::new("compact")
+                    .about("Fold the journal of changes to records into the DB file"))
+            .subcommand(
+                Command::new("rekey")
+                    .about("Change the password used to encrypt the DB")
+                    .arg(db::new_pwd())
+                    .arg(db::slot())
+                    .arg(
+                        Arg::new("no-key-file")
+                            .help("Re-key so that the key file is no longer needed")
+                            .long("no-key-file")
+                            .action(ArgAction::SetTrue),
+                    ))
+            .subcommand(
+                Command::new("keyslot")
+                    .about("Manage the passwords and recovery keys that can unlock the DB")
+                    .subcommand(
+                        Command::new("add")
+                            .about("Add a password (or, with --recovery, a generated recovery key) that unlocks the DB")
+                            .arg(db::slot().required(true))
+                            .arg(db::new_pwd())
+                            .arg(
+                                Arg::new("recovery")
+                                    .help("Generate a recovery key instead of prompting for a password")
+                                    .long("recovery")
+                                    .action(ArgAction::SetTrue),
+                            ))
+                    .subcommand(
+                        Command::new("list")
+                            .about("List the DB's key slots (no password needed)"))
+                    .subcommand(
+                        Command::new("remove")
+                            .about("Remove a key slot")
+                            .arg(db::slot().required(true))))
+            .subcommand(
+                Command::new("split")
+                    .about("Split the DB's key into shares, a threshold of which can recover the DB")
+                    .arg(db::shares())
+                    .arg(db::threshold()))
+            .subcommand(
+                Command::new("recover")
+                    .about("Recover the DB from key shares and set a new password")
+                    .arg(db::share())
+                    .arg(db::new_pwd()))
+            .subcommand(
+                Command::new("seal-metadata")
+                    .about("Encrypt the metadata of every record, keeping a blind index for filtering"))
+    )
     .subcommand(
         Command::new("dedupe")
             .about("Deduplication operations on rucksack records")
             .arg(db::path())
             .arg(db::pwd())
+            .arg(db::key_file())
             .arg(db::salt())
             .arg(db::backup_dir())
             .arg(dedupe::dd_type())
@@ -116,6 +225,7 @@ pub fn run() -> Command {
             .arg(record::url().required(true))
             .arg(db::path())
             .arg(db::pwd())
+            .arg(db::key_file())
             .arg(db::salt())
             .arg(db::backup_dir()
         )
@@ -132,9 +242,11 @@ pub fn run() -> Command {
             )
             .arg(db::path())
             .arg(db::pwd())
+            .arg(db::key_file())
             .arg(db::salt())
             .arg(db::backup_dir())
             .arg(db::serialised_format())
+            .arg(db::read_only())
             .arg(record::kind())
             .arg(record::category())
     )
@@ -149,7 +261,14 @@ pub fn run() -> Command {
                     .long("type")
                     .default_value("uuid++")
                     .env("RUXAK_TYPE")
-                    .value_parser(["lipsum", "random", "uuid", "uuid+", "uuid++", ]),
+                    .value_parser(["keyfile", "lipsum", "random", "uuid", "uuid+", "uuid++", ]),
+            )
+            .arg(
+                Arg::new("output")
+                    .help("Path to write the generated secret to (required for key files)")
+                    .short('o')
+                    .long("output")
+                    .env("RUXAK_OUTPUT"),
             )
             .arg(
                 Arg::new("length")
@@ -191,6 +310,12 @@ pub fn run() -> Command {
                     .long("encode")
                     .env("RUXAK_ENCODE")
                     .action(ArgAction::SetTrue),
+            )
+            .arg(
+                Arg::new("force")
+                    .help("Replace the file given by --output if it already exists (for key files)")
+                    .long("force")
+                    .action(ArgAction::SetTrue),
             ),
     )
     .subcommand(
@@ -206,6 +331,7 @@ pub fn run() -> Command {
             )
             .arg(db::path())
             .arg(db::pwd())
+            .arg(db::key_file())
             .arg(db::salt())
             .arg(db::backup_dir())
     )
@@ -302,8 +428,12 @@ pub fn run() -> Command {
             )
             .arg(db::path())
             .arg(db::pwd())
-            .arg(db::salt())
+            .arg(db::key_file())
+            // -s has always been --sort-by here
+            .arg(db::salt().short(None))
             .arg(db::backup_dir())
+            // Revealed records have their use counted, which has to be saved
+            .arg(db::read_only().default_value_if("reveal", "true", Some("false")))
             .arg(record::category()
                 .default_value(records::ANY_CATEGORY),
             )
@@ -338,6 +468,7 @@ pub fn run() -> Command {
             .about("Perform various 'write' operations")
             .arg(db::path())
             .arg(db::pwd())
+            .arg(db::key_file())
             .arg(db::salt())
             .arg(db::backup_dir())
             .arg(record::category())
@@ -383,6 +514,7 @@ pub fn run() -> Command {
             .arg(db::path())
             .arg(db::backup_dir())
             .arg(db::not_needed())
+            .arg(db::read_only())
             .subcommand(
                 Command::new("backup-dir")
                     .about("Display the location of the rucksack backup directory")
@@ -392,6 +524,7 @@ pub fn run() -> Command {
                     .about("Display the categories currently used across all records")
                     .arg(db::needed())
                     .arg(db::pwd())
+                    .arg(db::key_file())
                     .arg(db::salt())
             )
             .subcommand(
@@ -410,18 +543,20 @@ pub fn run() -> Command {
                 Command::new("db-file")
                     .about("display the location of the rucksack database file")
             )
+            .subcommand(
+                Command::new("db-info")
+                    .about("Display the schema version, cipher, and key derivation settings of a database file")
+            )
             .subcommand(
                 Command::new("db-version")
-                    .about("Display the file schema version of a given database file")
-                    .arg(db::needed())
-                    .arg(db::pwd())
-                    .arg(db::salt())
+                    .about("Display the file schema version of a given database file (read from its header)")
             )
             .subcommand(
                 Command::new("tags")
                     .about("Display the tags currently used across all records")
                     .arg(db::needed())
                     .arg(db::pwd())
+                    .arg(db::key_file())
                     .arg(db::salt())
             )
             .subcommand(
@@ -434,7 +569,52 @@ pub fn run() -> Command {
             .about("Run rucksack as a daemon, enabling local network syncing services")
             .arg(db::path())
             .arg(db::pwd())
+            .arg(db::key_file())
             .arg(db::salt())
             .arg(db::backup_dir())
     )
 }
+
+fn date(arg: &str) -> Result<DateTime<Local>, String> {
+    time::parse_date(arg).ok_or_else(|| format!("'{arg}' isn't a date of the form YYYY-MM-DD"))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn cli() {
+        super::run().debug_assert();
+    }
+
+    // Short flags that scripts may already use
+    #[test]
+    fn short_flags() {
+        let parse = |args: &[&str]| super::run().try_get_matches_from(args).unwrap();
+        let m = parse(&["rucksack", "add", "-s", "pepper", "-u", "alice", "--url", "x"]);
+        let (_, add) = m.subcommand().unwrap();
+        assert_eq!(add.get_one::<String>("salt").unwrap(), "pepper");
+        assert_eq!(add.get_one::<String>("user").unwrap(), "alice");
+
+        let m = parse(&["rucksack", "list", "-s", "name"]);
+        let (_, list) = m.subcommand().unwrap();
+        assert_eq!(list.get_one::<String>("sort-by").unwrap(), "name");
+
+        let m = parse(&[
+            "rucksack",
+            "set",
+            "-s",
+            "pepper",
+            "user",
+            "-u",
+            "alice",
+            "--new-user",
+            "bob",
+            "--url",
+            "x",
+        ]);
+        let (_, set) = m.subcommand().unwrap();
+        let (_, user) = set.subcommand().unwrap();
+        assert_eq!(user.get_one::<String>("salt").unwrap(), "pepper");
+        assert_eq!(user.get_one::<String>("old-user").unwrap(), "alice");
+    }
+}
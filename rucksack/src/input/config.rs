@@ -13,6 +13,8 @@ const DEFAULT: &str = r#"[rucksack]
 
 [db]
 
+[backups]
+
 [generation]
 
 [generation.defaults]
@@ -97,12 +99,41 @@ impl Opts {
     }
 }
 
+// When the defaults are merged into the config file (see Config::new), an
+// empty string replaces whatever the file has, so empty strings are left out
+// of the defaults and filled in when the config is read.
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
 #[allow(unused)]
 pub struct DbConfig {
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub path: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub data_dir: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub backup_dir: String,
+    // Argon2id costs used when a database is created or re-keyed; a value
+    // of 0 means "use the default".
+    pub kdf_memory: u32,
+    pub kdf_iterations: u32,
+    pub kdf_parallelism: u32,
+    // The AEAD used for new databases: "aes-256-gcm" (the default) or
+    // "xchacha20-poly1305"
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub cipher: String,
+    // Create new databases with sealed record metadata (see `db seal-metadata`)
+    pub seal_metadata: bool,
+    // A key file needed (along with the password) to unlock the database
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub key_file: String,
+    // Where the database is stored: "filesystem" (the default; one encrypted
+    // file), "persy", "redb", or "sqlite" (one row per record); or "memory",
+    // for a vault that's gone when rucksack exits (as with `--ephemeral`)
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub backend: String,
+    // How long (in seconds) to wait for another rucksack process to unlock
+    // the database; 0 (the default) means not to wait
+    pub lock_wait: u64,
 }
 
 impl DbConfig {
@@ -117,6 +148,16 @@ impl DbConfig {
         if !self.backup_dir.is_empty() {
             db.backup_dir = self.backup_dir.clone();
         }
+        db.kdf_memory = self.kdf_memory;
+        db.kdf_iterations = self.kdf_iterations;
+        db.kdf_parallelism = self.kdf_parallelism;
+        db.cipher = self.cipher.clone();
+        db.seal_metadata = self.seal_metadata;
+        db.backend = self.backend.clone();
+        db.lock_wait = self.lock_wait;
+        if !self.key_file.is_empty() {
+            db.key_file = self.key_file.clone();
+        }
         db
     }
 }
@@ -125,6 +166,8 @@ impl DbConfig {
 #[allow(unused)]
 pub struct Config {
     pub db: DbConfig,
+    #[serde(default)]
+    pub backups: model::Backups,
     pub generation: model::Generation,
     pub logging: model::Logging,
     pub records: model::Records,
@@ -138,6 +181,9 @@ impl Default for Config {
             db: DbConfig {
                 ..Default::default()
             },
+            backups: model::Backups {
+                ..Default::default()
+            },
             generation: model::Generation {
                 ..Default::default()
             },
@@ -203,6 +249,7 @@ impl Config {
     pub fn to_inputs(&self, matches: &ArgMatches) -> model::Inputs {
         model::Inputs {
             db: self.db.to_db(),
+            backups: self.backups.clone(),
             generation: self.generation.clone(),
             logging: self.logging.clone(),
             matches: matches.to_owned(),
@@ -248,4 +295,33 @@ mod tests {
         assert!(r.is_ok());
         assert!(r.unwrap().retention.delete_inactive);
     }
+
+    #[test]
+    fn in_memory_db_test() {
+        let cfg = super::Config::load(&super::Opts {
+            in_memory: true,
+            config: testing::configs::SQLITE_TOML.to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(cfg.db.backend, "sqlite");
+        assert_eq!(cfg.db.cipher, "xchacha20-poly1305");
+        assert!(cfg.db.path.is_empty());
+        assert_eq!(cfg.backups.keep_last, 0);
+    }
+
+    #[test]
+    fn in_memory_backups_test() {
+        let cfg = super::Config::load(&super::Opts {
+            in_memory: true,
+            config: testing::configs::BACKUPS_TOML.to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let policy = cfg.backups.to_policy();
+        assert_eq!(policy.keep_last, 3);
+        assert_eq!(policy.keep_daily, 7);
+        assert_eq!(policy.keep_weekly, 0);
+        assert_eq!(policy.max_age, 90);
+    }
 }
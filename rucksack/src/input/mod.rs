@@ -11,6 +11,8 @@ pub mod prompt;
 #[doc(hidden)]
 pub mod query;
 #[doc(hidden)]
+pub mod redact;
+#[doc(hidden)]
 pub mod testing;
 
 pub use config::Config;
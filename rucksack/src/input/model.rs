@@ -14,24 +14,31 @@
 //! well, from highest priority to lowest priority.
 //!
 use std::env;
+use std::fmt;
+use std::time::Duration;
 
+use anyhow::Result;
 use clap::ArgMatches;
 use secrecy::{ExposeSecret, Secret, SecretString};
 use serde::{Deserialize, Serialize};
 
+use rucksack_db::crypto::{kdf, Cipher, KdfParams};
+use rucksack_db::store::lock::LockMode;
+use rucksack_db::store::retention::Policy;
 use rucksack_db::{records, Tag};
 use rucksack_lib::file;
 
-use super::{constant, options};
+use super::{constant, options, redact};
 
 pub enum Flag {
     One,
     Many,
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Default)]
 pub struct Inputs {
     pub db: Db,
+    pub backups: Backups,
     pub generation: Generation,
     pub logging: Logging,
     pub records: Records,
@@ -40,6 +47,22 @@ pub struct Inputs {
     pub matches: ArgMatches,
 }
 
+// The CLI args can include passwords (e.g., --db-pass), so they're redacted.
+impl fmt::Debug for Inputs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Inputs")
+            .field("db", &self.db)
+            .field("backups", &self.backups)
+            .field("generation", &self.generation)
+            .field("logging", &self.logging)
+            .field("records", &self.records)
+            .field("retention", &self.retention)
+            .field("rucksack", &self.rucksack)
+            .field("matches", &redact::Matches(&self.matches))
+            .finish()
+    }
+}
+
 // The methods of Inputs are focused on one main goal: consolidate ENV vars,
 // CLI opts, configuration, and statically defined defaults, presenting a
 // single API (source of truth) whereby the rest of the app may come to get
@@ -68,11 +91,14 @@ impl Inputs {
         dir
     }
 
+    // Which backups to keep when they're pruned
+    pub fn backup_policy(&self) -> Policy {
+        self.backups.to_policy()
+    }
+
     pub fn category(&self, flag: Flag) -> String {
         match options::category(&self.matches) {
-            Some(c) => {
-                return c.trim().to_owned();
-            }
+            Some(c) => c.trim().to_owned(),
             None => match flag {
                 Flag::One => {
                     let c = self.records.defaults.new_category.trim().to_owned();
@@ -92,6 +118,19 @@ impl Inputs {
         }
     }
 
+    // The storage backend: the in-memory one for an ephemeral vault, otherwise
+    // whatever's configured (an empty name means the default)
+    pub fn backend(&self) -> String {
+        if options::ephemeral(&self.matches) {
+            return "memory".to_string();
+        }
+        self.db.backend.clone()
+    }
+
+    pub fn cipher(&self) -> Result<Cipher> {
+        Cipher::from_name(&self.db.cipher)
+    }
+
     pub fn config_file(&self) -> String {
         let mut cf = options::config_file(&self.matches);
         if !cf.is_empty() {
@@ -130,10 +169,51 @@ impl Inputs {
         options::db_needed(&self.matches).unwrap_or(true)
     }
 
+    // Whether nothing in the vault is to be written to disk
+    pub fn ephemeral(&self) -> bool {
+        self.backend() == "memory"
+    }
+
     pub fn db_passwd(&self) -> String {
         options::db_pwd(&self.matches).expose_secret().to_string()
     }
 
+    // The path to the key file, if one is to be used
+    pub fn key_file(&self) -> Option<String> {
+        if let Some(key_file) = options::key_file(&self.matches) {
+            log::debug!("Got key file from flag: {}", key_file);
+            return Some(key_file);
+        }
+        if !self.db.key_file.is_empty() {
+            log::debug!("Using configured key file ({:})", self.db.key_file);
+            return Some(self.db.key_file.clone());
+        }
+        None
+    }
+
+    pub fn kdf_params(&self) -> KdfParams {
+        let or_default = |v: u32, default: u32| if v == 0 { default } else { v };
+        KdfParams::with_costs(
+            or_default(self.db.kdf_memory, kdf::DEFAULT_M_COST),
+            or_default(self.db.kdf_iterations, kdf::DEFAULT_T_COST),
+            or_default(self.db.kdf_parallelism, kdf::DEFAULT_P_COST),
+        )
+    }
+
+    // Read-only commands share the database with each other; anything else
+    // has it to itself while it's open.
+    pub fn lock_mode(&self) -> LockMode {
+        match options::db_read_only(&self.matches).unwrap_or(false) {
+            true => LockMode::Shared,
+            false => LockMode::Exclusive,
+        }
+    }
+
+    // How long to wait for another process to unlock the database
+    pub fn lock_wait(&self) -> Duration {
+        Duration::from_secs(options::lock_wait(&self.matches).unwrap_or(self.db.lock_wait))
+    }
+
     pub fn key(&self) -> String {
         records::key(
             &self.category(Flag::One),
@@ -204,6 +284,14 @@ pub struct Db {
     pub path: String,
     pub data_dir: String,
     pub backup_dir: String,
+    pub kdf_memory: u32,
+    pub kdf_iterations: u32,
+    pub kdf_parallelism: u32,
+    pub cipher: String,
+    pub seal_metadata: bool,
+    pub key_file: String,
+    pub backend: String,
+    pub lock_wait: u64,
     pub defaults: DbDefaults,
     pub secrets: DbSecrets,
 }
@@ -214,6 +302,14 @@ impl Default for Db {
             path: String::new(),
             data_dir: String::new(),
             backup_dir: String::new(),
+            kdf_memory: 0,
+            kdf_iterations: 0,
+            kdf_parallelism: 0,
+            cipher: String::new(),
+            seal_metadata: false,
+            key_file: String::new(),
+            backend: String::new(),
+            lock_wait: 0,
             defaults: DbDefaults {
                 ..Default::default()
             },
@@ -246,6 +342,28 @@ pub struct DbDefaults {
     pub serialisation_format: String,
 }
 
+// How many backups to keep (see rucksack_db::store::retention); 0 turns a
+// rule off, and with none of them on, no backups are pruned.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Backups {
+    pub keep_last: u32,
+    pub keep_daily: u32,
+    pub keep_weekly: u32,
+    pub max_age_days: u32,
+}
+
+impl Backups {
+    pub fn to_policy(&self) -> Policy {
+        Policy {
+            keep_last: self.keep_last,
+            keep_daily: self.keep_daily,
+            keep_weekly: self.keep_weekly,
+            max_age: self.max_age_days,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 #[allow(unused)]
 pub struct Generation {
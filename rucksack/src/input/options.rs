@@ -1,5 +1,9 @@
+use std::any::Any;
 use std::path::PathBuf;
 
+use anyhow::Result;
+use chrono::{DateTime, Duration, Local};
+use clap::parser::{MatchesError, ValuesRef};
 use clap::ArgMatches;
 use secrecy::{ExposeSecret, Secret, SecretString};
 
@@ -8,35 +12,60 @@ use rucksack_db::records::{new_tags, Status, Tag};
 
 use super::prompt;
 
+// These are read for whichever command was given, and not every command
+// defines every arg; one it doesn't define is the same as one that wasn't
+// given (which is what release builds of clap make of it anyway)
+fn get_one<'a, T: Any + Clone + Send + Sync + 'static>(
+    matches: &'a ArgMatches,
+    id: &str,
+) -> Option<&'a T> {
+    match matches.try_get_one::<T>(id) {
+        Ok(value) => value,
+        Err(MatchesError::UnknownArgument { .. }) => None,
+        Err(e) => panic!("could not read --{id} ({e})"),
+    }
+}
+
+fn get_many<'a, T: Any + Clone + Send + Sync + 'static>(
+    matches: &'a ArgMatches,
+    id: &str,
+) -> Option<ValuesRef<'a, T>> {
+    match matches.try_get_many::<T>(id) {
+        Ok(values) => values,
+        Err(MatchesError::UnknownArgument { .. }) => None,
+        Err(e) => panic!("could not read --{id} ({e})"),
+    }
+}
+
 pub fn account_id(matches: &ArgMatches) -> String {
-    matches
-        .get_one::<String>("account-id")
+    get_one::<String>(matches, "account-id")
         .unwrap()
         .trim()
         .to_string()
 }
 
 pub fn all_tags(matches: &ArgMatches) -> Option<Vec<String>> {
-    matches
-        .get_many("all-tags")
-        .map(|x| x.cloned().collect::<Vec<String>>())
+    get_many::<String>(matches, "all-tags").map(|x| x.cloned().collect::<Vec<String>>())
 }
 
 pub fn any_tags(matches: &ArgMatches) -> Option<Vec<String>> {
-    matches
-        .get_many("any-tags")
-        .map(|x| x.cloned().collect::<Vec<String>>())
+    get_many::<String>(matches, "any-tags").map(|x| x.cloned().collect::<Vec<String>>())
 }
 
 pub fn backup_name(matches: &ArgMatches) -> String {
-    match matches.get_one::<String>("name") {
+    match get_one::<String>(matches, "name") {
         Some(n) => n.trim().to_string(),
         None => "".to_string(),
     }
 }
 
+// The second backup given to `backup diff`, if there is one
+pub fn backup_other(matches: &ArgMatches) -> Option<String> {
+    get_one::<String>(matches, "other").map(|n| n.trim().to_string())
+}
+
 pub fn backup_dir(matches: &ArgMatches) -> String {
-    match matches.get_one::<String>("backup-dir") {
+    match get_one::<String>(matches, "backup-dir") {
         Some(d) => d.trim().to_string(),
         None => "".to_string(),
     }
@@ -49,74 +78,137 @@ pub fn backup_path(matches: &ArgMatches) -> PathBuf {
 }
 
 pub fn category(matches: &ArgMatches) -> Option<String> {
-    matches.get_one::<String>("category").cloned()
+    get_one::<String>(matches, "category").cloned()
 }
 
 pub fn completions(matches: &ArgMatches) -> Option<clap_complete::Shell> {
-    matches
-        .get_one::<clap_complete::Shell>("completions")
-        .copied()
+    get_one::<clap_complete::Shell>(matches, "completions").copied()
 }
 
 pub fn config_file(matches: &ArgMatches) -> String {
-    match matches.get_one::<String>("config-file").cloned() {
+    match get_one::<String>(matches, "config-file").cloned() {
         Some(file) => file,
         None => "".to_string(),
     }
 }
 
 pub fn daemonise(matches: &ArgMatches) -> bool {
-    *matches.get_one::<bool>("daemonise").unwrap_or(&false)
+    *get_one::<bool>(matches, "daemonise").unwrap_or(&false)
 }
 
 pub fn db(matches: &ArgMatches) -> Option<String> {
-    matches.get_one::<String>("db").cloned()
+    get_one::<String>(matches, "db").cloned()
 }
 
 pub fn db_needed(matches: &ArgMatches) -> Option<bool> {
-    matches.get_one::<bool>("db-needed").cloned()
+    get_one::<bool>(matches, "db-needed").cloned()
+}
+
+pub fn db_read_only(matches: &ArgMatches) -> Option<bool> {
+    get_one::<bool>(matches, "db-read-only").cloned()
 }
 
 pub fn db_pwd(matches: &ArgMatches) -> Secret<String> {
-    match matches.get_one::<String>("db-pass") {
+    match get_one::<String>(matches, "db-pass") {
         Some(flag_pwd) => SecretString::new(flag_pwd.to_owned()),
         None => prompt::secret("Enter DB password: ").unwrap(),
     }
 }
 
+pub fn new_db_pwd(matches: &ArgMatches) -> Result<SecretString> {
+    match get_one::<String>(matches, "new-db-pass") {
+        Some(flag_pwd) => Ok(SecretString::new(flag_pwd.to_owned())),
+        None => prompt::new_secret("Enter new DB password: "),
+    }
+}
+
+// Key shares given with --share, or else prompted for one at a time
+pub fn key_shares(matches: &ArgMatches) -> Vec<String> {
+    if let Some(shares) = get_many::<String>(matches, "share") {
+        return shares.map(|s| s.trim().to_string()).collect();
+    }
+    let mut shares = Vec::new();
+    loop {
+        let prompt = format!(
+            "Enter key share {} (or nothing when done): ",
+            shares.len() + 1
+        );
+        let share = prompt::secret(&prompt).unwrap();
+        let share = share.expose_secret().trim().to_string();
+        if share.is_empty() {
+            return shares;
+        }
+        shares.push(share);
+    }
+}
+
+pub fn shares(matches: &ArgMatches) -> u8 {
+    *get_one::<u8>(matches, "shares").unwrap()
+}
+
+pub fn threshold(matches: &ArgMatches) -> u8 {
+    *get_one::<u8>(matches, "threshold").unwrap()
+}
+
+pub fn since(matches: &ArgMatches) -> Option<DateTime<Local>> {
+    get_one::<DateTime<Local>>(matches, "since").copied()
+}
+
+pub fn slot(matches: &ArgMatches) -> Option<String> {
+    get_one::<String>(matches, "slot").map(|s| s.trim().to_string())
+}
+
+pub fn recovery(matches: &ArgMatches) -> bool {
+    *get_one::<bool>(matches, "recovery").unwrap_or(&false)
+}
+
 pub fn decrypt(matches: &ArgMatches) -> bool {
-    *matches.get_one::<bool>("decrypt").unwrap_or(&false)
+    *get_one::<bool>(matches, "decrypt").unwrap_or(&false)
+}
+
+pub fn ephemeral(matches: &ArgMatches) -> bool {
+    *get_one::<bool>(matches, "ephemeral").unwrap_or(&false)
+}
+
+pub fn key_file(matches: &ArgMatches) -> Option<String> {
+    get_one::<String>(matches, "key-file").cloned()
+}
+
+pub fn dry_run(matches: &ArgMatches) -> bool {
+    *get_one::<bool>(matches, "dry-run").unwrap_or(&false)
 }
 
 pub fn latest(matches: &ArgMatches) -> bool {
-    *matches.get_one::<bool>("latest").unwrap_or(&false)
+    *get_one::<bool>(matches, "latest").unwrap_or(&false)
+}
+
+pub fn lock_wait(matches: &ArgMatches) -> Option<u64> {
+    get_one::<u64>(matches, "lock-wait").cloned()
 }
 
 pub fn log_level(matches: &ArgMatches) -> String {
-    match matches.get_one::<String>("log-level").cloned() {
+    match get_one::<String>(matches, "log-level").cloned() {
         Some(level) => level,
         None => "".to_string(),
     }
 }
 
 pub fn name(matches: &ArgMatches) -> String {
-    match matches.get_one::<String>("name") {
+    match get_one::<String>(matches, "name") {
         Some(n) => n.to_string(),
         None => user(matches),
     }
 }
 
 pub fn private(matches: &ArgMatches) -> Vec<u8> {
-    matches
-        .get_one::<String>("private")
+    get_one::<String>(matches, "private")
         .unwrap()
         .as_bytes()
         .to_vec()
 }
 
 pub fn public(matches: &ArgMatches) -> Vec<u8> {
-    matches
-        .get_one::<String>("public")
+    get_one::<String>(matches, "public")
         .unwrap()
         .as_bytes()
         .to_vec()
@@ -124,7 +216,7 @@ pub fn public(matches: &ArgMatches) -> Vec<u8> {
 
 // TODO: there is no corresponding inputs method for this yet ... maybe not needed?
 pub fn record_kind(matches: &ArgMatches) -> records::Kind {
-    let record_type = matches.get_one::<String>("type").map(|s| s.as_str());
+    let record_type = get_one::<String>(matches, "type").map(|s| s.as_str());
     match record_type {
         Some("account") => records::Kind::Account, // Anything that has an account ID, e.g., AWS creds
         Some("asymmetric-crypto") => records::Kind::AsymmetricCrypto, // SSH, GPG, etc.
@@ -142,7 +234,7 @@ pub fn record_kind(matches: &ArgMatches) -> records::Kind {
 }
 
 pub fn record_pwd(matches: &ArgMatches) -> Secret<String> {
-    match matches.get_one::<String>("password") {
+    match get_one::<String>(matches, "password") {
         Some(flag_pwd) => SecretString::new(flag_pwd.to_owned()),
         None => prompt::secret("Enter record password: ").unwrap(),
     }
@@ -154,7 +246,7 @@ pub fn record_pwd_revealed(matches: &ArgMatches) -> String {
 
 // TODO: there is no corresponding inputs method for this yet ... maybe not needed?
 pub fn record_state(matches: &ArgMatches) -> Status {
-    match matches.get_one::<String>("status").map(|s| s.as_str()) {
+    match get_one::<String>(matches, "status").map(|s| s.as_str()) {
         Some("active") => Status::Active,
         Some("inactive") => Status::Inactive,
         Some("deleted") => Status::Deleted,
@@ -164,82 +256,86 @@ pub fn record_state(matches: &ArgMatches) -> Status {
 }
 
 pub fn reveal(matches: &ArgMatches) -> bool {
-    *matches.get_one::<bool>("reveal").unwrap_or(&false)
+    *get_one::<bool>(matches, "reveal").unwrap_or(&false)
 }
 
 pub fn root(matches: &ArgMatches) -> Vec<u8> {
-    matches
-        .get_one::<String>("root")
+    get_one::<String>(matches, "root")
         .unwrap()
         .as_bytes()
         .to_vec()
 }
 
 pub fn salt(matches: &ArgMatches) -> Option<String> {
-    matches.get_one::<String>("salt").cloned()
+    get_one::<String>(matches, "salt").cloned()
 }
 
 pub fn service_key(matches: &ArgMatches) -> String {
-    matches.get_one::<String>("key").unwrap().trim().to_string()
+    get_one::<String>(matches, "key")
+        .unwrap()
+        .trim()
+        .to_string()
 }
 
 pub fn service_secret(matches: &ArgMatches) -> String {
-    matches
-        .get_one::<String>("secret")
+    get_one::<String>(matches, "secret")
         .unwrap()
         .trim()
         .to_string()
 }
 
 pub fn tags(matches: &ArgMatches) -> Option<Vec<Tag>> {
-    let values: Vec<String> = matches.get_many("tags")?.cloned().collect();
+    let values: Vec<String> = get_many::<String>(matches, "tags")?.cloned().collect();
     Some(new_tags(values))
 }
 
+// The end of the day given (that is, the start of the next one)
+pub fn until(matches: &ArgMatches) -> Option<DateTime<Local>> {
+    get_one::<DateTime<Local>>(matches, "until").map(|day| *day + Duration::days(1))
+}
+
 pub fn url(matches: &ArgMatches) -> String {
-    matches.get_one::<String>("url").unwrap().trim().to_string()
+    get_one::<String>(matches, "url")
+        .unwrap()
+        .trim()
+        .to_string()
 }
 
 pub fn url_old(matches: &ArgMatches) -> String {
-    matches
-        .get_one::<String>("old-url")
+    get_one::<String>(matches, "old-url")
         .unwrap()
         .trim()
         .to_string()
 }
 
 pub fn url_new(matches: &ArgMatches) -> String {
-    matches
-        .get_one::<String>("new-url")
+    get_one::<String>(matches, "new-url")
         .unwrap()
         .trim()
         .to_string()
 }
 
 pub fn user(matches: &ArgMatches) -> String {
-    matches
-        .get_one::<String>("user")
+    get_one::<String>(matches, "user")
         .unwrap()
         .trim()
         .to_string()
 }
 
 pub fn user_new(matches: &ArgMatches) -> String {
-    matches
-        .get_one::<String>("new-user")
+    get_one::<String>(matches, "new-user")
         .unwrap()
         .trim()
         .to_string()
 }
 
 pub fn user_old(matches: &ArgMatches) -> String {
-    matches
-        .get_one::<String>("old-user")
+    get_one::<String>(matches, "old-user")
         .unwrap()
         .trim()
         .to_string()
 }
 
 pub fn version(matches: &ArgMatches) -> bool {
-    *matches.get_one::<bool>("version").unwrap_or(&false)
+    *get_one::<bool>(matches, "version").unwrap_or(&false)
 }
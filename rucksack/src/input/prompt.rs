@@ -1,8 +1,18 @@
 use anyhow::{anyhow, Result};
-use secrecy::SecretString;
+use secrecy::{ExposeSecret, SecretString};
 
 pub fn secret(prompt: &str) -> Result<SecretString> {
     rpassword::prompt_password(prompt)
         .map(SecretString::new)
         .map_err(|e| anyhow!("password prompt failed: {}", e.to_string()))
 }
+
+// A new password, asked for twice so that a typo isn't what it's set to
+pub fn new_secret(prompt: &str) -> Result<SecretString> {
+    let pwd = secret(prompt)?;
+    let again = secret("Enter it again: ")?;
+    if pwd.expose_secret() != again.expose_secret() {
+        return Err(anyhow!("the passwords didn't match"));
+    }
+    Ok(pwd)
+}
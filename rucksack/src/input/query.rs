@@ -1,9 +1,12 @@
 use anyhow::{anyhow, Result};
+use clap::ArgMatches;
 use secrecy::{ExposeSecret, SecretString};
 
+use rucksack_db as store;
 use rucksack_db::records::DecryptedRecord;
 
 use crate::app::App;
+use crate::input::{options, Flag};
 
 pub fn record(app: &App) -> Result<DecryptedRecord> {
     log::trace!("Getting record key by app inputs: {:#?}", app.inputs);
@@ -13,7 +16,7 @@ pub fn record(app: &App) -> Result<DecryptedRecord> {
 pub fn record_with_default(app: &App) -> Result<DecryptedRecord> {
     let key = app.inputs.key();
     log::debug!("Querying record by key '{key}' ...");
-    match app.db.get(key.clone()) {
+    match app.db.get(key.clone())? {
         Some(dr) => Ok(dr),
         None => {
             log::debug!("Record not found; creating new one ...");
@@ -24,7 +27,7 @@ pub fn record_with_default(app: &App) -> Result<DecryptedRecord> {
 
 pub fn record_by_key(app: &App, key: String) -> Result<DecryptedRecord> {
     log::debug!("Querying record by key '{key}' ...");
-    match app.db.get(key.clone()) {
+    match app.db.get(key.clone())? {
         Some(dr) => Ok(dr),
         None => {
             let msg = format!("No secret record for given key '{key}'");
@@ -34,6 +37,18 @@ pub fn record_by_key(app: &App, key: String) -> Result<DecryptedRecord> {
     }
 }
 
+// The record named by the --user and --url given to a subcommand (`set
+// password`, say), which its parent command's inputs don't have
+pub fn subcommand_record(app: &App, matches: &ArgMatches) -> Result<DecryptedRecord> {
+    let key = store::key(
+        &app.inputs.category(Flag::One),
+        options::record_kind(matches),
+        &options::user(matches),
+        &options::url(matches),
+    );
+    record_by_key(app, key)
+}
+
 pub fn remove(app: &App) -> Result<()> {
     remove_by_key(app, app.inputs.key())
 }
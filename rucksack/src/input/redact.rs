@@ -0,0 +1,70 @@
+//! # Redaction
+//!
+//! The parsed CLI arguments end up in `Inputs`, which is logged at trace
+//! level; the values of the arguments listed here (passwords, salts, key
+//! shares, and the like) are masked whenever that happens.
+//!
+use std::fmt;
+
+use clap::ArgMatches;
+
+pub const MASK: &str = "*****";
+
+// The IDs of the arguments whose values must never be logged
+pub const SECRET_ARGS: &[&str] = &[
+    "db-pass",
+    "new-db-pass",
+    "password",
+    "private",
+    "salt",
+    "secret",
+    "share",
+];
+
+pub fn is_secret(id: &str) -> bool {
+    SECRET_ARGS.contains(&id)
+}
+
+// Wraps parsed arguments (and those of any subcommand) for debug output.
+pub struct Matches<'a>(pub &'a ArgMatches);
+
+impl fmt::Debug for Matches<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut map = f.debug_map();
+        for id in self.0.ids() {
+            let id = id.as_str();
+            if is_secret(id) {
+                map.entry(&id, &MASK);
+                continue;
+            }
+            match self.0.try_get_raw(id) {
+                Ok(Some(values)) => map.entry(&id, &values.collect::<Vec<_>>()),
+                _ => map.entry(&id, &()),
+            };
+        }
+        if let Some((name, sub_matches)) = self.0.subcommand() {
+            map.entry(&name, &Matches(sub_matches));
+        }
+        map.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{Arg, Command};
+
+    #[test]
+    fn masked_matches() {
+        let cmd = Command::new("rucksack").subcommand(
+            Command::new("add")
+                .arg(Arg::new("db-pass").long("db-pass"))
+                .arg(Arg::new("user").long("user")),
+        );
+        let matches =
+            cmd.get_matches_from(["rucksack", "add", "--db-pass", "hunter2", "--user", "alice"]);
+        let logged = format!("{:#?}", super::Matches(&matches));
+        assert!(!logged.contains("hunter2"));
+        assert!(logged.contains(super::MASK));
+        assert!(logged.contains("alice"));
+    }
+}
@@ -31,3 +31,28 @@ delete_inactive = true
 show_inactive = true
 show_deleted = false
 "#;
+
+pub const SQLITE_TOML: &str = r#"[rucksack]
+
+[db]
+backend = "sqlite"
+cipher = "xchacha20-poly1305"
+
+[logging]
+coloured = true
+level = "error"
+report_caller = false
+"#;
+
+pub const BACKUPS_TOML: &str = r#"[rucksack]
+
+[backups]
+keep_last = 3
+keep_daily = 7
+max_age_days = 90
+
+[logging]
+coloured = true
+level = "error"
+report_caller = false
+"#;
@@ -8,8 +8,10 @@ use super::Opts;
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum Column {
     Category,
+    Checksum,
     Count,
     Created,
+    Decrypted,
     DupeInfo,
     Hash,
     HistoryCount,
@@ -19,14 +21,17 @@ pub enum Column {
     Kind,
     LastAccessed,
     LastUpdated,
+    Migrated,
     Name,
     Password,
-    Permissions,
+    Records,
     Score,
+    Size,
     Status,
     Synced,
     Tags,
     Url,
+    Version,
 }
 
 impl fmt::Display for Column {
@@ -44,6 +49,7 @@ impl Column {
             Column::Kind => "Type".to_string(),
             Column::LastUpdated => "Last Updated".to_string(),
             Column::Score => "Score / Strength".to_string(),
+            Column::Size => "Size (bytes)".to_string(),
             Column::Url => "URL".to_string(),
             _ => format!("{self}"),
         }
@@ -127,7 +133,29 @@ pub struct ColsBackupFiles;
 
 impl Columns for ColsBackupFiles {
     fn pre(&self, _opts: &Opts) -> Vec<Column> {
-        vec![Column::Name, Column::Permissions]
+        vec![
+            Column::Name,
+            Column::Created,
+            Column::Version,
+            Column::Size,
+            Column::Records,
+            Column::Checksum,
+        ]
+    }
+}
+
+pub struct ColsBackupVerify;
+
+impl Columns for ColsBackupVerify {
+    fn pre(&self, _opts: &Opts) -> Vec<Column> {
+        vec![
+            Column::Name,
+            Column::Version,
+            Column::Decrypted,
+            Column::Migrated,
+            Column::Records,
+            Column::Status,
+        ]
     }
 }
 
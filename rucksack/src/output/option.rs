@@ -7,6 +7,7 @@ pub struct Opts {
     pub all_tags: Option<Vec<String>>,
     pub any_tags: Option<Vec<String>>,
     pub backup_files: bool,
+    pub backup_verify: bool,
     pub category: String,
     pub categories: bool,
     pub decrypted: bool,
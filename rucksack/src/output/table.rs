@@ -52,6 +52,8 @@ impl Table {
             self.columns = column::ColsOnlyCat {}.gen(&self.opts);
         } else if self.opts.backup_files {
             self.columns = column::ColsBackupFiles {}.gen(&self.opts);
+        } else if self.opts.backup_verify {
+            self.columns = column::ColsBackupVerify {}.gen(&self.opts);
         } else if self.opts.group_by_name {
             self.opts.with_passwd = true;
             self.columns = column::ColsGroupByName {}.gen(&self.opts);
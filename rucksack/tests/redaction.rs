@@ -0,0 +1,80 @@
+// Runs the rucksack binary at trace level and checks that no secret given to
+// it (on the command line or in a record) shows up in what it logs.
+use std::path::PathBuf;
+use std::process::Command;
+use std::{env, fs, process, time};
+
+const DB_PASS: &str = "db-pass-c4a6e1";
+const NEW_DB_PASS: &str = "new-db-pass-9f02b7";
+const SALT: &str = "salt-5d83a0";
+const RECORD_PASS: &str = "record-pass-7e21c4";
+
+fn temp_dir() -> PathBuf {
+    let nanos = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let dir = env::temp_dir().join(format!("rucksack-redaction-{}-{nanos}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn rucksack(dir: &PathBuf, args: &[&str]) -> String {
+    let db = dir.join("secrets.db").display().to_string();
+    let backups = dir.join("backups").display().to_string();
+    let config = dir.join("config.toml").display().to_string();
+    let output = Command::new(env!("CARGO_BIN_EXE_rucksack"))
+        .env_clear()
+        .env("HOME", dir)
+        .args(["--config-file", &config, "--log-level", "trace"])
+        .args(args)
+        .args(["--db", &db, "--backup-dir", &backups, "--salt", SALT])
+        .output()
+        .unwrap();
+    let mut logged = String::from_utf8_lossy(&output.stdout).to_string();
+    logged.push_str(&String::from_utf8_lossy(&output.stderr));
+    assert!(
+        output.status.success(),
+        "rucksack {args:?} failed:\n{logged}"
+    );
+    logged
+}
+
+#[test]
+fn no_secrets_in_trace_logs() {
+    let dir = temp_dir();
+    let mut logged = rucksack(
+        &dir,
+        &[
+            "add",
+            "--db-pass",
+            DB_PASS,
+            "--url",
+            "https://example.com",
+            "--user",
+            "alice",
+            "--password",
+            RECORD_PASS,
+        ],
+    );
+    logged.push_str(&rucksack(&dir, &["list", "--db-pass", DB_PASS]));
+    logged.push_str(&rucksack(
+        &dir,
+        &[
+            "db",
+            "rekey",
+            "--db-pass",
+            DB_PASS,
+            "--new-db-pass",
+            NEW_DB_PASS,
+        ],
+    ));
+    logged.push_str(&rucksack(&dir, &["list", "--db-pass", NEW_DB_PASS]));
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(logged.contains("TRACE"));
+    assert!(logged.contains("alice"));
+    for secret in [DB_PASS, NEW_DB_PASS, SALT, RECORD_PASS] {
+        assert!(!logged.contains(secret), "{secret:?} was logged");
+    }
+}
@@ -0,0 +1,73 @@
+// Runs the rucksack binary to check that revealing records with `list`
+// counts their use, even though `list` otherwise only reads the database.
+use std::path::PathBuf;
+use std::process::Command;
+use std::{env, fs, process, time};
+
+const DB_PASS: &str = "db-pass-3b81f0";
+const SALT: &str = "salt-a94c27";
+
+fn temp_dir() -> PathBuf {
+    let nanos = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let dir = env::temp_dir().join(format!("rucksack-reveal-{}-{nanos}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn rucksack(dir: &PathBuf, args: &[&str]) -> String {
+    let db = dir.join("secrets.db").display().to_string();
+    let backups = dir.join("backups").display().to_string();
+    let config = dir.join("config.toml").display().to_string();
+    let output = Command::new(env!("CARGO_BIN_EXE_rucksack"))
+        .env_clear()
+        .env("HOME", dir)
+        .args(["--config-file", &config])
+        .args(args)
+        .args(["--db", &db, "--backup-dir", &backups])
+        .args(["--db-pass", DB_PASS, "--salt", SALT])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    assert!(
+        output.status.success(),
+        "rucksack {args:?} failed:\n{stdout}{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    stdout
+}
+
+// The "Access Count" column of alice's row in a `list` table
+fn access_count(listed: &str) -> u64 {
+    let row = listed
+        .lines()
+        .find(|line| line.starts_with("| alice "))
+        .unwrap_or_else(|| panic!("no row for alice in:\n{listed}"));
+    row.split('|').nth(4).unwrap().trim().parse().unwrap()
+}
+
+#[test]
+fn list_reveal_counts_access() {
+    let dir = temp_dir();
+    rucksack(
+        &dir,
+        &[
+            "add",
+            "--url",
+            "https://example.com",
+            "--user",
+            "alice",
+            "--password",
+            "record-pass",
+        ],
+    );
+    assert_eq!(access_count(&rucksack(&dir, &["list"])), 0);
+    rucksack(&dir, &["list", "--reveal"]);
+    rucksack(&dir, &["list", "--reveal"]);
+    let listed = rucksack(&dir, &["list"]);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(access_count(&listed), 2);
+}